@@ -0,0 +1,19 @@
+use std::{env, process::Command};
+
+fn main() {
+    let target = env::var("TARGET").unwrap_or_default();
+    println!("cargo:rustc-env=SENTRY_LOG4RS_TARGET={}", target);
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .unwrap_or_default();
+    // `rustc --version` prints e.g. "rustc 1.68.0 (2c8cc3432 2023-03-06)".
+    let version = version.split_whitespace().nth(1).unwrap_or_default();
+    println!("cargo:rustc-env=SENTRY_LOG4RS_RUSTC_VERSION={}", version);
+
+    println!("cargo:rerun-if-changed=build.rs");
+}
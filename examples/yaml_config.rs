@@ -1,7 +1,6 @@
 use std::{thread, time::Duration};
 
 use log::{error, info};
-use log4rs;
 use sentry_log4rs::SentryAppender;
 
 fn main() {
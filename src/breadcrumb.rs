@@ -0,0 +1,371 @@
+//! An appender which only records breadcrumbs, for clients initialized elsewhere.
+
+use std::{collections::BTreeMap, sync::Arc, time::SystemTime};
+
+use derivative::Derivative;
+use log::{Level, LevelFilter, Record};
+use log4rs::{
+    append::Append,
+    config::{Deserialize, Deserializers},
+    encode::{pattern::PatternEncoder, writer::simple::SimpleWriter, Encode, EncoderConfig},
+};
+use sentry::{protocol::Breadcrumb, Hub};
+
+use crate::{capture::level_mapping, config::serialize_encoder, in_module};
+
+/// How records are turned into breadcrumbs: their category, type and level.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub(crate) struct BreadcrumbFormat {
+    pub(crate) category: Option<String>,
+    pub(crate) ty: Option<String>,
+    pub(crate) types: BTreeMap<String, String>,
+    pub(crate) levels: BTreeMap<Level, sentry::Level>,
+}
+
+impl BreadcrumbFormat {
+    /// A breadcrumb for `record`, with the encoded `message`.
+    pub(crate) fn breadcrumb(
+        &self,
+        record: &Record,
+        message: String,
+        level: sentry::Level,
+        timestamp: SystemTime,
+    ) -> Breadcrumb {
+        Breadcrumb {
+            timestamp,
+            ty: self.ty(record.target()).to_owned(),
+            category: Some(match self.category {
+                Some(ref template) => render_category(template, record),
+                None => record.target().to_owned(),
+            }),
+            level,
+            message: Some(message),
+            ..Default::default()
+        }
+    }
+
+    /// The sentry level of breadcrumbs of records at `level`: the one in `levels`, or that of
+    /// events.
+    pub(crate) fn level(&self, level: Level) -> sentry::Level {
+        self.levels
+            .get(&level)
+            .copied()
+            .unwrap_or_else(|| level_mapping(level))
+    }
+
+    /// The type for records from `target`: that of the longest matching prefix in `types`,
+    /// or `ty`.
+    fn ty(&self, target: &str) -> &str {
+        self.types
+            .iter()
+            .filter(|(prefix, _)| in_module(target, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, ty)| ty.as_str())
+            .or(self.ty.as_deref())
+            .unwrap_or("default")
+    }
+}
+
+/// Replaces `{target}`, `{module_path}` and `{level}` in `template` with the record's.
+fn render_category(template: &str, record: &Record) -> String {
+    template
+        .replace("{target}", record.target())
+        .replace(
+            "{module_path}",
+            record.module_path().unwrap_or_else(|| record.target()),
+        )
+        .replace("{level}", &record.level().as_str().to_lowercase())
+}
+
+/// An appender which adds records as breadcrumbs on a hub and never captures events.
+///
+/// It doesn't initialize a client: the breadcrumbs show on the events captured with the hub's
+/// client, which is set up elsewhere, e.g. by `sentry::init` or a `SentryAppender`.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct SentryBreadcrumbAppender {
+    threshold: LevelFilter,
+    encoder: Box<dyn Encode>,
+    format: BreadcrumbFormat,
+    #[derivative(Debug = "ignore")]
+    hub: Option<Arc<Hub>>,
+}
+
+impl SentryBreadcrumbAppender {
+    /// Creates a new `SentryBreadcrumbAppender` builder.
+    pub fn builder() -> SentryBreadcrumbAppenderBuilder {
+        SentryBreadcrumbAppenderBuilder {
+            threshold: None,
+            encoder: None,
+            format: BreadcrumbFormat::default(),
+            hub: None,
+        }
+    }
+}
+
+impl Append for SentryBreadcrumbAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        if record.level() > self.threshold {
+            return Ok(());
+        }
+
+        let mut buf: Vec<u8> = Vec::new();
+        self.encoder.encode(&mut SimpleWriter(&mut buf), record)?;
+        let breadcrumb = self.format.breadcrumb(
+            record,
+            String::from_utf8_lossy(&buf).into_owned(),
+            self.format.level(record.level()),
+            SystemTime::now(),
+        );
+        match self.hub {
+            Some(ref hub) => hub.add_breadcrumb(breadcrumb),
+            None => Hub::current().add_breadcrumb(breadcrumb),
+        }
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+/// A builder for `SentryBreadcrumbAppender`s.
+pub struct SentryBreadcrumbAppenderBuilder {
+    threshold: Option<LevelFilter>,
+    encoder: Option<Box<dyn Encode>>,
+    format: BreadcrumbFormat,
+    hub: Option<Arc<Hub>>,
+}
+
+impl SentryBreadcrumbAppenderBuilder {
+    /// Sets the most verbose level recorded. Defaults to `Info`.
+    pub fn threshold(mut self, threshold: LevelFilter) -> SentryBreadcrumbAppenderBuilder {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    /// Sets the encoder of the breadcrumbs' messages. Defaults to `PatternEncoder` with `{m}`.
+    pub fn encoder(mut self, encoder: Box<dyn Encode>) -> SentryBreadcrumbAppenderBuilder {
+        self.encoder = Some(encoder);
+        self
+    }
+
+    /// Sets the breadcrumbs' category, in which `{target}`, `{module_path}` and `{level}` are
+    /// replaced with the record's. Defaults to the target.
+    pub fn category(mut self, template: &str) -> SentryBreadcrumbAppenderBuilder {
+        self.format.category = Some(template.to_owned());
+        self
+    }
+
+    /// Sets the breadcrumbs' type, such as `http` or `query`. Defaults to `default`.
+    pub fn breadcrumb_type(mut self, ty: &str) -> SentryBreadcrumbAppenderBuilder {
+        self.format.ty = Some(ty.to_owned());
+        self
+    }
+
+    /// Sets the type of the breadcrumbs of records whose target is `prefix`, or is within the
+    /// `prefix` module. The longest matching prefix wins.
+    pub fn breadcrumb_type_for(
+        mut self,
+        prefix: &str,
+        ty: &str,
+    ) -> SentryBreadcrumbAppenderBuilder {
+        self.format.types.insert(prefix.to_owned(), ty.to_owned());
+        self
+    }
+
+    /// Gives the breadcrumbs of records at `level` the sentry level `mapped`.
+    pub fn level_for(
+        mut self,
+        level: Level,
+        mapped: sentry::Level,
+    ) -> SentryBreadcrumbAppenderBuilder {
+        self.format.levels.insert(level, mapped);
+        self
+    }
+
+    /// Records breadcrumbs on `hub` rather than on the current thread's hub.
+    pub fn hub(mut self, hub: Arc<Hub>) -> SentryBreadcrumbAppenderBuilder {
+        self.hub = Some(hub);
+        self
+    }
+
+    /// Consumes the `SentryBreadcrumbAppenderBuilder`, producing a `SentryBreadcrumbAppender`.
+    pub fn build(self) -> SentryBreadcrumbAppender {
+        SentryBreadcrumbAppender {
+            threshold: self.threshold.unwrap_or(LevelFilter::Info),
+            encoder: self
+                .encoder
+                .unwrap_or_else(|| Box::new(PatternEncoder::new("{m}"))),
+            format: self.format,
+            hub: self.hub,
+        }
+    }
+}
+
+/// Configuration for the breadcrumb appender.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SentryBreadcrumbAppenderConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    threshold: Option<LevelFilter>,
+    #[serde(
+        serialize_with = "serialize_encoder",
+        skip_serializing_if = "Option::is_none"
+    )]
+    encoder: Option<EncoderConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    ty: Option<String>,
+    #[serde(default)]
+    types: BTreeMap<String, String>,
+    #[serde(default)]
+    level_map: BTreeMap<Level, sentry::Level>,
+}
+
+/// A deserializer for the `SentryBreadcrumbAppender`.
+///
+/// # Configuration
+///
+/// ```yaml
+/// kind: sentry_breadcrumb
+///
+/// # The most verbose level recorded as a breadcrumb. Defaults to `info`.
+/// threshold: info
+///
+/// # The encoder of the breadcrumbs' messages. Defaults to `kind: pattern`.
+/// encoder:
+///   pattern: "{m}"
+///
+/// # The breadcrumbs' category, in which `{target}`, `{module_path}` and `{level}` are
+/// # replaced with the record's. Defaults to `{target}`.
+/// category: "{module_path}"
+///
+/// # The breadcrumbs' type, and the types of those of target prefixes, the longest matching
+/// # prefix winning. Defaults to `default`.
+/// type: default
+/// types:
+///   reqwest: http
+///   sqlx: query
+///
+/// # The sentry level of the breadcrumbs of each log level: `debug`, `info`, `warning`, `error`
+/// # or `fatal`. Levels not listed get the level events at them would.
+/// level_map:
+///   warn: warning
+///   trace: debug
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct SentryBreadcrumbAppenderDeserializer;
+
+impl Deserialize for SentryBreadcrumbAppenderDeserializer {
+    type Trait = dyn Append;
+
+    type Config = SentryBreadcrumbAppenderConfig;
+
+    fn deserialize(
+        &self,
+        config: SentryBreadcrumbAppenderConfig,
+        deserializers: &Deserializers,
+    ) -> anyhow::Result<Box<dyn Append>> {
+        let mut appender = SentryBreadcrumbAppender::builder();
+
+        if let Some(threshold) = config.threshold {
+            appender = appender.threshold(threshold);
+        }
+
+        if let Some(encoder) = config.encoder {
+            appender = appender.encoder(deserializers.deserialize(&encoder.kind, encoder.config)?);
+        }
+
+        if let Some(ref category) = config.category {
+            appender = appender.category(category);
+        }
+
+        if let Some(ref ty) = config.ty {
+            appender = appender.breadcrumb_type(ty);
+        }
+
+        for (prefix, ty) in &config.types {
+            appender = appender.breadcrumb_type_for(prefix, ty);
+        }
+
+        for (&level, &mapped) in &config.level_map {
+            appender = appender.level_for(level, mapped);
+        }
+
+        Ok(Box::new(appender.build()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use sentry::protocol::Event;
+
+    fn log(appender: &dyn Append, level: Level, target: &str, msg: &str) {
+        appender
+            .append(
+                &Record::builder()
+                    .level(level)
+                    .target(target)
+                    .args(format_args!("{}", msg))
+                    .build(),
+            )
+            .unwrap();
+    }
+
+    /// Logs to a breadcrumb appender built from `builder` with `log`, then captures an event
+    /// on its hub and returns everything the hub's client sent.
+    fn breadcrumbs_of(
+        builder: SentryBreadcrumbAppenderBuilder,
+        log: impl FnOnce(&SentryBreadcrumbAppender),
+    ) -> Vec<Event<'static>> {
+        let (hub, transport) = crate::tests::test_hub();
+        let appender = builder.hub(hub.clone()).build();
+        log(&appender);
+        hub.capture_message("boom", sentry::Level::Error);
+        transport.fetch_and_clear_events()
+    }
+
+    #[test]
+    fn records_breadcrumbs_without_capturing_events() {
+        let events = breadcrumbs_of(SentryBreadcrumbAppender::builder(), |appender| {
+            log(appender, Level::Debug, "app", "too quiet");
+            log(appender, Level::Info, "app", "loading");
+            log(appender, Level::Error, "app", "failed");
+        });
+        assert_eq!(events.len(), 1);
+        let breadcrumbs: Vec<_> = events[0]
+            .breadcrumbs
+            .iter()
+            .map(|breadcrumb| (breadcrumb.message.as_deref(), breadcrumb.level))
+            .collect();
+        assert_eq!(
+            breadcrumbs,
+            [
+                (Some("loading"), sentry::Level::Info),
+                (Some("failed"), sentry::Level::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn levels_are_mapped_above_the_threshold() {
+        let events = breadcrumbs_of(
+            SentryBreadcrumbAppender::builder()
+                .threshold(LevelFilter::Debug)
+                .level_for(Level::Warn, sentry::Level::Fatal),
+            |appender| {
+                log(appender, Level::Trace, "app", "spam");
+                log(appender, Level::Debug, "app", "debug");
+                log(appender, Level::Warn, "app", "warn");
+            },
+        );
+        let levels: Vec<_> = events[0]
+            .breadcrumbs
+            .iter()
+            .map(|breadcrumb| breadcrumb.level)
+            .collect();
+        assert_eq!(levels, [sentry::Level::Debug, sentry::Level::Fatal]);
+    }
+}
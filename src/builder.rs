@@ -0,0 +1,1627 @@
+//! The builder setting up the sentry appender and the client it captures events with.
+
+use anyhow::Context as _;
+use log::{Level, LevelFilter};
+use log4rs::encode::{pattern::PatternEncoder, Encode};
+use regex::Regex;
+use sentry::{
+    integrations::contexts::utils,
+    protocol::{
+        value::{Number, Value},
+        Context, Map, OsContext, RuntimeContext,
+    },
+    transports::ReqwestHttpTransport,
+    types::Dsn,
+    Client, ClientOptions, Hub, Level as SentryLevel, Transport,
+};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    breadcrumb,
+    config::{
+        config_errors, default_dedup_max_entries, default_fingerprint_key,
+        default_max_attachment_size, default_max_extra_entries, default_max_extra_value_bytes,
+        default_max_message_bytes, redact_dsn,
+    },
+    fingerprint, periodic, ratelimit, recent, scrub, spool, stats, suppress, throttle, transport,
+    worker, AttachAs, Clock, CulpritSource, EncodingErrorPolicy, GitInfo, Inner, LoggerSource,
+    MessageFormat, MultilineMode, QueueFullPolicy, RecordFilter, RecordProcessor, RouteClients,
+    SampleRate, Sampling, ScrubPreset, SentryAppender, Stats, SystemClock, TimestampSource,
+    TransactionSource, FLUSH_TIMEOUT,
+};
+
+/// The initial backoff `build` retries with when the one given to `retry` is zero.
+pub(crate) const MIN_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How many fingerprints `per_fingerprint_limit` keeps track of.
+const MAX_FINGERPRINTS: usize = 1024;
+
+/// ANSI escape sequences: CSI sequences such as colors, OSC sequences such as hyperlinks and
+/// two-byte escapes. A lone ESC is left alone.
+const ANSI_ESCAPES: &str = r"\x1b(?:\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(?:\x07|\x1b\\)|[@-Z\\-_])";
+
+/// A builder for `SentryAppender`s.
+pub struct SentryAppenderBuilder {
+    encoder: Option<Box<dyn Encode>>,
+    default_pattern: Option<String>,
+    fingerprint_encoder: Option<Box<dyn Encode>>,
+    breadcrumb_encoder: Option<Box<dyn Encode>>,
+    breadcrumb_format: breadcrumb::BreadcrumbFormat,
+    pub(crate) dsn: String,
+    threshold: Option<LevelFilter>,
+    breadcrumb_threshold: LevelFilter,
+    queue_size: Option<usize>,
+    queue_full: QueueFullPolicy,
+    include_build_context: bool,
+    default_contexts: bool,
+    redact_patterns: Vec<Regex>,
+    location_extras: bool,
+    include_kv: bool,
+    report_as_exception: bool,
+    exception_type: Option<String>,
+    auto_session_tracking: bool,
+    release: Option<String>,
+    tags: BTreeMap<String, String>,
+    backtrace_levels: Vec<Level>,
+    capture_process_info: bool,
+    transaction_from: Option<TransactionSource>,
+    culprit_source: Option<CulpritSource>,
+    logger_source: LoggerSource,
+    platform: String,
+    dist: Option<String>,
+    level_tag: Option<String>,
+    target_as_tag: bool,
+    env_tags: bool,
+    pub(crate) in_app_include: Vec<&'static str>,
+    pub(crate) in_app_exclude: Vec<&'static str>,
+    build_info: BTreeMap<String, String>,
+    #[cfg(feature = "tracing")]
+    tracing_span: bool,
+    timestamp: TimestampSource,
+    pub(crate) ca_certs: Vec<reqwest::Certificate>,
+    pub(crate) retry: Option<transport::Backoff>,
+    pub(crate) spool: Option<(PathBuf, u64)>,
+    pub(crate) compress_payloads: bool,
+    accept_invalid_certs: bool,
+    culprit_from_location: bool,
+    include_location: bool,
+    send_trace: bool,
+    logger_map: BTreeMap<String, String>,
+    attach_files: Vec<PathBuf>,
+    max_attachment_size: u64,
+    template_key: Option<String>,
+    pub(crate) fingerprint: Vec<fingerprint::Template>,
+    fingerprint_key: String,
+    normalize_fingerprint: bool,
+    normalize_patterns: Vec<(Regex, String)>,
+    message_prefix: Option<String>,
+    message_suffix: Option<String>,
+    self_describe: bool,
+    strip_ansi: bool,
+    multiline: MultilineMode,
+    message_format: MessageFormat,
+    pub(crate) flush_interval: Option<Duration>,
+    skip_empty_messages: bool,
+    max_message_bytes: usize,
+    encoding_error_policy: EncodingErrorPolicy,
+    extra: Map<String, Value>,
+    traces_sample_rate: SampleRate,
+    max_extra_entries: usize,
+    max_extra_value_bytes: usize,
+    normalize_depth: Option<usize>,
+    max_value_length: Option<usize>,
+    routes: Vec<(Level, Level, String)>,
+    scrub_presets: Vec<ScrubPreset>,
+    include_targets: Vec<String>,
+    exclude_targets: Vec<String>,
+    target_thresholds: BTreeMap<String, LevelFilter>,
+    ignore_messages: Vec<Regex>,
+    only_messages: Vec<Regex>,
+    drop_if_contains: Vec<String>,
+    drop_if_contains_ignore_case: bool,
+    pub(crate) max_events_per_minute: Option<u32>,
+    burst: Option<u32>,
+    per_fingerprint_limit: Option<u32>,
+    per_fingerprint_window: Duration,
+    dedup_window: Option<Duration>,
+    dedup_max_entries: usize,
+    level_sample_rates: BTreeMap<Level, SampleRate>,
+    sampling: Sampling,
+    sampling_salt: String,
+    report_drops_every: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    enabled: bool,
+    repeat_policy: Option<(u32, SampleRate, Duration)>,
+    recent_logs: Option<(usize, AttachAs, LevelFilter)>,
+    record_processor: Option<RecordProcessor>,
+    filter: Option<RecordFilter>,
+    hub: Option<Arc<Hub>>,
+}
+
+impl SentryAppenderBuilder {
+    /// A builder with every option at its default, see `SentryAppender::builder`.
+    pub(crate) fn new() -> SentryAppenderBuilder {
+        SentryAppenderBuilder {
+            encoder: None,
+            default_pattern: None,
+            fingerprint_encoder: None,
+            breadcrumb_encoder: None,
+            breadcrumb_format: breadcrumb::BreadcrumbFormat::default(),
+            dsn: String::default(),
+            threshold: None,
+            breadcrumb_threshold: LevelFilter::Off,
+            queue_size: None,
+            queue_full: QueueFullPolicy::default(),
+            include_build_context: false,
+            default_contexts: false,
+            redact_patterns: Vec::new(),
+            location_extras: false,
+            include_kv: false,
+            report_as_exception: false,
+            exception_type: None,
+            auto_session_tracking: false,
+            release: None,
+            tags: BTreeMap::new(),
+            backtrace_levels: Vec::new(),
+            capture_process_info: false,
+            transaction_from: None,
+            culprit_source: None,
+            logger_source: LoggerSource::default(),
+            platform: "rust".to_owned(),
+            dist: None,
+            level_tag: None,
+            target_as_tag: false,
+            env_tags: false,
+            in_app_include: Vec::new(),
+            in_app_exclude: Vec::new(),
+            build_info: BTreeMap::new(),
+            #[cfg(feature = "tracing")]
+            tracing_span: false,
+            timestamp: TimestampSource::default(),
+            ca_certs: Vec::new(),
+            retry: None,
+            spool: None,
+            compress_payloads: false,
+            accept_invalid_certs: false,
+            culprit_from_location: false,
+            include_location: true,
+            send_trace: false,
+            logger_map: BTreeMap::new(),
+            attach_files: Vec::new(),
+            max_attachment_size: default_max_attachment_size(),
+            template_key: None,
+            fingerprint: Vec::new(),
+            fingerprint_key: default_fingerprint_key(),
+            normalize_fingerprint: false,
+            normalize_patterns: Vec::new(),
+            message_prefix: None,
+            message_suffix: None,
+            self_describe: false,
+            strip_ansi: true,
+            multiline: MultilineMode::default(),
+            message_format: MessageFormat::default(),
+            flush_interval: None,
+            skip_empty_messages: false,
+            max_message_bytes: default_max_message_bytes(),
+            encoding_error_policy: EncodingErrorPolicy::default(),
+            extra: Map::new(),
+            traces_sample_rate: SampleRate::default(),
+            max_extra_entries: default_max_extra_entries(),
+            max_extra_value_bytes: default_max_extra_value_bytes(),
+            normalize_depth: None,
+            max_value_length: None,
+            routes: Vec::new(),
+            scrub_presets: Vec::new(),
+            include_targets: Vec::new(),
+            exclude_targets: Vec::new(),
+            target_thresholds: BTreeMap::new(),
+            ignore_messages: Vec::new(),
+            only_messages: Vec::new(),
+            drop_if_contains: Vec::new(),
+            drop_if_contains_ignore_case: false,
+            max_events_per_minute: None,
+            burst: None,
+            per_fingerprint_limit: None,
+            per_fingerprint_window: Duration::from_secs(60),
+            dedup_window: None,
+            dedup_max_entries: default_dedup_max_entries(),
+            level_sample_rates: BTreeMap::new(),
+            sampling: Sampling::default(),
+            sampling_salt: String::new(),
+            report_drops_every: None,
+            clock: Arc::new(SystemClock),
+            enabled: true,
+            repeat_policy: None,
+            recent_logs: None,
+            record_processor: None,
+            filter: None,
+            hub: None,
+        }
+    }
+
+    pub fn encoder(mut self, encoder: Box<dyn Encode>) -> SentryAppenderBuilder {
+        self.encoder = Some(encoder);
+        self
+    }
+
+    /// Sets the pattern of the `PatternEncoder` used when no encoder is set, e.g.
+    /// `{l} {t} - {m}`. Defaults to `{m}`.
+    pub fn default_pattern(mut self, pattern: &str) -> SentryAppenderBuilder {
+        self.default_pattern = Some(pattern.to_owned());
+        self
+    }
+
+    /// Computes the fingerprint by formatting records with `encoder`, e.g. to leave out the
+    /// per-request context the message includes. The output is normalized when
+    /// `normalize_fingerprint` is on.
+    pub fn fingerprint_encoder(mut self, encoder: Box<dyn Encode>) -> SentryAppenderBuilder {
+        self.fingerprint_encoder = Some(encoder);
+        self
+    }
+
+    /// Sets the DSN of the project events are sent to. Without one, the client is disabled and
+    /// the appender drops records without encoding them.
+    pub fn dsn(mut self, dsn: impl Into<String>) -> SentryAppenderBuilder {
+        self.dsn = dsn.into();
+        self
+    }
+
+    /// Sets the most verbose level sent to Sentry. Defaults to `Error`. `Off` disables the
+    /// appender, unless `target_threshold` lets some records through: no client is
+    /// initialized, and records are dropped without being encoded.
+    pub fn threshold(mut self, threshold: LevelFilter) -> SentryAppenderBuilder {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    /// Adds records below the threshold, but at or above `threshold`, as breadcrumbs on the
+    /// appender's hub instead of dropping them, so the events which follow show what led up to
+    /// them. See `breadcrumb_encoder`, `breadcrumb_category` and `breadcrumb_type` for what
+    /// they look like. Defaults to `Off`.
+    pub fn breadcrumb_threshold(mut self, threshold: LevelFilter) -> SentryAppenderBuilder {
+        self.breadcrumb_threshold = threshold;
+        self
+    }
+
+    /// Formats the messages of breadcrumbs with `encoder` rather than the appender's, e.g. to
+    /// keep them shorter.
+    pub fn breadcrumb_encoder(mut self, encoder: Box<dyn Encode>) -> SentryAppenderBuilder {
+        self.breadcrumb_encoder = Some(encoder);
+        self
+    }
+
+    /// Sets the breadcrumbs' category, in which `{target}`, `{module_path}` and `{level}` are
+    /// replaced with the record's. Defaults to the target.
+    pub fn breadcrumb_category(mut self, template: &str) -> SentryAppenderBuilder {
+        self.breadcrumb_format.category = Some(template.to_owned());
+        self
+    }
+
+    /// Sets the breadcrumbs' type, such as `http` or `query`. Defaults to `default`.
+    pub fn breadcrumb_type(mut self, ty: &str) -> SentryAppenderBuilder {
+        self.breadcrumb_format.ty = Some(ty.to_owned());
+        self
+    }
+
+    /// Sets the type of the breadcrumbs of records whose target is `prefix`, or is within the
+    /// `prefix` module. The longest matching prefix wins.
+    pub fn breadcrumb_type_for(mut self, prefix: &str, ty: &str) -> SentryAppenderBuilder {
+        self.breadcrumb_format
+            .types
+            .insert(prefix.to_owned(), ty.to_owned());
+        self
+    }
+
+    /// Gives the breadcrumbs of records at `level` the sentry level `mapped`, e.g. `Debug` to
+    /// `trace` records. A `sentry.level` key-value still takes precedence.
+    pub fn breadcrumb_level_for(
+        mut self,
+        level: Level,
+        mapped: SentryLevel,
+    ) -> SentryAppenderBuilder {
+        self.breadcrumb_format.levels.insert(level, mapped);
+        self
+    }
+
+    /// Applies `threshold` instead of the appender's to records whose target is `prefix`, or
+    /// is within the `prefix` module. The longest matching prefix wins.
+    pub fn target_threshold(
+        mut self,
+        prefix: &str,
+        threshold: LevelFilter,
+    ) -> SentryAppenderBuilder {
+        self.target_thresholds.insert(prefix.to_owned(), threshold);
+        self
+    }
+
+    /// Moves encoding and capturing of records onto a background thread, buffering up to
+    /// `queue_size` records between the logging threads and the worker. When the appender is
+    /// dropped, queued records are captured and sent within two seconds in all, and whatever is
+    /// still queued then is discarded.
+    pub fn async_queue(mut self, queue_size: usize) -> SentryAppenderBuilder {
+        self.queue_size = Some(queue_size);
+        self
+    }
+
+    /// Sets what `append` does when the background queue is full. Defaults to `DropNew`; with
+    /// `Block`, `append` waits for the worker to make room.
+    pub fn queue_full(mut self, policy: QueueFullPolicy) -> SentryAppenderBuilder {
+        self.queue_full = policy;
+        self
+    }
+
+    /// Attaches the rustc version and target triple the binary was built with as a `rust`
+    /// runtime context on every event.
+    pub fn include_build_context(mut self, include_build_context: bool) -> SentryAppenderBuilder {
+        self.include_build_context = include_build_context;
+        self
+    }
+
+    /// Attaches `os` and `device` contexts, gathered once when the appender is built, on
+    /// every event.
+    pub fn default_contexts(mut self, default_contexts: bool) -> SentryAppenderBuilder {
+        self.default_contexts = default_contexts;
+        self
+    }
+
+    /// Adds a pattern whose matches are replaced with `[REDACTED]` in the message, exception
+    /// values, string extras and tag values before the event is sent.
+    pub fn redact_pattern(mut self, pattern: Regex) -> SentryAppenderBuilder {
+        self.redact_patterns.push(pattern);
+        self
+    }
+
+    /// Same as `redact_pattern`.
+    pub fn scrub_pattern(self, pattern: Regex) -> SentryAppenderBuilder {
+        self.redact_pattern(pattern)
+    }
+
+    /// Scrubs the data `preset` finds, e.g. email addresses, with a marker for its kind, such as
+    /// `[EMAIL]`, wherever redaction patterns apply, and in the user context. Presets apply
+    /// after the redaction patterns.
+    pub fn scrub_preset(mut self, preset: ScrubPreset) -> SentryAppenderBuilder {
+        self.scrub_presets.push(preset);
+        self
+    }
+
+    /// Also records the file and line as `file` and `line` extras, as older versions did.
+    pub fn location_extras(mut self, location_extras: bool) -> SentryAppenderBuilder {
+        self.location_extras = location_extras;
+        self
+    }
+
+    /// Attaches a structured view of the record, including its key-values, as the `log_record`
+    /// extra. Breadcrumbs get the key-values as their data, except those prefixed with
+    /// `sentry.`.
+    pub fn include_kv(mut self, include_kv: bool) -> SentryAppenderBuilder {
+        self.include_kv = include_kv;
+        self
+    }
+
+    /// Reports records as exceptions rather than plain messages, so they show up in Sentry's
+    /// exception views. The message is still set for searchability.
+    pub fn report_as_exception(mut self, report_as_exception: bool) -> SentryAppenderBuilder {
+        self.report_as_exception = report_as_exception;
+        self
+    }
+
+    /// Sets the exception type used by `report_as_exception`. Defaults to the record's target.
+    pub fn exception_type(mut self, exception_type: &str) -> SentryAppenderBuilder {
+        self.exception_type = Some(exception_type.to_owned());
+        self
+    }
+
+    /// Tracks a release health session for the lifetime of the appender, on the hub given to
+    /// `hub` or the current one. The session is started when the appender is built and ended
+    /// when it is dropped or `shutdown`. Sessions need a `release`.
+    pub fn auto_session_tracking(mut self, auto_session_tracking: bool) -> SentryAppenderBuilder {
+        self.auto_session_tracking = auto_session_tracking;
+        self
+    }
+
+    /// Sets the release events are reported under, e.g. `my-app@1.4.2`. Defaults to the
+    /// `SENTRY_RELEASE` environment variable.
+    pub fn release(mut self, release: &str) -> SentryAppenderBuilder {
+        self.release = Some(release.to_owned());
+        self
+    }
+
+    /// Sets the distribution of the release events are reported under, telling apart builds
+    /// of the same release, e.g. per architecture.
+    pub fn dist(mut self, dist: &str) -> SentryAppenderBuilder {
+        self.dist = Some(dist.to_owned());
+        self
+    }
+
+    /// Uses the commit in `git`, usually `sentry_log4rs::git_info!()`, as the release and tags
+    /// events with it as `git_commit`, and with the branch as `git_branch`. Does nothing for
+    /// the parts the build script didn't emit.
+    pub fn release_from_vergen(mut self, git: GitInfo) -> SentryAppenderBuilder {
+        if let Some(sha) = git.sha {
+            self.release = Some(sha.to_owned());
+            self.tags.insert("git_commit".to_owned(), sha.to_owned());
+        }
+        if let Some(branch) = git.branch {
+            self.tags.insert("git_branch".to_owned(), branch.to_owned());
+        }
+        self
+    }
+
+    /// Captures a backtrace when a record of one of `levels` is appended, and reports it
+    /// instead of the single frame pointing at the record's location. Capturing and resolving
+    /// a backtrace is expensive, so this is off for every level by default.
+    pub fn backtrace_levels(mut self, levels: Vec<Level>) -> SentryAppenderBuilder {
+        self.backtrace_levels = levels;
+        self
+    }
+
+    /// Attaches the process id and executable path as a `process` extra, to tell apart
+    /// several processes of the same service.
+    pub fn capture_process_info(mut self, capture_process_info: bool) -> SentryAppenderBuilder {
+        self.capture_process_info = capture_process_info;
+        self
+    }
+
+    /// Sets where `event.transaction` is read from when a record is appended. Left unset if
+    /// the source has no value.
+    pub fn transaction_from(mut self, source: TransactionSource) -> SentryAppenderBuilder {
+        self.transaction_from = Some(source);
+        self
+    }
+
+    /// Sets the event's transaction, which Sentry shows as the issue's culprit, from the
+    /// record's metadata when neither `set_transaction` nor `transaction_from` provide one.
+    pub fn culprit_source(mut self, source: CulpritSource) -> SentryAppenderBuilder {
+        self.culprit_source = Some(source);
+        self
+    }
+
+    /// Sets what `event.logger` is populated from. Defaults to the record's target.
+    pub fn logger_source(mut self, source: LoggerSource) -> SentryAppenderBuilder {
+        self.logger_source = source;
+        self
+    }
+
+    /// Sets the event's `platform`, which Sentry uses to pick how to display it. Defaults to
+    /// `rust`.
+    pub fn platform(mut self, platform: &str) -> SentryAppenderBuilder {
+        self.platform = platform.to_owned();
+        self
+    }
+
+    /// Tags events with the record's original level as `log_level`.
+    pub fn level_tag(mut self, level_tag: bool) -> SentryAppenderBuilder {
+        self.level_tag = if level_tag {
+            Some("log_level".to_owned())
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Tags events with the record's original level, in lowercase, as `key`.
+    pub fn level_tag_key(mut self, key: &str) -> SentryAppenderBuilder {
+        self.level_tag = Some(key.to_owned());
+        self
+    }
+
+    /// Tags events with the record's target as `target`, making it searchable even when the
+    /// logger is renamed. Every distinct target becomes a tag value, so with many modules
+    /// logging this adds a lot of values to the tag.
+    pub fn target_as_tag(mut self, target_as_tag: bool) -> SentryAppenderBuilder {
+        self.target_as_tag = target_as_tag;
+        self
+    }
+
+    /// Tags every event with the environment variables prefixed with `SENTRY_TAG_`, read
+    /// once when the appender is built. `SENTRY_TAG_CLUSTER=eu-1` becomes the tag
+    /// `cluster: eu-1`.
+    pub fn env_tags(mut self, env_tags: bool) -> SentryAppenderBuilder {
+        self.env_tags = env_tags;
+        self
+    }
+
+    /// Marks stacktrace frames whose module starts with `prefix` as in-app.
+    pub fn in_app_include(mut self, prefix: &str) -> SentryAppenderBuilder {
+        self.in_app_include.push(intern(prefix));
+        self
+    }
+
+    /// Marks stacktrace frames whose module starts with `prefix` as not in-app.
+    pub fn in_app_exclude(mut self, prefix: &str) -> SentryAppenderBuilder {
+        self.in_app_exclude.push(intern(prefix));
+        self
+    }
+
+    /// Reads the environment variable `env_var` when the appender is built and reports its
+    /// value as `name` in a `build` context and as a tag on every event. Unset variables are
+    /// skipped.
+    pub fn build_info(mut self, name: &str, env_var: &str) -> SentryAppenderBuilder {
+        self.build_info.insert(name.to_owned(), env_var.to_owned());
+        self
+    }
+
+    /// Attaches the name, target and field names of the current `tracing` span as the `span`
+    /// extra. Requires the `tracing` feature.
+    #[cfg(feature = "tracing")]
+    pub fn tracing_span(mut self, tracing_span: bool) -> SentryAppenderBuilder {
+        self.tracing_span = tracing_span;
+        self
+    }
+
+    /// Sets which moment the event's timestamp records. Defaults to `Append`.
+    pub fn timestamp(mut self, timestamp: TimestampSource) -> SentryAppenderBuilder {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Trusts the certificates in the PEM bundle at `path` when sending events, in addition to
+    /// the system's trust store. Fails if the file can't be read or parsed.
+    pub fn ca_certs(mut self, path: impl AsRef<Path>) -> anyhow::Result<SentryAppenderBuilder> {
+        self.ca_certs.extend(read_ca_certs(path.as_ref())?);
+        Ok(self)
+    }
+
+    /// Sends events again when Sentry answers with a server error or can't be reached: up to
+    /// `max_retries` times, waiting `initial_backoff` and then twice as long each time, up to
+    /// `max_backoff`. A `Retry-After` header longer than `max_backoff` makes the appender give
+    /// up straight away. Events given up on are spooled with `spool`, or counted as send
+    /// errors. Events Sentry rate limits, with `429 Too Many Requests` or the
+    /// `X-Sentry-Rate-Limits` header, are dropped until the limit expires.
+    ///
+    /// Events are then sent one at a time, so later ones wait for the retries of earlier ones.
+    /// Only applies to the client the appender initializes, not to one given with `hub`.
+    pub fn retry(
+        mut self,
+        max_retries: u32,
+        initial_backoff: Duration,
+        max_backoff: Duration,
+    ) -> SentryAppenderBuilder {
+        self.retry = Some(transport::Backoff {
+            max_retries,
+            initial: initial_backoff,
+            max: max_backoff,
+        });
+        self
+    }
+
+    /// Writes events which can't be sent, once `retry` gives up, to files in `dir`, and sends
+    /// them again at startup, once an event gets through, and every minute. The oldest are
+    /// deleted, and counted as send errors, when they take more than `max_size` bytes. Fails
+    /// if `dir` can't be created.
+    ///
+    /// Only applies to the client the appender initializes, not to one given with `hub`.
+    pub fn spool(
+        mut self,
+        dir: impl AsRef<Path>,
+        max_size: u64,
+    ) -> anyhow::Result<SentryAppenderBuilder> {
+        let dir = dir.as_ref();
+        create_spool_dir(dir)?;
+        self.spool = Some((dir.to_owned(), max_size));
+        Ok(self)
+    }
+
+    /// Gzips the events sent to Sentry, e.g. over slow links. Defaults to false, like sentry's
+    /// own transport.
+    ///
+    /// Only applies to the client the appender initializes, not to one given with `hub`.
+    pub fn compress_payloads(mut self, compress_payloads: bool) -> SentryAppenderBuilder {
+        self.compress_payloads = compress_payloads;
+        self
+    }
+
+    /// Disables TLS certificate validation when sending events.
+    ///
+    /// **This is insecure**: anyone able to intercept the connection can read the events.
+    /// Prefer `ca_certs` to trust a private certificate authority.
+    pub fn accept_invalid_certs(mut self, accept_invalid_certs: bool) -> SentryAppenderBuilder {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// Sets the event's culprit to `module_path:line`, so identical messages from different
+    /// places are told apart. Falls back to the target when the record has no module path.
+    pub fn culprit_from_location(mut self, culprit_from_location: bool) -> SentryAppenderBuilder {
+        self.culprit_from_location = culprit_from_location;
+        self
+    }
+
+    /// Reports where records were logged from. When disabled, events carry no stacktrace,
+    /// `module_path` tag, location culprit or file and line extras, keeping source paths and
+    /// module names out of Sentry. Defaults to true.
+    pub fn include_location(mut self, include_location: bool) -> SentryAppenderBuilder {
+        self.include_location = include_location;
+        self
+    }
+
+    /// Sends trace-level records. They are dropped by default, even when the threshold would
+    /// admit them.
+    pub fn send_trace(mut self, send_trace: bool) -> SentryAppenderBuilder {
+        self.send_trace = send_trace;
+        self
+    }
+
+    /// Adds a `sentry_log4rs` context describing the appender: the crate's version, the
+    /// threshold and whether records are sent asynchronously.
+    pub fn self_describe(mut self, self_describe: bool) -> SentryAppenderBuilder {
+        self.self_describe = self_describe;
+        self
+    }
+
+    /// Strips ANSI escape sequences, such as the colors of a pattern's `{h(..)}`, from encoded
+    /// messages. Defaults to true.
+    pub fn strip_ansi(mut self, strip_ansi: bool) -> SentryAppenderBuilder {
+        self.strip_ansi = strip_ansi;
+        self
+    }
+
+    /// Sets how messages spanning several lines are reported. Defaults to `Raw`.
+    pub fn multiline(mut self, multiline: MultilineMode) -> SentryAppenderBuilder {
+        self.multiline = multiline;
+        self
+    }
+
+    /// Sets the format of the encoder's output. With `Json`, the output of e.g. log4rs's JSON
+    /// encoder is parsed: its `message` or `msg` field becomes the event's message, and the
+    /// other top-level fields become extras. Defaults to `Text`.
+    pub fn message_format(mut self, message_format: MessageFormat) -> SentryAppenderBuilder {
+        self.message_format = message_format;
+        self
+    }
+
+    /// Flushes the sentry client every `interval` on a background thread, so events don't sit
+    /// in the transport's queue while the application logs nothing else, and are otherwise
+    /// sent as the transport batches them. `flush` and dropping the appender still flush
+    /// straight away; the thread stops when the appender is dropped.
+    pub fn flush_interval(mut self, interval: Duration) -> SentryAppenderBuilder {
+        self.flush_interval = Some(interval);
+        self
+    }
+
+    /// Drops records whose encoded message is empty or only whitespace.
+    pub fn skip_empty_messages(mut self, skip_empty_messages: bool) -> SentryAppenderBuilder {
+        self.skip_empty_messages = skip_empty_messages;
+        self
+    }
+
+    /// Truncates messages longer than `max_message_bytes` bytes, marking them as truncated and
+    /// reporting their original length as the `message_bytes` extra. 0 disables truncation.
+    /// Defaults to 8 KiB.
+    pub fn max_message_bytes(mut self, max_message_bytes: usize) -> SentryAppenderBuilder {
+        self.max_message_bytes = max_message_bytes;
+        self
+    }
+
+    /// Sets what happens when the encoder's output isn't valid UTF-8. Defaults to `Lossy`, which
+    /// delivers the event with the invalid sequences replaced rather than losing it.
+    pub fn encoding_error_policy(
+        mut self,
+        encoding_error_policy: EncodingErrorPolicy,
+    ) -> SentryAppenderBuilder {
+        self.encoding_error_policy = encoding_error_policy;
+        self
+    }
+
+    /// Adds the extra `key` to every event. Extras the appender sets per record, such as
+    /// `file` and `line`, take precedence.
+    pub fn extra(mut self, key: &str, value: impl Into<Value>) -> SentryAppenderBuilder {
+        self.extra.insert(key.to_owned(), value.into());
+        self
+    }
+
+    /// Sets the share of transactions, started with `SentryAppender::start_transaction`, which
+    /// are sent to Sentry. Defaults to 0.
+    pub fn traces_sample_rate(mut self, traces_sample_rate: SampleRate) -> SentryAppenderBuilder {
+        self.traces_sample_rate = traces_sample_rate;
+        self
+    }
+
+    /// Keeps at most `max_extra_entries` extras per event, dropping the surplus in key order.
+    /// Defaults to 100.
+    pub fn max_extra_entries(mut self, max_extra_entries: usize) -> SentryAppenderBuilder {
+        self.max_extra_entries = max_extra_entries;
+        self
+    }
+
+    /// Truncates extras whose JSON representation is longer than `max_extra_value_bytes` bytes,
+    /// turning them into strings. Defaults to 16 KiB.
+    pub fn max_extra_value_bytes(mut self, max_extra_value_bytes: usize) -> SentryAppenderBuilder {
+        self.max_extra_value_bytes = max_extra_value_bytes;
+        self
+    }
+
+    /// Replaces the arrays and objects nested more than `depth` levels deep in extras with
+    /// `"[Array]"` or `"[Object]"`. Unlimited by default, like the sentry client.
+    pub fn normalize_depth(mut self, depth: usize) -> SentryAppenderBuilder {
+        self.normalize_depth = Some(depth);
+        self
+    }
+
+    /// Truncates the strings within extras, at any depth, to `max_value_length` bytes. Unlimited
+    /// by default, like the sentry client.
+    pub fn max_value_length(mut self, max_value_length: usize) -> SentryAppenderBuilder {
+        self.max_value_length = Some(max_value_length);
+        self
+    }
+
+    /// Sends records from `min_level` up to `max_level`, e.g. `Warn` to `Warn`, to a separate
+    /// client for `dsn` instead of the appender's own. The first matching route wins; records
+    /// matching none go to the appender's client. Routes sharing a DSN share a client.
+    pub fn route(
+        mut self,
+        min_level: Level,
+        max_level: Level,
+        dsn: impl Into<String>,
+    ) -> SentryAppenderBuilder {
+        self.routes.push((min_level, max_level, dsn.into()));
+        self
+    }
+
+    /// Only captures records whose target is one of `targets` or within one of their modules,
+    /// e.g. `my_app` matches `my_app::db`. No include targets means every target.
+    pub fn include_targets(mut self, targets: Vec<String>) -> SentryAppenderBuilder {
+        self.include_targets.extend(targets);
+        self
+    }
+
+    /// Drops records whose target is one of `targets` or within one of their modules, e.g.
+    /// `sqlx` matches `sqlx::query`. Excluding wins over including.
+    pub fn exclude_targets(mut self, targets: Vec<String>) -> SentryAppenderBuilder {
+        self.exclude_targets.extend(targets);
+        self
+    }
+
+    /// Drops records whose encoded message matches `pattern`.
+    pub fn ignore_message(mut self, pattern: Regex) -> SentryAppenderBuilder {
+        self.ignore_messages.push(pattern);
+        self
+    }
+
+    /// Only captures records whose encoded message matches one of the patterns given this way.
+    /// Records matching an `ignore_message` pattern are dropped regardless.
+    pub fn only_message(mut self, pattern: Regex) -> SentryAppenderBuilder {
+        self.only_messages.push(pattern);
+        self
+    }
+
+    /// Drops records whose encoded message contains `phrase`, e.g. a known-benign warning.
+    pub fn drop_if_contains(mut self, phrase: &str) -> SentryAppenderBuilder {
+        self.drop_if_contains.push(phrase.to_owned());
+        self
+    }
+
+    /// Makes `drop_if_contains` ignore case. Defaults to false.
+    pub fn drop_if_contains_ignore_case(mut self, ignore_case: bool) -> SentryAppenderBuilder {
+        self.drop_if_contains_ignore_case = ignore_case;
+        self
+    }
+
+    /// Sends at most `max_events_per_minute` events a minute, dropping the rest. The next event
+    /// sent reports how many were dropped as the `rate_limited_dropped` extra.
+    pub fn max_events_per_minute(mut self, max_events_per_minute: u32) -> SentryAppenderBuilder {
+        self.max_events_per_minute = Some(max_events_per_minute);
+        self
+    }
+
+    /// Sets how many events `max_events_per_minute` lets through at once after a quiet period.
+    /// Defaults to `max_events_per_minute`.
+    pub fn burst(mut self, burst: u32) -> SentryAppenderBuilder {
+        self.burst = Some(burst);
+        self
+    }
+
+    /// Sends at most `limit` events per fingerprint in each `per_fingerprint_window`, dropping
+    /// the rest. Events grouped by Sentry's default fingerprint are told apart by their target
+    /// and message. The first event sent after some were dropped reports how many as the
+    /// `suppressed_count` extra.
+    pub fn per_fingerprint_limit(mut self, limit: u32) -> SentryAppenderBuilder {
+        self.per_fingerprint_limit = Some(limit);
+        self
+    }
+
+    /// Sets the window of `per_fingerprint_limit`. Defaults to a minute.
+    pub fn per_fingerprint_window(mut self, window: Duration) -> SentryAppenderBuilder {
+        self.per_fingerprint_window = window;
+        self
+    }
+
+    /// Sends a message from a target only once in each `window`, dropping repeats. Messages are
+    /// compared after normalization if `normalize_fingerprint` is on. The next one sent after
+    /// repeats were dropped reports how many as the `duplicate_count` extra.
+    pub fn dedup_window(mut self, window: Duration) -> SentryAppenderBuilder {
+        self.dedup_window = Some(window);
+        self
+    }
+
+    /// Sets how many distinct messages `dedup_window` keeps track of, forgetting the least
+    /// recently seen one when full. Defaults to 1024.
+    pub fn dedup_max_entries(mut self, max_entries: usize) -> SentryAppenderBuilder {
+        self.dedup_max_entries = max_entries;
+        self
+    }
+
+    /// Sends only a random `rate` share of the records at `level`. Levels without a rate are
+    /// all sent.
+    pub fn level_sample_rate(mut self, level: Level, rate: SampleRate) -> SentryAppenderBuilder {
+        self.level_sample_rates.insert(level, rate);
+        self
+    }
+
+    /// Sets how `level_sample_rates` picks the records to send. Defaults to `Random`.
+    pub fn sampling(mut self, sampling: Sampling) -> SentryAppenderBuilder {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Mixes `salt` into the hash `Sampling::Deterministic` samples by, so that different
+    /// salts keep different records.
+    pub fn sampling_salt(mut self, salt: &str) -> SentryAppenderBuilder {
+        self.sampling_salt = salt.to_owned();
+        self
+    }
+
+    /// Sends an info event every `interval` counting the records dropped since the last one by
+    /// `max_events_per_minute` and `per_fingerprint_limit`, `dedup_window`,
+    /// `level_sample_rates`, message filters, a full queue and the sentry client. Nothing is
+    /// sent for intervals without drops.
+    pub fn report_drops_every(mut self, interval: Duration) -> SentryAppenderBuilder {
+        self.report_drops_every = Some(interval);
+        self
+    }
+
+    /// Sets the clock read by `max_events_per_minute`, `per_fingerprint_limit` and
+    /// `dedup_window`, e.g. one advanced by hand to test them without waiting. Defaults to
+    /// `SystemClock`.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> SentryAppenderBuilder {
+        self.clock = clock;
+        self
+    }
+
+    /// Turns the appender off when false: no client is initialized, no background threads
+    /// are started, and records are dropped without being encoded. Defaults to true.
+    pub fn enabled(mut self, enabled: bool) -> SentryAppenderBuilder {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Sends the first `first` events sharing a fingerprint in each `window`, then only a
+    /// `then_sample` share of them, annotated with the `occurrence_count` extra. Messages are
+    /// compared after normalization if `normalize_fingerprint` is on.
+    pub fn repeat_policy(
+        mut self,
+        first: u32,
+        then_sample: SampleRate,
+        window: Duration,
+    ) -> SentryAppenderBuilder {
+        self.repeat_policy = Some((first, then_sample, window));
+        self
+    }
+
+    /// Keeps the last `capacity` lines which pass the appender's thresholds and filters,
+    /// encoded with its encoder and including those recorded as breadcrumbs, and attaches them
+    /// `attach_as` to events at `threshold` or more severe.
+    pub fn recent_logs(
+        mut self,
+        capacity: usize,
+        attach_as: AttachAs,
+        threshold: LevelFilter,
+    ) -> SentryAppenderBuilder {
+        self.recent_logs = Some((capacity, attach_as, threshold));
+        self
+    }
+
+    /// Calls `processor` with each record and the event built from it, right before the event
+    /// is captured, so it can change the event using the record, e.g. its key-values. Unlike
+    /// `before_send`, it sees the record; in async mode it's called on the worker thread.
+    pub fn record_processor(mut self, processor: RecordProcessor) -> SentryAppenderBuilder {
+        self.record_processor = Some(processor);
+        self
+    }
+
+    /// Only captures records for which `filter` returns true. It's consulted after the
+    /// threshold, on the logging thread.
+    pub fn filter(mut self, filter: RecordFilter) -> SentryAppenderBuilder {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Reports records whose logger name is `prefix`, or is within the `prefix` module, under
+    /// `name` instead, e.g. `my_app::adapters::stripe` as `payments.stripe`. The longest
+    /// matching prefix wins; unmatched records keep their logger name.
+    pub fn logger_map(mut self, prefix: &str, name: &str) -> SentryAppenderBuilder {
+        self.logger_map.insert(prefix.to_owned(), name.to_owned());
+        self
+    }
+
+    /// Attaches the file at `path` to every captured event. The file is read when the event is
+    /// captured, so it reflects its contents at that time.
+    pub fn attach_file(mut self, path: impl Into<PathBuf>) -> SentryAppenderBuilder {
+        self.attach_files.push(path.into());
+        self
+    }
+
+    /// Sets the size in bytes above which attached files are skipped. Defaults to 1 MiB.
+    pub fn max_attachment_size(mut self, max_attachment_size: u64) -> SentryAppenderBuilder {
+        self.max_attachment_size = max_attachment_size;
+        self
+    }
+
+    /// Populates `event.logentry` from records carrying the key-value `key`, using its value as
+    /// the message template and the record's other key-values, in order, as its parameters.
+    /// Sentry then groups these events on the template instead of the formatted message.
+    pub fn template_key(mut self, key: &str) -> SentryAppenderBuilder {
+        self.template_key = Some(key.to_owned());
+        self
+    }
+
+    /// Sets the fingerprint Sentry groups events by. Each entry may contain the placeholders
+    /// `{target}`, `{module_path}`, `{level}` and `{message}`, expanded per record; an entry of
+    /// `{{ default }}` stands for Sentry's own grouping. Fails on unknown placeholders.
+    pub fn fingerprint(
+        mut self,
+        fingerprint: Vec<String>,
+    ) -> anyhow::Result<SentryAppenderBuilder> {
+        self.fingerprint = fingerprint
+            .iter()
+            .map(|template| fingerprint::Template::parse(template))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(self)
+    }
+
+    /// Sets the key-value whose value, a single fingerprint or a comma-separated list of them,
+    /// overrides the fingerprint of the record it's attached to. The key-value itself isn't
+    /// reported. Defaults to `sentry.fingerprint`.
+    pub fn fingerprint_key(mut self, key: &str) -> SentryAppenderBuilder {
+        self.fingerprint_key = key.to_owned();
+        self
+    }
+
+    /// Computes the fingerprint from a normalized copy of the message, in which quoted strings,
+    /// UUIDs, hex ids and integers are replaced by placeholders, so messages differing only in
+    /// those group together. With `fingerprint` set, the normalized message is what `{message}`
+    /// expands to. `event.message` keeps the original text.
+    pub fn normalize_fingerprint(mut self, normalize_fingerprint: bool) -> SentryAppenderBuilder {
+        self.normalize_fingerprint = normalize_fingerprint;
+        self
+    }
+
+    /// Replaces matches of `pattern` with `replacement` when normalizing messages. Setting any
+    /// pattern replaces the built-in ones; patterns apply in the order they're added.
+    pub fn normalize_pattern(mut self, pattern: Regex, replacement: &str) -> SentryAppenderBuilder {
+        self.normalize_patterns
+            .push((pattern, replacement.to_owned()));
+        self
+    }
+
+    /// Prepends `prefix` to every message, e.g. `[billing] `.
+    pub fn message_prefix(mut self, prefix: &str) -> SentryAppenderBuilder {
+        self.message_prefix = Some(prefix.to_owned());
+        self
+    }
+
+    /// Appends `suffix` to every message.
+    pub fn message_suffix(mut self, suffix: &str) -> SentryAppenderBuilder {
+        self.message_suffix = Some(suffix.to_owned());
+        self
+    }
+
+    /// Captures events into `hub` instead of the current thread's hub, isolating them from the
+    /// rest of the process. The hub's client is used as is: the appender doesn't initialize a
+    /// client of its own, so the DSN and the other client options are ignored.
+    pub fn hub(mut self, hub: Arc<Hub>) -> SentryAppenderBuilder {
+        self.hub = Some(hub);
+        self
+    }
+
+    /// Checks the options for invalid values, such as an unparsable DSN or a zero interval,
+    /// reporting every one of them in a single error.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        config_errors(self.problems())
+    }
+
+    /// The invalid values among the options.
+    pub(crate) fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.dsn.is_empty() && self.dsn.parse::<Dsn>().is_err() {
+            problems.push(format!("invalid dsn `{}`", redact_dsn(&self.dsn)));
+        }
+        for (_, _, dsn) in &self.routes {
+            if dsn.parse::<Dsn>().is_err() {
+                problems.push(format!("invalid route dsn `{}`", redact_dsn(dsn)));
+            }
+        }
+        let zero = [
+            (
+                "flush_interval",
+                self.flush_interval.is_some_and(|d| d.is_zero()),
+            ),
+            (
+                "max_events_per_minute",
+                self.max_events_per_minute == Some(0),
+            ),
+            (
+                "per_fingerprint_limit",
+                self.per_fingerprint_limit == Some(0),
+            ),
+            (
+                "per_fingerprint_window",
+                self.per_fingerprint_window.is_zero(),
+            ),
+            (
+                "dedup_window",
+                self.dedup_window.is_some_and(|d| d.is_zero()),
+            ),
+            (
+                "report_drops_every",
+                self.report_drops_every.is_some_and(|d| d.is_zero()),
+            ),
+            (
+                "repeat_policy.window",
+                self.repeat_policy
+                    .is_some_and(|(_, _, window)| window.is_zero()),
+            ),
+            (
+                "recent_logs.capacity",
+                self.recent_logs
+                    .is_some_and(|(capacity, _, _)| capacity == 0),
+            ),
+        ];
+        for (name, _) in zero.iter().filter(|(_, zero)| *zero) {
+            problems.push(format!("`{}` must be greater than zero", name));
+        }
+        if let Some(backoff) = self.retry {
+            if backoff.initial.is_zero() || backoff.initial > backoff.max {
+                problems.push(
+                    "`retry.initial_backoff` must be greater than zero and at most `max_backoff`"
+                        .to_owned(),
+                );
+            }
+        }
+        problems
+    }
+
+    /// Like `build`, but returns the errors `validate` finds instead of ignoring the invalid
+    /// values.
+    pub fn try_build(self) -> anyhow::Result<SentryAppender> {
+        self.validate()?;
+        Ok(self.build())
+    }
+
+    /// Reports the invalid values among the options on stderr and falls back to their defaults,
+    /// so that the appender can be built anyway. An invalid DSN disables the client, and an
+    /// invalid route is left out.
+    pub(crate) fn without_invalid_values(mut self) -> SentryAppenderBuilder {
+        for problem in self.problems() {
+            eprintln!("log4rs: {} in the sentry appender, ignoring it", problem);
+        }
+        if self.dsn.parse::<Dsn>().is_err() {
+            self.dsn.clear();
+        }
+        self.routes.retain(|(_, _, dsn)| dsn.parse::<Dsn>().is_ok());
+        let nonzero = |d: Option<Duration>| d.filter(|d| !d.is_zero());
+        self.flush_interval = nonzero(self.flush_interval);
+        self.dedup_window = nonzero(self.dedup_window);
+        self.report_drops_every = nonzero(self.report_drops_every);
+        self.max_events_per_minute = self.max_events_per_minute.filter(|&max| max > 0);
+        self.per_fingerprint_limit = self.per_fingerprint_limit.filter(|&limit| limit > 0);
+        if self.per_fingerprint_window.is_zero() {
+            self.per_fingerprint_window = SentryAppender::builder().per_fingerprint_window;
+        }
+        self.repeat_policy = self
+            .repeat_policy
+            .filter(|(_, _, window)| !window.is_zero());
+        self.recent_logs = self.recent_logs.filter(|(capacity, _, _)| *capacity > 0);
+        self.retry = self.retry.map(|backoff| {
+            let initial = backoff.initial.max(MIN_RETRY_BACKOFF);
+            transport::Backoff {
+                initial,
+                max: backoff.max.max(initial),
+                ..backoff
+            }
+        });
+        self
+    }
+
+    /// The options of the client the appender initializes, before its transport is set up.
+    pub(crate) fn client_options(&self) -> ClientOptions {
+        ClientOptions {
+            release: self.release.clone().map(Cow::Owned),
+            accept_invalid_certs: self.accept_invalid_certs,
+            in_app_include: self.in_app_include.clone(),
+            in_app_exclude: self.in_app_exclude.clone(),
+            traces_sample_rate: self.traces_sample_rate.get(),
+            ..Default::default()
+        }
+    }
+
+    /// Consumes the `SentryAppenderBuilder`, producing a `SentryAppender`.
+    ///
+    /// Invalid values `validate` finds are reported on stderr and replaced with their defaults,
+    /// e.g. an unparsable DSN disables the client. Use `try_build` to fail on them instead.
+    pub fn build(self) -> SentryAppender {
+        self.without_invalid_values().build_valid()
+    }
+
+    fn build_valid(self) -> SentryAppender {
+        let mut options = self.client_options();
+        let stats = Arc::new(stats::Counters::default());
+        if !self.ca_certs.is_empty()
+            || self.retry.is_some()
+            || self.spool.is_some()
+            || self.compress_payloads
+        {
+            let ca_certs = self.ca_certs;
+            let spool = self.spool;
+            let compress = self.compress_payloads;
+            // Spooling or compressing without retries sends events once, spooling them as soon
+            // as they fail.
+            let retry = self.retry.or_else(|| {
+                (spool.is_some() || compress).then_some(transport::Backoff {
+                    max_retries: 0,
+                    initial: Duration::ZERO,
+                    max: Duration::ZERO,
+                })
+            });
+            let stats = stats.clone();
+            options.transport = Some(Arc::new(move |options: &ClientOptions| {
+                let client = http_client(options, &ca_certs);
+                match retry {
+                    Some(backoff) => Arc::new(transport::RetryTransport::new(
+                        options,
+                        client,
+                        backoff,
+                        spool.as_ref().and_then(|(dir, max_size)| {
+                            let dsn = options.dsn.as_ref()?;
+                            spool::Spool::for_dsn(dir, dsn, *max_size)
+                                .map_err(|err| {
+                                    eprintln!(
+                                        "log4rs: failed to create spool directory in {}: {}",
+                                        dir.display(),
+                                        err
+                                    )
+                                })
+                                .ok()
+                        }),
+                        compress,
+                        stats.clone(),
+                    )) as Arc<dyn Transport>,
+                    None => Arc::new(ReqwestHttpTransport::with_client(options, client)),
+                }
+            }));
+        }
+        let redactor = Arc::new(scrub::Redactor::new(
+            self.redact_patterns,
+            &self.scrub_presets,
+        ));
+        if !redactor.is_empty() {
+            let redactor = redactor.clone();
+            options.before_send = Some(Arc::new(move |mut event| {
+                if let Some(ref mut user) = event.user {
+                    redactor.redact_user(user);
+                }
+                Some(event)
+            }));
+        }
+
+        let threshold = self.threshold.unwrap_or(LevelFilter::Error);
+        let off = !self.enabled
+            || (threshold == LevelFilter::Off
+                && self
+                    .target_thresholds
+                    .values()
+                    .all(|&threshold| threshold == LevelFilter::Off));
+
+        let mut route_clients: Vec<(String, Arc<Client>)> = Vec::new();
+        let mut routes = Vec::new();
+        for (min_level, max_level, dsn) in self.routes.into_iter().filter(|_| !off) {
+            let client = match route_clients
+                .iter()
+                .find(|(route_dsn, _)| *route_dsn == dsn)
+            {
+                Some((_, client)) => client.clone(),
+                None => {
+                    let client = Arc::new(Client::from(sentry::apply_defaults(ClientOptions {
+                        dsn: dsn.parse().ok(),
+                        ..options.clone()
+                    })));
+                    route_clients.push((dsn, client.clone()));
+                    client
+                }
+            };
+            let hub = Arc::new(Hub::new(Some(client), Default::default()));
+            routes.push((min_level, max_level, hub));
+        }
+        let _routes = RouteClients(
+            route_clients
+                .into_iter()
+                .map(|(_, client)| client)
+                .collect(),
+        );
+
+        let _sentry = match self.hub {
+            Some(_) => None,
+            None if off => None,
+            None => Some(sentry::init((self.dsn, options))),
+        };
+        // Without a client to send events with, encoding records would be wasted work.
+        let disabled = match _sentry {
+            _ if off => true,
+            Some(ref guard) => !guard.is_enabled() && _routes.0.is_empty(),
+            None => false,
+        };
+        if disabled && !off {
+            eprintln!("log4rs: sentry is disabled, so the sentry appender drops every record");
+        }
+
+        let mut contexts = Map::new();
+        if self.include_build_context {
+            contexts.insert("rust".to_owned(), build_context());
+        }
+        if self.default_contexts {
+            contexts.insert("os".to_owned(), os_context());
+            contexts.insert("device".to_owned(), utils::device_context());
+        }
+        if self.self_describe {
+            contexts.insert(
+                "sentry_log4rs".to_owned(),
+                appender_context(self.threshold, self.queue_size),
+            );
+        }
+
+        let mut tags = if self.env_tags {
+            env_tags()
+        } else {
+            Map::new()
+        };
+        tags.extend(self.tags);
+
+        let mut build = Map::new();
+        for (name, env_var) in &self.build_info {
+            match std::env::var(env_var) {
+                Ok(value) => {
+                    tags.insert(name.clone(), value.clone());
+                    build.insert(name.clone(), Value::String(value));
+                }
+                Err(_) => log::debug!("{} is not set, skipping build info `{}`", env_var, name),
+            }
+        }
+        if !build.is_empty() {
+            contexts.insert("build".to_owned(), Context::Other(build));
+        }
+
+        let encoder = match self.encoder {
+            Some(encoder) => encoder,
+            None => Box::new(PatternEncoder::new(
+                self.default_pattern.as_deref().unwrap_or("{m}"),
+            )),
+        };
+
+        let clock = self.clock;
+        let per_fingerprint_window = self.per_fingerprint_window;
+        let fingerprint_throttle = self.per_fingerprint_limit.map(|limit| {
+            throttle::Throttle::new(
+                limit,
+                per_fingerprint_window,
+                MAX_FINGERPRINTS,
+                clock.clone(),
+            )
+        });
+
+        let repeat_policy = self.repeat_policy.map(|(first, then_sample, window)| {
+            (
+                throttle::Throttle::new(1, window, MAX_FINGERPRINTS, clock.clone()),
+                u64::from(first),
+                then_sample,
+            )
+        });
+
+        let dedup_max_entries = self.dedup_max_entries;
+        let dedup = self
+            .dedup_window
+            .map(|window| throttle::Throttle::new(1, window, dedup_max_entries, clock.clone()));
+
+        let inner = Arc::new(Inner {
+            hub: self.hub,
+            encoder,
+            fingerprint_encoder: self.fingerprint_encoder,
+            breadcrumb_encoder: self.breadcrumb_encoder,
+            breadcrumb_format: self.breadcrumb_format,
+            ansi_escapes: if self.strip_ansi {
+                Some(Regex::new(ANSI_ESCAPES).expect("invalid ANSI escape pattern"))
+            } else {
+                None
+            },
+            contexts,
+            redactor,
+            location_extras: self.location_extras,
+            include_kv: self.include_kv,
+            report_as_exception: self.report_as_exception,
+            exception_type: self.exception_type,
+            backtrace_levels: self.backtrace_levels,
+            process_info: if self.capture_process_info {
+                Some(process_info())
+            } else {
+                None
+            },
+            transaction_from: self.transaction_from,
+            culprit_source: self.culprit_source,
+            logger_source: self.logger_source,
+            platform: self.platform,
+            dist: self.dist,
+            level_tag: self.level_tag,
+            target_as_tag: self.target_as_tag,
+            tags,
+            in_app_include: self.in_app_include,
+            in_app_exclude: self.in_app_exclude,
+            #[cfg(feature = "tracing")]
+            tracing_span: self.tracing_span,
+            timestamp: self.timestamp,
+            culprit_from_location: self.culprit_from_location,
+            include_location: self.include_location,
+            logger_map: self.logger_map,
+            attach_files: self.attach_files,
+            max_attachment_size: self.max_attachment_size,
+            template_key: self.template_key,
+            fingerprint: self.fingerprint,
+            fingerprint_key: self.fingerprint_key,
+            normalizer: match (
+                self.normalize_fingerprint,
+                self.normalize_patterns.is_empty(),
+            ) {
+                (false, _) => None,
+                (true, true) => Some(fingerprint::Normalizer::default()),
+                (true, false) => Some(fingerprint::Normalizer::new(self.normalize_patterns)),
+            },
+            message_prefix: self.message_prefix,
+            message_suffix: self.message_suffix,
+            multiline: self.multiline,
+            message_format: self.message_format,
+            skip_empty_messages: self.skip_empty_messages,
+            max_message_bytes: self.max_message_bytes,
+            encoding_error_policy: self.encoding_error_policy,
+            extra: self.extra,
+            max_extra_entries: self.max_extra_entries,
+            max_extra_value_bytes: self.max_extra_value_bytes,
+            normalize_depth: self.normalize_depth,
+            max_value_length: self.max_value_length,
+            routes,
+            ignore_messages: self.ignore_messages,
+            only_messages: self.only_messages,
+            drop_if_contains: if self.drop_if_contains_ignore_case {
+                self.drop_if_contains
+                    .iter()
+                    .map(|phrase| phrase.to_lowercase())
+                    .collect()
+            } else {
+                self.drop_if_contains
+            },
+            drop_if_contains_ignore_case: self.drop_if_contains_ignore_case,
+            fingerprint_throttle,
+            dedup,
+            repeat_policy,
+            recent_lines: self.recent_logs.map(|(capacity, attach_as, threshold)| {
+                recent::RecentLines::new(capacity, attach_as, threshold)
+            }),
+            record_processor: self.record_processor,
+            stats,
+        });
+        // Started here rather than by `sentry::init`, so that it's also tracked on a given hub.
+        let session = (self.auto_session_tracking && !disabled).then(|| {
+            let hub = inner.hub();
+            hub.start_session();
+            hub
+        });
+        let queue_full = self.queue_full;
+        let worker = self
+            .queue_size
+            .filter(|_| !off)
+            .map(|queue_size| worker::Worker::spawn(inner.clone(), queue_size, queue_full));
+        let flusher = self.flush_interval.filter(|_| !off).map(|interval| {
+            let inner = inner.clone();
+            periodic::Periodic::spawn("sentry-log4rs-flush", interval, move || {
+                for client in inner.clients() {
+                    client.flush(Some(FLUSH_TIMEOUT));
+                }
+            })
+        });
+        let drop_reporter = self.report_drops_every.filter(|_| !off).map(|interval| {
+            let inner = inner.clone();
+            let mut reported = Stats::default();
+            periodic::Periodic::spawn("sentry-log4rs-drops", interval, move || {
+                reported = inner.report_drops(reported);
+            })
+        });
+        let burst = self.burst;
+        let rate_limiter = self.max_events_per_minute.map(|max_events_per_minute| {
+            ratelimit::RateLimiter::new(
+                max_events_per_minute,
+                burst.unwrap_or(max_events_per_minute),
+                clock,
+            )
+        });
+        SentryAppender {
+            worker,
+            flusher,
+            drop_reporter,
+            session,
+            _routes,
+            _sentry,
+            inner,
+            disabled,
+            threshold,
+            breadcrumb_threshold: self.breadcrumb_threshold,
+            target_thresholds: self.target_thresholds,
+            send_trace: self.send_trace,
+            include_targets: self.include_targets,
+            exclude_targets: self.exclude_targets,
+            sampler: suppress::Sampler::new(
+                self.level_sample_rates,
+                self.sampling,
+                self.sampling_salt,
+            ),
+            rate_limiter,
+            filter: self.filter,
+        }
+    }
+}
+
+/// Reads the certificates in the PEM bundle at `path`.
+pub(crate) fn read_ca_certs(path: &Path) -> anyhow::Result<Vec<reqwest::Certificate>> {
+    let pem = std::fs::read(path)
+        .with_context(|| format!("failed to read CA certificates from {}", path.display()))?;
+    reqwest::Certificate::from_pem_bundle(&pem)
+        .with_context(|| format!("invalid CA certificates in {}", path.display()))
+}
+
+pub(crate) fn create_spool_dir(dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create spool directory {}", dir.display()))
+}
+
+/// An HTTP client for the sentry transport which also trusts `ca_certs`, set up like the one
+/// `ReqwestHttpTransport` builds by default.
+fn http_client(options: &ClientOptions, ca_certs: &[reqwest::Certificate]) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder();
+    for cert in ca_certs {
+        builder = builder.add_root_certificate(cert.clone());
+    }
+    if options.accept_invalid_certs {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    if let Some(proxy) = options
+        .http_proxy
+        .as_ref()
+        .and_then(|url| reqwest::Proxy::http(url.as_ref()).ok())
+    {
+        builder = builder.proxy(proxy);
+    }
+    if let Some(proxy) = options
+        .https_proxy
+        .as_ref()
+        .and_then(|url| reqwest::Proxy::https(url.as_ref()).ok())
+    {
+        builder = builder.proxy(proxy);
+    }
+    builder.build().unwrap_or_else(|_| reqwest::Client::new())
+}
+
+/// The strings handed out by `intern`.
+static INTERNED: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+/// `ClientOptions` only takes static strings, so configured ones are leaked, once per distinct
+/// string for the whole process however often appenders are built or reconfigured.
+fn intern(s: &str) -> &'static str {
+    let mut interned = INTERNED.lock().unwrap_or_else(|e| e.into_inner());
+    match interned.get(s) {
+        Some(interned) => interned,
+        None => {
+            let leaked: &'static str = Box::leak(s.to_owned().into_boxed_str());
+            interned.insert(leaked);
+            leaked
+        }
+    }
+}
+
+/// The id and executable path of the current process.
+pub(crate) fn process_info() -> Value {
+    let mut map = Map::new();
+    map.insert(
+        "pid".to_owned(),
+        Value::Number(Number::from(std::process::id())),
+    );
+    if let Ok(exe) = std::env::current_exe() {
+        map.insert(
+            "exe".to_owned(),
+            Value::String(exe.to_string_lossy().into_owned()),
+        );
+    }
+    Value::Object(map.into_iter().collect())
+}
+
+/// Tags taken from the `SENTRY_TAG_*` environment variables.
+///
+/// Characters sentry doesn't allow in tag keys are replaced with `_`. Variables which still
+/// don't make a valid tag are skipped with a warning.
+pub(crate) fn env_tags() -> Map<String, String> {
+    let mut tags = Map::new();
+    for (name, value) in std::env::vars_os() {
+        let name = name.to_string_lossy();
+        let key = match name.strip_prefix("SENTRY_TAG_") {
+            Some(key) => key,
+            None => continue,
+        };
+        let key: String = key
+            .to_lowercase()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | '0'..='9' | '_' | '.' | ':' | '-' => c,
+                _ => '_',
+            })
+            .collect();
+        let value = value.to_string_lossy();
+        if key.is_empty() || key.len() > 32 {
+            eprintln!("log4rs: skipping {}, not a valid sentry tag name", name);
+        } else if value.is_empty() || value.len() > 200 || value.contains('\n') {
+            eprintln!("log4rs: skipping {}, not a valid sentry tag value", name);
+        } else {
+            tags.insert(key, value.into_owned());
+        }
+    }
+    tags
+}
+
+/// Describes the appender's own configuration.
+fn appender_context(threshold: Option<LevelFilter>, queue_size: Option<usize>) -> Context {
+    let mut map = Map::new();
+    map.insert(
+        "version".to_owned(),
+        Value::String(env!("CARGO_PKG_VERSION").to_owned()),
+    );
+    map.insert(
+        "threshold".to_owned(),
+        Value::String(threshold.unwrap_or(LevelFilter::Error).as_str().to_owned()),
+    );
+    map.insert("async".to_owned(), Value::Bool(queue_size.is_some()));
+    if let Some(queue_size) = queue_size {
+        map.insert(
+            "queue_size".to_owned(),
+            Value::Number(Number::from(queue_size)),
+        );
+    }
+    Context::Other(map)
+}
+
+/// The toolchain and target this crate was compiled with, as captured by the build script.
+fn build_context() -> Context {
+    let mut other = Map::new();
+    other.insert(
+        "target".to_owned(),
+        Value::String(env!("SENTRY_LOG4RS_TARGET").to_owned()),
+    );
+    RuntimeContext {
+        name: Some("rustc".to_owned()),
+        version: Some(env!("SENTRY_LOG4RS_RUSTC_VERSION").to_owned()).filter(|v| !v.is_empty()),
+        other,
+    }
+    .into()
+}
+
+/// The OS this process runs on, falling back to the compile-time OS name if it can't be detected.
+fn os_context() -> Context {
+    utils::os_context().unwrap_or_else(fallback_os_context)
+}
+
+pub(crate) fn fallback_os_context() -> Context {
+    OsContext {
+        name: Some(std::env::consts::OS.to_owned()),
+        ..Default::default()
+    }
+    .into()
+}
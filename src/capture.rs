@@ -0,0 +1,951 @@
+//! Turning records into sentry events and breadcrumbs, on the logging or the worker thread.
+
+use anyhow::Context as _;
+use log::{Level, Record};
+use log4rs::encode::{writer::simple::SimpleWriter, Encode};
+use regex::Regex;
+use sentry::{
+    integrations::backtrace,
+    protocol::{
+        value::{Number, Value},
+        Attachment, Exception, Frame, Map, Stacktrace,
+    },
+    Client, Hub, Level as SentryLevel,
+};
+use std::{borrow::Cow, path::Path, sync::Arc, time::SystemTime};
+
+use crate::{
+    in_module, kv, AttachAs, EncodingErrorPolicy, Inner, MessageFormat, Snapshot, Stats,
+    TimestampSource, ENCODE_BUFFER, LAST_EVENT_ID, TRANSACTION,
+};
+
+/// The capacity the per-thread encode buffer is shrunk back to after a longer message, so a
+/// huge one doesn't hold on to its memory.
+pub(crate) const ENCODE_BUFFER_CAPACITY: usize = 16 * 1024;
+
+impl Inner {
+    /// The hub events are captured into: the one given to the builder, or the current one.
+    pub(crate) fn hub(&self) -> Arc<Hub> {
+        match self.hub {
+            Some(ref hub) => hub.clone(),
+            None => Hub::current(),
+        }
+    }
+
+    /// The hub a record at `level` is captured into: that of the first matching route, or
+    /// `hub()`.
+    fn hub_for(&self, level: Level) -> Arc<Hub> {
+        self.routes
+            .iter()
+            .find(|&&(min_level, max_level, _)| level <= min_level && level >= max_level)
+            .map_or_else(|| self.hub(), |(_, _, hub)| hub.clone())
+    }
+
+    /// Every client events may be captured with, for flushing.
+    pub(crate) fn clients(&self) -> Vec<Arc<Client>> {
+        self.hub()
+            .client()
+            .into_iter()
+            .chain(self.routes.iter().filter_map(|(_, _, hub)| hub.client()))
+            .collect()
+    }
+
+    pub(crate) fn snapshot(&self, record: &Record, now: SystemTime) -> Snapshot {
+        Snapshot {
+            backtrace: self.backtrace(record),
+            transaction: TRANSACTION
+                .with(|transaction| transaction.borrow().clone())
+                .or_else(|| {
+                    self.transaction_from
+                        .as_ref()
+                        .and_then(|source| source.get())
+                }),
+            span: self.span(),
+            timestamp: match self.timestamp {
+                TimestampSource::Append => Some(now),
+                TimestampSource::Transport => None,
+            },
+            rate_limited_dropped: 0,
+            breadcrumb: false,
+            recent_logs: None,
+            exceptions: kv::error_chain(record),
+            template: record.args().as_str(),
+        }
+    }
+
+    /// Describes the current `tracing` span, if there is one.
+    ///
+    /// Span field values are only visible to the tracing subscriber, so just their names are
+    /// reported.
+    #[cfg(feature = "tracing")]
+    fn span(&self) -> Option<Value> {
+        if !self.tracing_span {
+            return None;
+        }
+        let span = tracing::Span::current();
+        let metadata = span.metadata()?;
+        let mut map = sentry::protocol::value::Map::new();
+        map.insert("name".to_owned(), Value::String(metadata.name().to_owned()));
+        map.insert(
+            "target".to_owned(),
+            Value::String(metadata.target().to_owned()),
+        );
+        map.insert(
+            "fields".to_owned(),
+            metadata
+                .fields()
+                .iter()
+                .map(|field| Value::String(field.name().to_owned()))
+                .collect(),
+        );
+        Some(Value::Object(map))
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    fn span(&self) -> Option<Value> {
+        None
+    }
+
+    /// Captures the current backtrace if the record's level asks for one, trimmed to the
+    /// frame which logged the record.
+    fn backtrace(&self, record: &Record) -> Option<Stacktrace> {
+        if !self.include_location || !self.backtrace_levels.contains(&record.level()) {
+            return None;
+        }
+        let mut stacktrace = backtrace::current_stacktrace()?;
+        // Frames are ordered outermost first, so the first logging frame is the one called by
+        // the code which logged the record. It and everything it called are dropped.
+        let logging = stacktrace.frames.iter().position(|frame| {
+            frame.function.as_deref().is_some_and(|function| {
+                let function = function.trim_start_matches('<');
+                function.starts_with("log::") || function.starts_with("log4rs::")
+            })
+        });
+        if let Some(logging) = logging {
+            stacktrace.frames.truncate(logging);
+        }
+        Some(stacktrace)
+    }
+
+    pub(crate) fn capture(&self, record: &Record, snapshot: Snapshot) -> anyhow::Result<()> {
+        if snapshot.breadcrumb {
+            let level =
+                kv::level(record).unwrap_or_else(|| self.breadcrumb_format.level(record.level()));
+            let encoder = self.breadcrumb_encoder.as_ref().unwrap_or(&self.encoder);
+            let message = self.encode(&**encoder, record)?;
+            let mut breadcrumb = self.breadcrumb_format.breadcrumb(
+                record,
+                self.redactor.redact(&message).into_owned(),
+                level,
+                snapshot.timestamp.unwrap_or_else(SystemTime::now),
+            );
+            if self.include_kv {
+                breadcrumb.data = kv::collect(record);
+                breadcrumb
+                    .data
+                    .retain(|key, _| !key.starts_with("sentry.") && *key != self.fingerprint_key);
+                for value in breadcrumb.data.values_mut() {
+                    self.redactor.redact_value(value);
+                }
+            }
+            // Add it to every hub, since the events it leads up to may be routed to any of them.
+            for (_, _, hub) in &self.routes {
+                hub.add_breadcrumb(breadcrumb.clone());
+            }
+            self.hub().add_breadcrumb(breadcrumb);
+            return Ok(());
+        }
+
+        let level = kv::level(record).unwrap_or_else(|| level_mapping(record.level()));
+
+        let encoded = self
+            .encode_partial(&*self.encoder, record)
+            .and_then(|(msg, error)| {
+                let fingerprint = match self.fingerprint_encoder {
+                    Some(ref encoder) => Some(self.encode(&**encoder, record)?),
+                    None => None,
+                };
+                Ok((msg, error.is_some(), fingerprint))
+            });
+        let (msg, incomplete, encoded_fingerprint) = match encoded {
+            Ok(encoded) => encoded,
+            Err(err) => {
+                self.stats.send_error();
+                return Err(err);
+            }
+        };
+
+        if self.skip_empty_messages && msg.trim().is_empty() {
+            self.stats.dropped_filtered();
+            return Ok(());
+        }
+
+        if !self.message_allowed(&msg) {
+            self.stats.dropped_message();
+            return Ok(());
+        }
+
+        let duplicates = match self.dedup {
+            Some(ref dedup) => {
+                let normalized = match self.normalizer {
+                    Some(ref normalizer) => normalizer.normalize(&msg),
+                    None => Cow::Borrowed(msg.as_str()),
+                };
+                match dedup.check(&(record.target(), &*normalized)) {
+                    Some(duplicates) => duplicates,
+                    None => {
+                        self.stats.dropped_duplicate();
+                        return Ok(());
+                    }
+                }
+            }
+            None => 0,
+        };
+
+        let mut event = sentry::protocol::Event::new();
+        event.level = level;
+        if incomplete {
+            event
+                .extra
+                .insert("encode_incomplete".to_owned(), Value::Bool(true));
+        }
+        if duplicates > 0 {
+            event
+                .extra
+                .insert("duplicate_count".to_owned(), duplicates.into());
+        }
+        let msg = match self.message_format {
+            MessageFormat::Text => msg,
+            MessageFormat::Json => self.parse_json(record, msg, &mut event.extra),
+        };
+        let (msg, full_message) = self.multiline.apply(msg);
+        let msg = match (&self.message_prefix, &self.message_suffix) {
+            (None, None) => msg,
+            (prefix, suffix) => format!(
+                "{}{}{}",
+                prefix.as_deref().unwrap_or_default(),
+                msg,
+                suffix.as_deref().unwrap_or_default()
+            ),
+        };
+        // Redacted in full first, so a secret cut by the truncation still gets caught.
+        let logged_bytes = msg.len();
+        let msg = self.redactor.redact(&msg).into_owned();
+        let msg = if self.max_message_bytes > 0 && msg.len() > self.max_message_bytes {
+            event.extra.insert(
+                "message_bytes".to_owned(),
+                Value::Number(Number::from(logged_bytes)),
+            );
+            truncate(msg, self.max_message_bytes)
+        } else {
+            msg
+        };
+        event.message = Some(msg);
+        if let Some(full_message) = full_message {
+            event
+                .extra
+                .insert("full_message".to_owned(), Value::String(full_message));
+        }
+        if let Some(ref key) = self.template_key {
+            event.logentry = kv::log_entry(record, key, &self.fingerprint_key).map(|mut entry| {
+                if let Cow::Owned(redacted) = self.redactor.redact(&entry.message) {
+                    entry.message = redacted;
+                }
+                entry
+                    .params
+                    .iter_mut()
+                    .for_each(|param| self.redactor.redact_value(param));
+                entry
+            });
+        }
+        event.logger = Some(self.logger(record).to_owned());
+        event.platform = self.platform.clone().into();
+        if let Some(ref dist) = self.dist {
+            event.dist = Some(dist.clone().into());
+        }
+        event.transaction = snapshot
+            .transaction
+            .map(|transaction| self.redactor.redact(&transaction).into_owned())
+            .or_else(|| {
+                self.culprit_source
+                    .as_ref()
+                    .and_then(|source| source.culprit(record))
+            });
+        if let Some(timestamp) = snapshot.timestamp {
+            event.timestamp = timestamp;
+        }
+        if self.include_location && self.culprit_from_location {
+            let module = record.module_path().unwrap_or_else(|| record.target());
+            event.culprit = Some(match record.line() {
+                Some(line) => format!("{}:{}", module, line),
+                None => module.to_owned(),
+            });
+        }
+        event.contexts.extend(self.contexts.clone());
+
+        let stacktrace = snapshot.backtrace.or_else(|| {
+            self.location_frame(record).map(|frame| Stacktrace {
+                frames: vec![frame],
+                ..Default::default()
+            })
+        });
+
+        if let Some(mut chain) = snapshot.exceptions {
+            if let Some(outermost) = chain.last_mut() {
+                outermost.stacktrace = stacktrace;
+            }
+            event.exception = chain.into();
+        } else if self.report_as_exception {
+            let ty = self
+                .exception_type
+                .clone()
+                .unwrap_or_else(|| record.target().to_owned());
+            event.exception = vec![Exception {
+                ty,
+                value: event.message.clone(),
+                module: record
+                    .module_path()
+                    .filter(|_| self.include_location)
+                    .map(str::to_owned),
+                stacktrace,
+                ..Default::default()
+            }]
+            .into();
+        } else {
+            event.stacktrace = stacktrace;
+        }
+
+        if self.include_location && self.location_extras {
+            if let Some(file) = record.file() {
+                event
+                    .extra
+                    .insert("file".to_owned(), Value::String(file.to_owned()));
+            }
+
+            if let Some(line) = record.line() {
+                event
+                    .extra
+                    .insert("line".to_owned(), Value::Number(Number::from(line)));
+            }
+        }
+
+        if let Some(module_path) = record.module_path().filter(|_| self.include_location) {
+            event
+                .tags
+                .insert("module_path".to_owned(), module_path.to_owned());
+        }
+
+        event.tags.extend(self.tags.clone());
+
+        if let Some(ref key) = self.level_tag {
+            // Unlike `event.level`, this keeps trace and debug apart.
+            event
+                .tags
+                .insert(key.clone(), record.level().as_str().to_lowercase());
+        }
+
+        if self.target_as_tag {
+            event
+                .tags
+                .insert("target".to_owned(), record.target().to_owned());
+        }
+
+        if let Some(ref process_info) = self.process_info {
+            event
+                .extra
+                .insert("process".to_owned(), process_info.clone());
+        }
+
+        if let Some(span) = snapshot.span {
+            event.extra.insert("span".to_owned(), span);
+        }
+
+        if snapshot.rate_limited_dropped > 0 {
+            event.extra.insert(
+                "rate_limited_dropped".to_owned(),
+                snapshot.rate_limited_dropped.into(),
+            );
+        }
+
+        let mut recent_logs = None;
+        if let (Some(lines), Some(recent)) = (snapshot.recent_logs, &self.recent_lines) {
+            match recent.attach_as {
+                AttachAs::Extra => {
+                    event.extra.insert("recent_logs".to_owned(), lines.into());
+                }
+                AttachAs::Attachment => {
+                    let mut text = lines.join("\n");
+                    if let Cow::Owned(redacted) = self.redactor.redact(&text) {
+                        text = redacted;
+                    }
+                    recent_logs = Some(Attachment {
+                        buffer: text.into_bytes(),
+                        filename: "recent_logs.txt".to_owned(),
+                        content_type: Some("text/plain".to_owned()),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        if self.include_kv {
+            let mut kv = kv::collect(record);
+            kv.remove(&self.fingerprint_key);
+            kv.remove(kv::LEVEL_KEY);
+            event.extra.insert(
+                "log_record".to_owned(),
+                log_record(record, snapshot.template, self.include_location, kv),
+            );
+        }
+
+        for (key, value) in &self.extra {
+            event
+                .extra
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+
+        for value in event.extra.values_mut() {
+            self.redactor.redact_value(value);
+        }
+        if self.limit_extras(&mut event.extra) {
+            event
+                .tags
+                .insert("extras_truncated".to_owned(), "true".to_owned());
+        }
+        for value in event.tags.values_mut() {
+            if let Cow::Owned(redacted) = self.redactor.redact(value) {
+                *value = redacted;
+            }
+        }
+        for exception in event.exception.values.iter_mut() {
+            if let Some(ref mut value) = exception.value {
+                if let Cow::Owned(redacted) = self.redactor.redact(value) {
+                    *value = redacted;
+                }
+            }
+        }
+
+        if let Some(mut fingerprint) = self.fingerprint(
+            record,
+            event.message.as_deref(),
+            encoded_fingerprint.as_deref(),
+        ) {
+            // Taken from key-values and encoder output the message redaction never saw.
+            for part in &mut fingerprint {
+                if let Cow::Owned(redacted) = self.redactor.redact(part) {
+                    *part = Cow::Owned(redacted);
+                }
+            }
+            event.fingerprint = Cow::Owned(fingerprint);
+        }
+
+        if let Some((ref repeats, first, then_sample)) = self.repeat_policy {
+            let message = event.message.as_deref().unwrap_or_default();
+            let normalized = match self.normalizer {
+                Some(ref normalizer) => normalizer.normalize(message),
+                None => Cow::Borrowed(message),
+            };
+            let mut key: Vec<&str> = event.fingerprint.iter().map(|part| part.as_ref()).collect();
+            if key.contains(&"{{ default }}") {
+                key.push(record.target());
+                key.push(&normalized);
+            }
+            let occurrences = repeats.count(&key);
+            if occurrences > first {
+                if rand::random::<f32>() >= then_sample.get() {
+                    self.stats.dropped_sampled();
+                    return Ok(());
+                }
+                event
+                    .extra
+                    .insert("occurrence_count".to_owned(), occurrences.into());
+            }
+        }
+
+        if let Some(ref throttle) = self.fingerprint_throttle {
+            let mut key: Vec<&str> = event.fingerprint.iter().map(|part| part.as_ref()).collect();
+            if key.contains(&"{{ default }}") {
+                key.push(record.target());
+                key.push(event.message.as_deref().unwrap_or_default());
+            }
+            match throttle.check(&key) {
+                Some(0) => {}
+                Some(suppressed) => {
+                    event
+                        .extra
+                        .insert("suppressed_count".to_owned(), suppressed.into());
+                }
+                None => {
+                    self.stats.dropped_ratelimit();
+                    return Ok(());
+                }
+            }
+        }
+
+        if let Some(ref processor) = self.record_processor {
+            processor(record, &mut event);
+        }
+
+        let hub = self.hub_for(record.level());
+        let mut attachments = self.attachments();
+        attachments.extend(recent_logs);
+        let event_id = if attachments.is_empty() {
+            hub.capture_event(event)
+        } else {
+            hub.with_scope(
+                |scope| {
+                    for attachment in attachments {
+                        scope.add_attachment(attachment);
+                    }
+                },
+                || hub.capture_event(event),
+            )
+        };
+        if event_id.is_nil() {
+            self.stats.dropped_client();
+        } else {
+            self.stats.sent();
+            LAST_EVENT_ID.with(|last| last.set(Some(event_id)));
+        }
+        Ok(())
+    }
+
+    /// Takes the message out of the JSON object `msg`, moving its other fields to `extra`. The
+    /// `level` and `target` fields are dropped if they match the record's. Output which isn't a
+    /// JSON object is counted and returned as is.
+    fn parse_json(&self, record: &Record, msg: String, extra: &mut Map<String, Value>) -> String {
+        let mut fields = match msg.parse::<Value>() {
+            Ok(Value::Object(fields)) => fields,
+            _ => {
+                self.stats.invalid_json();
+                return msg;
+            }
+        };
+        let message = fields
+            .remove("message")
+            .or_else(|| fields.remove("msg"))
+            .map(|message| match message {
+                Value::String(message) => message,
+                message => message.to_string(),
+            })
+            .unwrap_or_default();
+        for (key, expected) in [
+            ("level", record.level().as_str()),
+            ("target", record.target()),
+        ] {
+            if fields.get(key).and_then(Value::as_str) == Some(expected) {
+                fields.remove(key);
+            }
+        }
+        extra.extend(fields);
+        message
+    }
+
+    /// The fingerprint for `record` from its `fingerprint_key` key-value, the output of the
+    /// fingerprint encoder, the configured templates or, when normalization is enabled, the
+    /// normalized message. `None` leaves grouping to Sentry.
+    fn fingerprint(
+        &self,
+        record: &Record,
+        message: Option<&str>,
+        encoded: Option<&str>,
+    ) -> Option<Vec<Cow<'static, str>>> {
+        if let Some(fingerprint) = record
+            .key_values()
+            .get(log::kv::Key::from(self.fingerprint_key.as_str()))
+        {
+            let fingerprint: Vec<Cow<str>> = fingerprint
+                .to_string()
+                .split(',')
+                .map(str::trim)
+                .filter(|part| !part.is_empty())
+                .map(|part| Cow::Owned(part.to_owned()))
+                .collect();
+            if !fingerprint.is_empty() {
+                return Some(fingerprint);
+            }
+        }
+
+        let message = encoded.or(message).unwrap_or_default();
+        let normalized = self
+            .normalizer
+            .as_ref()
+            .map(|normalizer| normalizer.normalize(message));
+        let message = normalized.as_deref().unwrap_or(message);
+        if encoded.is_some() {
+            Some(vec![Cow::Owned(message.to_owned())])
+        } else if !self.fingerprint.is_empty() {
+            Some(
+                self.fingerprint
+                    .iter()
+                    .map(|template| Cow::Owned(template.expand(record, message)))
+                    .collect(),
+            )
+        } else if normalized.is_some() {
+            Some(vec![Cow::Owned(message.to_owned())])
+        } else {
+            None
+        }
+    }
+
+    /// Sends an event counting the records dropped, by reason, since the counters were
+    /// `reported`, unless none were. Returns the counters the report is up to date with.
+    pub(crate) fn report_drops(&self, reported: Stats) -> Stats {
+        let stats = self.stats.snapshot();
+        let drops = [
+            (
+                "rate_limited",
+                stats.events_dropped_ratelimit - reported.events_dropped_ratelimit,
+            ),
+            (
+                "deduped",
+                stats.events_dropped_duplicate - reported.events_dropped_duplicate,
+            ),
+            (
+                "sampled",
+                stats.events_dropped_sampled - reported.events_dropped_sampled,
+            ),
+            (
+                "filtered",
+                stats.events_dropped_message + stats.events_dropped_filtered
+                    - reported.events_dropped_message
+                    - reported.events_dropped_filtered,
+            ),
+            (
+                "queue_full",
+                stats.events_dropped_queue_full - reported.events_dropped_queue_full,
+            ),
+            (
+                "client",
+                stats.events_dropped_client - reported.events_dropped_client,
+            ),
+        ];
+        let total: u64 = drops.iter().map(|&(_, count)| count).sum();
+        if total > 0 {
+            let mut event = sentry::protocol::Event::new();
+            event.level = SentryLevel::Info;
+            event.logger = Some("sentry_log4rs".to_owned());
+            event.message = Some(format!("sentry-log4rs dropped {} records", total));
+            for &(reason, count) in &drops {
+                event.extra.insert(reason.to_owned(), count.into());
+            }
+            self.hub().capture_event(event);
+        }
+        stats
+    }
+
+    /// Whether the encoded message `msg` passes `ignore_messages`, `only_messages` and
+    /// `drop_if_contains`.
+    fn message_allowed(&self, msg: &str) -> bool {
+        let matches = |patterns: &[Regex]| patterns.iter().any(|pattern| pattern.is_match(msg));
+        !matches(&self.ignore_messages)
+            && (self.only_messages.is_empty() || matches(&self.only_messages))
+            && !self.contains_dropped_phrase(msg)
+    }
+
+    fn contains_dropped_phrase(&self, msg: &str) -> bool {
+        if self.drop_if_contains.is_empty() {
+            return false;
+        }
+        let msg = if self.drop_if_contains_ignore_case {
+            Cow::Owned(msg.to_lowercase())
+        } else {
+            Cow::Borrowed(msg)
+        };
+        self.drop_if_contains
+            .iter()
+            .any(|phrase| msg.contains(phrase.as_str()))
+    }
+
+    /// Formats `record` with `encoder`, stripping ANSI escape sequences if enabled.
+    fn encode(&self, encoder: &dyn Encode, record: &Record) -> anyhow::Result<String> {
+        match self.encode_partial(encoder, record)? {
+            (msg, None) => Ok(msg),
+            (_, Some(err)) => Err(err),
+        }
+    }
+
+    /// Encodes `record` like `encode`, but when the encoder fails after writing part of the
+    /// message, returns that part along with the error.
+    ///
+    /// The record is encoded into a buffer reused by each thread, so only the message itself is
+    /// allocated.
+    fn encode_partial(
+        &self,
+        encoder: &dyn Encode,
+        record: &Record,
+    ) -> anyhow::Result<(String, Option<anyhow::Error>)> {
+        ENCODE_BUFFER
+            .try_with(|buf| match buf.try_borrow_mut() {
+                Ok(mut buf) => {
+                    let encoded = self.encode_into(encoder, record, &mut buf);
+                    buf.clear();
+                    buf.shrink_to(ENCODE_BUFFER_CAPACITY);
+                    encoded
+                }
+                // The encoder logged, and this is its record being encoded.
+                Err(_) => self.encode_into(encoder, record, &mut Vec::new()),
+            })
+            // The thread is exiting and its buffer is gone.
+            .unwrap_or_else(|_| self.encode_into(encoder, record, &mut Vec::new()))
+    }
+
+    /// Encodes `record` like `encode_partial`, into the empty `buf`.
+    fn encode_into(
+        &self,
+        encoder: &dyn Encode,
+        record: &Record,
+        buf: &mut Vec<u8>,
+    ) -> anyhow::Result<(String, Option<anyhow::Error>)> {
+        let error = match encoder.encode(&mut SimpleWriter(&mut *buf), record) {
+            Ok(()) => None,
+            Err(err) if buf.is_empty() => return Err(err),
+            Err(err) => Some(err),
+        };
+        let msg = match std::str::from_utf8(buf) {
+            Ok(msg) => msg.to_owned(),
+            Err(err) => {
+                self.stats.invalid_utf8();
+                match self.encoding_error_policy {
+                    EncodingErrorPolicy::Fail => return Err(err.into()),
+                    EncodingErrorPolicy::Lossy => String::from_utf8_lossy(buf).into_owned(),
+                    EncodingErrorPolicy::Replace(replacement) => {
+                        replace_invalid_utf8(buf, replacement)
+                    }
+                }
+            }
+        };
+        let msg = match self.ansi_escapes {
+            Some(ref ansi_escapes) => match ansi_escapes.replace_all(&msg, "") {
+                Cow::Borrowed(_) => msg,
+                Cow::Owned(stripped) => stripped,
+            },
+            None => msg,
+        };
+        Ok((msg, error))
+    }
+
+    /// Adds `record` to the recent lines, returning them if they're attached to its event.
+    pub(crate) fn remember(&self, record: &Record) -> Option<Vec<String>> {
+        let recent = self.recent_lines.as_ref()?;
+        let line = self
+            .encode(&*self.encoder, record)
+            .unwrap_or_else(|_| record.args().to_string());
+        recent.push(line, recent.attached_to(record.level()))
+    }
+
+    /// Reads the files to attach to an event. Files which can't be read or are larger than
+    /// `max_attachment_size` are reported on stderr and skipped.
+    fn attachments(&self) -> Vec<Attachment> {
+        self.attach_files
+            .iter()
+            .filter_map(
+                |path| match read_attachment(path, self.max_attachment_size) {
+                    Ok(attachment) => Some(attachment),
+                    Err(err) => {
+                        eprintln!("log4rs: {:#}", err);
+                        None
+                    }
+                },
+            )
+            .collect()
+    }
+
+    /// The logger name for `record`, renamed by the longest matching `logger_map` prefix.
+    fn logger<'a>(&'a self, record: &'a Record) -> &'a str {
+        let logger = self.logger_source.logger(record);
+        self.logger_map
+            .iter()
+            .filter(|(prefix, _)| in_module(logger, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(logger, |(_, name)| name)
+    }
+
+    /// A single frame pointing at the location the record was logged from, if it has one.
+    ///
+    /// The frame is in-app unless its module matches `in_app_exclude` and not `in_app_include`.
+    fn location_frame(&self, record: &Record) -> Option<Frame> {
+        if !self.include_location || (record.file().is_none() && record.line().is_none()) {
+            return None;
+        }
+        let module = record.module_path();
+        let matches = |prefixes: &[&str]| {
+            module.is_some_and(|module| prefixes.iter().any(|prefix| in_module(module, prefix)))
+        };
+        Some(Frame {
+            filename: record.file().map(str::to_owned),
+            lineno: record.line().map(u64::from),
+            module: module.map(str::to_owned),
+            in_app: Some(matches(&self.in_app_include) || !matches(&self.in_app_exclude)),
+            ..Default::default()
+        })
+    }
+
+    /// Replaces the containers in `value` nested more than `depth` levels deep, and truncates
+    /// its strings longer than `max_value_length`. Returns whether anything was cut.
+    fn normalize(&self, value: &mut Value, depth: Option<usize>) -> bool {
+        match value {
+            Value::String(s) => match self.max_value_length {
+                Some(max) if s.len() > max => {
+                    *s = truncate(std::mem::take(s), max);
+                    true
+                }
+                _ => false,
+            },
+            Value::Array(_) if depth == Some(0) => {
+                *value = Value::String("[Array]".to_owned());
+                true
+            }
+            Value::Object(_) if depth == Some(0) => {
+                *value = Value::String("[Object]".to_owned());
+                true
+            }
+            Value::Array(values) => {
+                let mut cut = false;
+                for value in values {
+                    cut |= self.normalize(value, depth.map(|depth| depth - 1));
+                }
+                cut
+            }
+            Value::Object(map) => {
+                let mut cut = false;
+                for value in map.values_mut() {
+                    cut |= self.normalize(value, depth.map(|depth| depth - 1));
+                }
+                cut
+            }
+            _ => false,
+        }
+    }
+
+    /// Drops the extras beyond `max_extra_entries`, in key order, and truncates the ones longer
+    /// than `max_extra_value_bytes`, after normalizing them. Returns whether anything was cut.
+    fn limit_extras(&self, extra: &mut Map<String, Value>) -> bool {
+        let mut cut = false;
+        if extra.len() > self.max_extra_entries {
+            let surplus: Vec<String> = extra.keys().skip(self.max_extra_entries).cloned().collect();
+            for key in surplus {
+                extra.remove(&key);
+            }
+            cut = true;
+        }
+        for value in extra.values_mut() {
+            cut |= self.normalize(value, self.normalize_depth);
+            let text = match *value {
+                Value::String(ref s) => Cow::Borrowed(s.as_str()),
+                ref value => Cow::Owned(value.to_string()),
+            };
+            if text.len() > self.max_extra_value_bytes {
+                *value = Value::String(truncate(text.into_owned(), self.max_extra_value_bytes));
+                cut = true;
+            }
+        }
+        cut
+    }
+}
+
+/// Decodes `bytes` as UTF-8, replacing each invalid sequence with `replacement`.
+fn replace_invalid_utf8(mut bytes: &[u8], replacement: char) -> String {
+    let mut decoded = String::with_capacity(bytes.len());
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                decoded.push_str(valid);
+                return decoded;
+            }
+            Err(err) => {
+                let (valid, invalid) = bytes.split_at(err.valid_up_to());
+                decoded.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                decoded.push(replacement);
+                bytes = &invalid[err.error_len().unwrap_or(invalid.len())..];
+            }
+        }
+    }
+}
+
+pub(crate) fn level_mapping(level: Level) -> SentryLevel {
+    match level {
+        Level::Error => SentryLevel::Error,
+        Level::Warn => SentryLevel::Warning,
+        Level::Info => SentryLevel::Info,
+        Level::Debug => SentryLevel::Debug,
+        Level::Trace => SentryLevel::Debug,
+    }
+}
+
+/// Reads the file at `path` as an event attachment, refusing files larger than `max_size`.
+fn read_attachment(path: &Path, max_size: u64) -> anyhow::Result<Attachment> {
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("failed to read attachment {}", path.display()))?
+        .len();
+    if size > max_size {
+        anyhow::bail!(
+            "attachment {} is {} bytes, larger than the {} byte limit",
+            path.display(),
+            size,
+            max_size
+        );
+    }
+    let buffer = std::fs::read(path)
+        .with_context(|| format!("failed to read attachment {}", path.display()))?;
+    Ok(Attachment {
+        buffer,
+        filename: path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        ),
+        ..Default::default()
+    })
+}
+
+/// A structured view of the record, alongside the formatted message.
+pub(crate) fn log_record(
+    record: &Record,
+    template: Option<&str>,
+    include_location: bool,
+    kv: Map<String, Value>,
+) -> Value {
+    let mut map = Map::new();
+    map.insert("args".to_owned(), Value::String(record.args().to_string()));
+    if let Some(template) = template {
+        map.insert("template".to_owned(), Value::String(template.to_owned()));
+    }
+    map.insert(
+        "level".to_owned(),
+        Value::String(record.level().as_str().to_owned()),
+    );
+    map.insert(
+        "target".to_owned(),
+        Value::String(record.target().to_owned()),
+    );
+    if include_location {
+        if let Some(module_path) = record.module_path() {
+            map.insert(
+                "module_path".to_owned(),
+                Value::String(module_path.to_owned()),
+            );
+        }
+        if let Some(file) = record.file() {
+            map.insert("file".to_owned(), Value::String(file.to_owned()));
+        }
+        if let Some(line) = record.line() {
+            map.insert("line".to_owned(), Value::Number(Number::from(line)));
+        }
+    }
+    map.insert("kv".to_owned(), Value::Object(kv.into_iter().collect()));
+    Value::Object(map.into_iter().collect())
+}
+
+/// Cuts `msg` down to at most `max_bytes` bytes, on a character boundary, and marks how many
+/// bytes were cut.
+pub(crate) fn truncate(mut msg: String, max_bytes: usize) -> String {
+    let mut end = max_bytes;
+    while !msg.is_char_boundary(end) {
+        end -= 1;
+    }
+    let truncated = msg.len() - end;
+    msg.truncate(end);
+    msg.push_str(&format!("… [truncated {} bytes]", truncated));
+    msg
+}
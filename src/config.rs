@@ -0,0 +1,1381 @@
+//! The YAML configuration of the sentry appender, and its log4rs deserializer.
+
+use anyhow::Context as _;
+use log::{Level, LevelFilter};
+use log4rs::{
+    append::Append,
+    config::{Deserialize, Deserializers},
+    encode::EncoderConfig,
+};
+use regex::Regex;
+use sentry::{protocol::value::Value, Level as SentryLevel};
+use std::{collections::BTreeMap, convert::TryFrom, path::PathBuf, time::Duration};
+
+use crate::{
+    builder::{create_spool_dir, read_ca_certs},
+    fingerprint,
+    options::UncheckedSampleRate,
+    AttachAs, CulpritSource, EncodingErrorPolicy, LoggerSource, MessageFormat, MultilineMode,
+    QueueFullPolicy, Sampling, ScrubPreset, SentryAppender, SentryAppenderBuilder, TimestampSource,
+    TransactionSource,
+};
+
+/// Configuration for the sentry appender.
+///
+/// Unknown keys are rejected unless `lenient_config` is set, in which case they are reported
+/// on stderr and ignored.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SentryAppenderConfig {
+    #[serde(default = "default_true", deserialize_with = "deserialize_enabled")]
+    pub(crate) enabled: bool,
+    dsn: String,
+    #[serde(
+        serialize_with = "serialize_encoder",
+        skip_serializing_if = "Option::is_none"
+    )]
+    encoder: Option<EncoderConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default_pattern: Option<String>,
+    #[serde(
+        serialize_with = "serialize_encoder",
+        skip_serializing_if = "Option::is_none"
+    )]
+    fingerprint_encoder: Option<EncoderConfig>,
+    #[serde(default = "default_threshold")]
+    threshold: LevelFilter,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    breadcrumb_threshold: Option<LevelFilter>,
+    #[serde(
+        serialize_with = "serialize_encoder",
+        skip_serializing_if = "Option::is_none"
+    )]
+    breadcrumb_encoder: Option<EncoderConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    breadcrumb_category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    breadcrumb_type: Option<String>,
+    #[serde(default)]
+    breadcrumb_types: BTreeMap<String, String>,
+    #[serde(default)]
+    breadcrumb_level_map: BTreeMap<Level, SentryLevel>,
+    #[serde(rename = "async", skip_serializing_if = "Option::is_none")]
+    async_worker: Option<AsyncConfig>,
+    #[serde(default)]
+    include_build_context: bool,
+    #[serde(default)]
+    default_contexts: bool,
+    #[serde(default, alias = "scrub")]
+    redact_patterns: Vec<String>,
+    #[serde(default)]
+    location_extras: bool,
+    #[serde(default)]
+    include_kv: bool,
+    #[serde(default)]
+    report_as_exception: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exception_type: Option<String>,
+    #[serde(default)]
+    auto_session_tracking: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    release: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dist: Option<String>,
+    #[serde(default)]
+    backtrace_levels: Vec<Level>,
+    #[serde(default)]
+    capture_process_info: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transaction_from: Option<TransactionSource>,
+    #[serde(
+        default,
+        with = "serde_yaml::with::singleton_map",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) culprit_source: Option<CulpritSource>,
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    pub(crate) logger_source: LoggerSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    platform: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_level_tag",
+        skip_serializing_if = "Option::is_none"
+    )]
+    level_tag: Option<String>,
+    #[serde(default)]
+    target_as_tag: bool,
+    #[serde(default)]
+    env_tags: bool,
+    #[serde(default)]
+    in_app_include: Vec<String>,
+    #[serde(default)]
+    in_app_exclude: Vec<String>,
+    #[serde(default)]
+    build_info: BTreeMap<String, String>,
+    #[serde(default)]
+    tracing_span: bool,
+    #[serde(default)]
+    timestamp: TimestampSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ca_certs: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry: Option<RetryPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spool_dir: Option<PathBuf>,
+    #[serde(default = "default_max_spool_size")]
+    max_spool_size: u64,
+    #[serde(default)]
+    compress_payloads: bool,
+    #[serde(default)]
+    accept_invalid_certs: bool,
+    #[serde(default)]
+    culprit_from_location: bool,
+    #[serde(default = "default_true")]
+    include_location: bool,
+    #[serde(default)]
+    send_trace: bool,
+    #[serde(default)]
+    logger_map: BTreeMap<String, String>,
+    #[serde(default)]
+    attach_files: Vec<PathBuf>,
+    #[serde(default = "default_max_attachment_size")]
+    max_attachment_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    template_key: Option<String>,
+    #[serde(default)]
+    fingerprint: Vec<String>,
+    #[serde(default = "default_fingerprint_key")]
+    fingerprint_key: String,
+    #[serde(default)]
+    normalize_fingerprint: bool,
+    #[serde(default)]
+    normalize_patterns: Vec<NormalizePattern>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_suffix: Option<String>,
+    #[serde(default = "default_true")]
+    strip_ansi: bool,
+    #[serde(default)]
+    self_describe: bool,
+    #[serde(default)]
+    multiline: MultilineMode,
+    #[serde(default)]
+    message_format: MessageFormat,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    flush_interval: Option<Duration>,
+    #[serde(default)]
+    skip_empty_messages: bool,
+    #[serde(default = "default_max_message_bytes")]
+    max_message_bytes: usize,
+    #[serde(default, alias = "utf8", with = "serde_yaml::with::singleton_map")]
+    pub(crate) encoding_error_policy: EncodingErrorPolicy,
+    #[serde(default)]
+    extra: BTreeMap<String, Value>,
+    #[serde(default)]
+    traces_sample_rate: UncheckedSampleRate,
+    #[serde(default = "default_max_extra_entries")]
+    max_extra_entries: usize,
+    #[serde(default = "default_max_extra_value_bytes")]
+    max_extra_value_bytes: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    normalize_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_value_length: Option<usize>,
+    #[serde(default)]
+    routes: Vec<Route>,
+    #[serde(default)]
+    scrub_presets: Vec<ScrubPreset>,
+    #[serde(default)]
+    include_targets: Vec<String>,
+    #[serde(default)]
+    exclude_targets: Vec<String>,
+    #[serde(default)]
+    target_thresholds: BTreeMap<String, LevelFilter>,
+    #[serde(default)]
+    ignore_messages: Vec<String>,
+    #[serde(default)]
+    only_messages: Vec<String>,
+    #[serde(default)]
+    drop_if_contains: Vec<String>,
+    #[serde(default)]
+    drop_if_contains_ignore_case: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_events_per_minute: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    burst: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    per_fingerprint_limit: Option<u32>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    per_fingerprint_window: Option<Duration>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    dedup_window: Option<Duration>,
+    #[serde(default = "default_dedup_max_entries")]
+    dedup_max_entries: usize,
+    #[serde(default)]
+    level_sample_rates: BTreeMap<Level, UncheckedSampleRate>,
+    #[serde(default)]
+    sampling: Sampling,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sampling_salt: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    report_drops_every: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repeat_policy: Option<RepeatPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recent_logs: Option<RecentLogs>,
+    #[serde(default)]
+    lenient_config: bool,
+    #[serde(flatten, skip_serializing)]
+    unknown_fields: UnknownFields,
+}
+
+impl SentryAppenderConfig {
+    /// Serializes the configuration as YAML, with the keys in its DSNs, including those of
+    /// `routes`, replaced by `[REDACTED]`.
+    pub fn to_redacted_yaml(&self) -> anyhow::Result<String> {
+        let mut config = self.clone();
+        config.dsn = redact_dsn(&config.dsn);
+        for route in &mut config.routes {
+            route.dsn = redact_dsn(&route.dsn);
+        }
+        Ok(serde_yaml::to_string(&config)?)
+    }
+}
+
+/// The names of any keys in the configuration that don't match a field.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+struct UnknownFields(Vec<String>);
+
+impl<'de> serde::Deserialize<'de> for UnknownFields {
+    fn deserialize<D>(deserializer: D) -> Result<UnknownFields, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = UnknownFields;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a map")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<UnknownFields, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut keys = Vec::new();
+                while let Some((key, _)) = map.next_entry::<String, serde::de::IgnoredAny>()? {
+                    keys.push(key);
+                }
+                Ok(UnknownFields(keys))
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+/// Configuration for the background worker of the sentry appender.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AsyncConfig {
+    #[serde(default = "default_queue_size", alias = "queue_capacity")]
+    queue_size: usize,
+    #[serde(default, alias = "queue_full_policy")]
+    queue_full: QueueFullPolicy,
+}
+
+/// A pattern replacing part of a message when normalizing it for the fingerprint.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NormalizePattern {
+    pattern: String,
+    replacement: String,
+}
+
+/// A range of levels sent to a DSN of their own.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    #[serde(default = "default_route_min_level")]
+    min_level: Level,
+    #[serde(default = "default_route_max_level")]
+    max_level: Level,
+    dsn: String,
+}
+
+/// How many events sharing a fingerprint are sent in full before sampling kicks in.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepeatPolicy {
+    first: u32,
+    then_sample: UncheckedSampleRate,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    window: Option<Duration>,
+}
+
+/// How events Sentry fails to take are sent again.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    #[serde(default = "default_max_retries")]
+    max_retries: u32,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    initial_backoff: Option<Duration>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    max_backoff: Option<Duration>,
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+/// The recent log lines attached to events.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecentLogs {
+    #[serde(default = "default_recent_logs_capacity")]
+    capacity: usize,
+    #[serde(default)]
+    attach_as: AttachAs,
+    #[serde(default = "default_threshold")]
+    threshold: LevelFilter,
+}
+
+fn default_recent_logs_capacity() -> usize {
+    100
+}
+
+fn default_route_min_level() -> Level {
+    Level::Trace
+}
+
+fn default_route_max_level() -> Level {
+    Level::Error
+}
+
+/// Deserializes an optional duration written like `30s` or `1m 30s`.
+pub(crate) fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let duration: Option<String> = serde::Deserialize::deserialize(deserializer)?;
+    duration
+        .map(|duration| humantime::parse_duration(&duration).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Deserializes `level_tag`, either a boolean, true meaning the `log_level` key, or a key.
+fn deserialize_level_tag<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum LevelTag {
+        Bool(bool),
+        Key(String),
+    }
+
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        LevelTag::Bool(true) => Some("log_level".to_owned()),
+        LevelTag::Bool(false) => None,
+        LevelTag::Key(key) => Some(key),
+    })
+}
+
+/// Deserializes `enabled`, either a boolean or a string such as `${SENTRY_ENABLED:-true}`
+/// which is expanded from the environment first.
+fn deserialize_enabled<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Enabled {
+        Bool(bool),
+        String(String),
+    }
+
+    match serde::Deserialize::deserialize(deserializer)? {
+        Enabled::Bool(enabled) => Ok(enabled),
+        Enabled::String(enabled) => {
+            let expanded = expand_env(&enabled).map_err(serde::de::Error::custom)?;
+            expanded.trim().parse().map_err(|_| {
+                serde::de::Error::custom(format!(
+                    "`enabled` must be true or false, got `{}`",
+                    expanded
+                ))
+            })
+        }
+    }
+}
+
+/// Replaces each `${VAR}` in `value` with the variable's value, and each `${VAR:-default}`
+/// with it or, if it's unset or empty, with `default`.
+fn expand_env(mut value: &str) -> anyhow::Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    while let Some(start) = value.find("${") {
+        expanded.push_str(&value[..start]);
+        let rest = &value[start + 2..];
+        let end = rest
+            .find('}')
+            .with_context(|| format!("unterminated `${{` in `{}`", value))?;
+        let (name, default) = match rest[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&rest[..end], None),
+        };
+        match (std::env::var(name), default) {
+            (Ok(var), Some(default)) if var.is_empty() => expanded.push_str(default),
+            (Ok(var), _) => expanded.push_str(&var),
+            (Err(_), Some(default)) => expanded.push_str(default),
+            (Err(_), None) => anyhow::bail!("environment variable `{}` is not set", name),
+        }
+        value = &rest[end + 1..];
+    }
+    expanded.push_str(value);
+    Ok(expanded)
+}
+
+/// Serializes an optional duration the way `deserialize_duration` reads it.
+pub(crate) fn serialize_duration<S>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    use serde::Serialize;
+
+    duration
+        .map(|duration| humantime::format_duration(duration).to_string())
+        .serialize(serializer)
+}
+
+/// Serializes an encoder configuration the way log4rs reads it: its kind next to the rest of
+/// its configuration.
+pub(crate) fn serialize_encoder<S>(
+    encoder: &Option<EncoderConfig>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    use serde::ser::SerializeMap;
+
+    let encoder = match encoder {
+        Some(encoder) => encoder,
+        None => return serializer.serialize_none(),
+    };
+    let mut map = serializer.serialize_map(None)?;
+    map.serialize_entry("kind", &encoder.kind)?;
+    if let serde_value::Value::Map(ref config) = encoder.config {
+        for (key, value) in config {
+            map.serialize_entry(key, value)?;
+        }
+    }
+    map.end()
+}
+
+/// Replaces the keys in `dsn` with `[REDACTED]`.
+pub(crate) fn redact_dsn(dsn: &str) -> String {
+    match (dsn.find("://"), dsn.rfind('@')) {
+        (Some(scheme), Some(host)) if scheme + 3 <= host => {
+            format!("{}[REDACTED]{}", &dsn[..scheme + 3], &dsn[host..])
+        }
+        _ => dsn.to_owned(),
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+pub(crate) fn default_max_attachment_size() -> u64 {
+    1024 * 1024
+}
+
+fn default_max_spool_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+pub(crate) fn default_fingerprint_key() -> String {
+    "sentry.fingerprint".to_owned()
+}
+
+pub(crate) fn default_max_message_bytes() -> usize {
+    8 * 1024
+}
+
+pub(crate) fn default_max_extra_entries() -> usize {
+    100
+}
+
+pub(crate) fn default_max_extra_value_bytes() -> usize {
+    16 * 1024
+}
+
+fn default_threshold() -> LevelFilter {
+    LevelFilter::Error
+}
+
+pub(crate) fn default_dedup_max_entries() -> usize {
+    1024
+}
+
+fn default_queue_size() -> usize {
+    1024
+}
+
+impl SentryAppenderBuilder {
+    /// Creates a builder with every option of `config` applied, deserializing its encoder with
+    /// `deserializers`. Fails on invalid values and, unless `lenient_config` is set, on unknown
+    /// fields.
+    pub fn from_config(
+        config: SentryAppenderConfig,
+        deserializers: &Deserializers,
+    ) -> anyhow::Result<SentryAppenderBuilder> {
+        // Problems are collected rather than returned one at a time, so they can all be fixed
+        // at once.
+        let mut problems = Vec::new();
+
+        if !config.unknown_fields.0.is_empty() {
+            let fields = config.unknown_fields.0.join("`, `");
+            if config.lenient_config {
+                eprintln!(
+                    "log4rs: ignoring unknown sentry appender fields `{}`",
+                    fields
+                );
+            } else {
+                problems.push(format!("unknown sentry appender fields `{}`", fields));
+            }
+        }
+
+        let mut appender = SentryAppender::builder().enabled(config.enabled);
+
+        if let Some(encoder) = config.encoder {
+            match deserializers.deserialize(&encoder.kind, encoder.config) {
+                Ok(encoder) => appender = appender.encoder(encoder),
+                Err(err) => problems.push(format!("invalid encoder: {:#}", err)),
+            }
+        }
+
+        if let Some(ref pattern) = config.default_pattern {
+            appender = appender.default_pattern(pattern);
+        }
+
+        if let Some(encoder) = config.fingerprint_encoder {
+            match deserializers.deserialize(&encoder.kind, encoder.config) {
+                Ok(encoder) => appender = appender.fingerprint_encoder(encoder),
+                Err(err) => problems.push(format!("invalid fingerprint_encoder: {:#}", err)),
+            }
+        }
+
+        appender = appender.dsn(config.dsn);
+
+        appender = appender.threshold(config.threshold);
+
+        if let Some(threshold) = config.breadcrumb_threshold {
+            appender = appender.breadcrumb_threshold(threshold);
+        }
+
+        if let Some(encoder) = config.breadcrumb_encoder {
+            match deserializers.deserialize(&encoder.kind, encoder.config) {
+                Ok(encoder) => appender = appender.breadcrumb_encoder(encoder),
+                Err(err) => problems.push(format!("invalid breadcrumb_encoder: {:#}", err)),
+            }
+        }
+
+        if let Some(ref category) = config.breadcrumb_category {
+            appender = appender.breadcrumb_category(category);
+        }
+
+        if let Some(ref ty) = config.breadcrumb_type {
+            appender = appender.breadcrumb_type(ty);
+        }
+
+        for (prefix, ty) in &config.breadcrumb_types {
+            appender = appender.breadcrumb_type_for(prefix, ty);
+        }
+
+        for (&level, &mapped) in &config.breadcrumb_level_map {
+            appender = appender.breadcrumb_level_for(level, mapped);
+        }
+
+        appender = appender.include_build_context(config.include_build_context);
+
+        appender = appender.default_contexts(config.default_contexts);
+
+        for pattern in &config.redact_patterns {
+            match Regex::new(pattern) {
+                Ok(pattern) => appender = appender.redact_pattern(pattern),
+                Err(err) => problems.push(format!("invalid redact pattern `{}`: {}", pattern, err)),
+            }
+        }
+
+        for &preset in &config.scrub_presets {
+            appender = appender.scrub_preset(preset);
+        }
+
+        appender = appender.location_extras(config.location_extras);
+
+        appender = appender.include_kv(config.include_kv);
+
+        appender = appender.report_as_exception(config.report_as_exception);
+
+        if let Some(exception_type) = config.exception_type {
+            appender = appender.exception_type(&exception_type);
+        }
+
+        appender = appender.auto_session_tracking(config.auto_session_tracking);
+
+        if let Some(ref release) = config.release {
+            appender = appender.release(release);
+        }
+
+        if let Some(ref dist) = config.dist {
+            appender = appender.dist(dist);
+        }
+
+        appender = appender.backtrace_levels(config.backtrace_levels);
+
+        appender = appender.capture_process_info(config.capture_process_info);
+
+        if let Some(source) = config.transaction_from {
+            appender = appender.transaction_from(source);
+        }
+
+        if let Some(source) = config.culprit_source {
+            appender = appender.culprit_source(source);
+        }
+
+        appender = appender.logger_source(config.logger_source);
+        if let Some(platform) = config.platform {
+            appender = appender.platform(&platform);
+        }
+
+        if let Some(ref key) = config.level_tag {
+            appender = appender.level_tag_key(key);
+        }
+
+        appender = appender.target_as_tag(config.target_as_tag);
+
+        appender = appender.env_tags(config.env_tags);
+
+        for prefix in &config.in_app_include {
+            appender = appender.in_app_include(prefix);
+        }
+
+        for prefix in &config.in_app_exclude {
+            appender = appender.in_app_exclude(prefix);
+        }
+
+        for (name, env_var) in &config.build_info {
+            appender = appender.build_info(name, env_var);
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            appender = appender.tracing_span(config.tracing_span);
+        }
+        #[cfg(not(feature = "tracing"))]
+        if config.tracing_span {
+            problems
+                .push("`tracing_span` requires the `tracing` feature of sentry-log4rs".to_owned());
+        }
+
+        appender = appender.timestamp(config.timestamp);
+
+        if let Some(ref path) = config.ca_certs {
+            match read_ca_certs(path) {
+                Ok(certs) => appender.ca_certs.extend(certs),
+                Err(err) => problems.push(format!("{:#}", err)),
+            }
+        }
+
+        if let Some(retry) = config.retry {
+            appender = appender.retry(
+                retry.max_retries,
+                retry.initial_backoff.unwrap_or(Duration::from_secs(1)),
+                retry.max_backoff.unwrap_or(Duration::from_secs(30)),
+            );
+        }
+
+        if let Some(ref dir) = config.spool_dir {
+            match create_spool_dir(dir) {
+                Ok(()) => appender.spool = Some((dir.clone(), config.max_spool_size)),
+                Err(err) => problems.push(format!("{:#}", err)),
+            }
+        }
+
+        appender = appender.compress_payloads(config.compress_payloads);
+
+        appender = appender.accept_invalid_certs(config.accept_invalid_certs);
+
+        appender = appender.culprit_from_location(config.culprit_from_location);
+
+        appender = appender.include_location(config.include_location);
+
+        appender = appender.send_trace(config.send_trace);
+
+        for (prefix, name) in &config.logger_map {
+            appender = appender.logger_map(prefix, name);
+        }
+
+        for path in config.attach_files {
+            appender = appender.attach_file(path);
+        }
+
+        appender = appender.max_attachment_size(config.max_attachment_size);
+
+        if let Some(ref key) = config.template_key {
+            appender = appender.template_key(key);
+        }
+
+        if !config.fingerprint.is_empty() {
+            let templates = config
+                .fingerprint
+                .iter()
+                .map(|template| fingerprint::Template::parse(template))
+                .collect::<anyhow::Result<_>>();
+            match templates {
+                Ok(templates) => appender.fingerprint = templates,
+                Err(err) => problems.push(format!("invalid fingerprint: {:#}", err)),
+            }
+        }
+
+        appender = appender.fingerprint_key(&config.fingerprint_key);
+
+        appender = appender.normalize_fingerprint(config.normalize_fingerprint);
+
+        for NormalizePattern {
+            pattern,
+            replacement,
+        } in &config.normalize_patterns
+        {
+            match Regex::new(pattern) {
+                Ok(pattern) => appender = appender.normalize_pattern(pattern, replacement),
+                Err(err) => {
+                    problems.push(format!("invalid normalize pattern `{}`: {}", pattern, err))
+                }
+            }
+        }
+
+        if let Some(ref prefix) = config.message_prefix {
+            appender = appender.message_prefix(prefix);
+        }
+
+        if let Some(ref suffix) = config.message_suffix {
+            appender = appender.message_suffix(suffix);
+        }
+
+        appender = appender.strip_ansi(config.strip_ansi);
+
+        appender = appender.self_describe(config.self_describe);
+
+        appender = appender.multiline(config.multiline);
+
+        appender = appender.message_format(config.message_format);
+
+        if let Some(interval) = config.flush_interval {
+            appender = appender.flush_interval(interval);
+        }
+
+        appender = appender.skip_empty_messages(config.skip_empty_messages);
+
+        appender = appender.max_message_bytes(config.max_message_bytes);
+
+        appender = appender.encoding_error_policy(config.encoding_error_policy);
+
+        for (key, value) in config.extra {
+            appender = appender.extra(&key, value);
+        }
+
+        appender = appender.traces_sample_rate(
+            config
+                .traces_sample_rate
+                .check("traces_sample_rate", &mut problems),
+        );
+
+        appender = appender
+            .max_extra_entries(config.max_extra_entries)
+            .max_extra_value_bytes(config.max_extra_value_bytes);
+
+        if let Some(depth) = config.normalize_depth {
+            appender = appender.normalize_depth(depth);
+        }
+
+        if let Some(max_value_length) = config.max_value_length {
+            appender = appender.max_value_length(max_value_length);
+        }
+
+        for route in config.routes {
+            appender = appender.route(route.min_level, route.max_level, route.dsn);
+        }
+
+        appender = appender.include_targets(config.include_targets);
+
+        appender = appender.exclude_targets(config.exclude_targets);
+
+        for (prefix, &threshold) in &config.target_thresholds {
+            appender = appender.target_threshold(prefix, threshold);
+        }
+
+        for pattern in &config.ignore_messages {
+            match Regex::new(pattern) {
+                Ok(pattern) => appender = appender.ignore_message(pattern),
+                Err(err) => problems.push(format!(
+                    "invalid ignore_messages pattern `{}`: {}",
+                    pattern, err
+                )),
+            }
+        }
+
+        for pattern in &config.only_messages {
+            match Regex::new(pattern) {
+                Ok(pattern) => appender = appender.only_message(pattern),
+                Err(err) => problems.push(format!(
+                    "invalid only_messages pattern `{}`: {}",
+                    pattern, err
+                )),
+            }
+        }
+
+        for phrase in &config.drop_if_contains {
+            appender = appender.drop_if_contains(phrase);
+        }
+
+        appender = appender.drop_if_contains_ignore_case(config.drop_if_contains_ignore_case);
+
+        if let Some(max_events_per_minute) = config.max_events_per_minute {
+            appender = appender.max_events_per_minute(max_events_per_minute);
+        }
+
+        if let Some(burst) = config.burst {
+            appender = appender.burst(burst);
+        }
+
+        if let Some(limit) = config.per_fingerprint_limit {
+            appender = appender.per_fingerprint_limit(limit);
+        }
+
+        if let Some(window) = config.per_fingerprint_window {
+            appender = appender.per_fingerprint_window(window);
+        }
+
+        if let Some(window) = config.dedup_window {
+            appender = appender.dedup_window(window);
+        }
+
+        appender = appender.dedup_max_entries(config.dedup_max_entries);
+
+        for (&level, &rate) in &config.level_sample_rates {
+            let name = format!("level_sample_rates.{}", level.as_str().to_ascii_lowercase());
+            let rate = rate.check(&name, &mut problems);
+            appender = appender.level_sample_rate(level, rate);
+        }
+
+        appender = appender.sampling(config.sampling);
+
+        if let Some(ref salt) = config.sampling_salt {
+            appender = appender.sampling_salt(salt);
+        }
+
+        if let Some(interval) = config.report_drops_every {
+            appender = appender.report_drops_every(interval);
+        }
+
+        if let Some(policy) = config.repeat_policy {
+            appender = appender.repeat_policy(
+                policy.first,
+                policy
+                    .then_sample
+                    .check("repeat_policy.then_sample", &mut problems),
+                policy.window.unwrap_or(Duration::from_secs(60)),
+            );
+        }
+
+        if let Some(recent_logs) = config.recent_logs {
+            appender = appender.recent_logs(
+                recent_logs.capacity,
+                recent_logs.attach_as,
+                recent_logs.threshold,
+            );
+        }
+
+        if let Some(async_worker) = config.async_worker {
+            appender = appender
+                .async_queue(async_worker.queue_size)
+                .queue_full(async_worker.queue_full);
+        }
+
+        problems.extend(appender.problems());
+        config_errors(problems)?;
+        Ok(appender)
+    }
+}
+
+impl TryFrom<SentryAppenderConfig> for SentryAppenderBuilder {
+    type Error = anyhow::Error;
+
+    /// See `SentryAppenderBuilder::from_config`; the encoder is deserialized with log4rs's
+    /// default deserializers.
+    fn try_from(config: SentryAppenderConfig) -> anyhow::Result<SentryAppenderBuilder> {
+        SentryAppenderBuilder::from_config(config, &Deserializers::default())
+    }
+}
+
+impl TryFrom<SentryAppenderConfig> for SentryAppender {
+    type Error = anyhow::Error;
+
+    fn try_from(config: SentryAppenderConfig) -> anyhow::Result<SentryAppender> {
+        Ok(SentryAppenderBuilder::try_from(config)?.build())
+    }
+}
+
+/// A deserializer for the `SentryAppender`.
+///
+/// # Configuration
+///
+/// ```yaml
+/// kind: sentry
+///
+/// # Turn the appender off, keeping the stanza so references to it still resolve. Either a
+/// # boolean or a string expanded from the environment, like `${SENTRY_ENABLED:-true}`.
+/// # Defaults to true.
+/// enabled: true
+///
+/// # The sentry DSN, e.g. "https://key@sentry.io/42"
+/// dsn: "YOUR_DSN_HERE"
+///
+/// # The log level threshold. `off` disables the appender without initializing a client.
+/// # Defaults to `error`.
+/// threshold: error  # overriding the logging threshold to the ERROR level
+///
+/// # Records below `threshold` but at or above this level are added as breadcrumbs, so the
+/// # events which follow show them. Optional.
+/// breadcrumb_threshold: info
+///
+/// # How breadcrumbs look: the encoder of their message, which defaults to `encoder`; their
+/// # category, in which `{target}`, `{module_path}` and `{level}` are replaced with the
+/// # record's, defaulting to `{target}`; their type, `default` unless given, also per target
+/// # prefix; and the sentry level of those of each log level, `debug`, `info`, `warning`,
+/// # `error` or `fatal`, defaulting to that of events.
+/// breadcrumb_encoder:
+///   pattern: "{m}"
+/// breadcrumb_category: "{module_path}"
+/// breadcrumb_type: default
+/// breadcrumb_types:
+///   reqwest: http
+///   sqlx: query
+/// breadcrumb_level_map:
+///   warn: warning
+///   info: info
+///
+/// # The encoder to use to format output. Defaults to `kind: pattern`.
+/// encoder:
+///   kind: pattern
+///
+/// # The pattern used when no encoder is given. Defaults to `{m}`.
+/// default_pattern: "{l} {t} - {m}"
+///
+/// # Encode and send records on a background thread. Once the appender is dropped, queued
+/// # records are still sent for up to two seconds. Optional.
+/// async:
+///   # The maximum number of records waiting to be sent. Defaults to 1024. Also accepted as
+///   # `queue_capacity`.
+///   queue_size: 1024
+///   # What to do when the queue is full: `drop_new`, `drop_oldest` or `block`, which makes
+///   # the logging thread wait for room. Records dropped are counted in the appender's stats.
+///   # Defaults to `drop_new`. Also accepted as `queue_full_policy`.
+///   queue_full: drop_new
+///
+/// # Attach the rustc version and target triple as a `rust` context. Defaults to false.
+/// include_build_context: false
+///
+/// # Attach `os` and `device` contexts. Defaults to false.
+/// default_contexts: false
+///
+/// # Regexes whose matches are replaced with `[REDACTED]` in the message, exceptions, extras
+/// # and tags. Also accepted as `scrub`. Optional.
+/// redact_patterns:
+///   - "token=[^&\\s]+"
+///
+/// # Built-in scrubbers for `email`, `ip` and `credit_card`, replacing matches with `[EMAIL]`,
+/// # `[IP]` and `[CREDIT_CARD]` wherever `redact_patterns` apply, and in the user context.
+/// # Optional.
+/// scrub_presets: [email, ip]
+///
+/// # Also record the file and line as `file` and `line` extras, in addition to the
+/// # stacktrace frame. Defaults to false.
+/// location_extras: false
+///
+/// # Attach the record's arguments and key-values as a structured `log_record` extra, and the
+/// # key-values as the data of breadcrumbs. Defaults to false.
+/// include_kv: false
+///
+/// # Report records as exceptions instead of plain messages. Defaults to false.
+/// report_as_exception: false
+///
+/// # The exception type used by `report_as_exception`. Defaults to the record's target.
+/// exception_type: "LogError"
+///
+/// # Track a release health session while the appender is alive. Defaults to false.
+/// auto_session_tracking: false
+///
+/// # The release events are reported under. Defaults to the `SENTRY_RELEASE` environment
+/// # variable.
+/// release: "my-app@1.4.2"
+///
+/// # The distribution of the release, telling apart its builds, e.g. per architecture.
+/// # Optional.
+/// dist: x86_64
+///
+/// # Capture a backtrace for records of these levels. Defaults to none.
+/// backtrace_levels:
+///   - error
+///
+/// # Attach the process id and executable path as a `process` extra. Defaults to false.
+/// capture_process_info: false
+///
+/// # Where to read the event's transaction from: `thread_name`, or `mdc:<key>` for a
+/// # log4rs MDC entry. Optional.
+/// transaction_from: "mdc:request_path"
+///
+/// # What to set the event's transaction, shown as the issue's culprit, to when there's no
+/// # other transaction: `module_path`, `file:line`, or `fixed: <name>`. Optional.
+/// culprit_source: module_path
+///
+/// # What the event's logger is set to: `target`, `module_path`, or `fixed: <name>`.
+/// # Defaults to `target`.
+/// logger_source: target
+///
+/// # The event's platform, e.g. for a proxy expecting `native`. Defaults to `rust`.
+/// platform: rust
+///
+/// # Tag events with the record's level (trace, debug, info, warn or error) as `log_level`
+/// # when true, or under the given tag key. Defaults to false.
+/// level_tag: severity
+///
+/// # Tag events with the record's target as `target`. Each target is a distinct tag value,
+/// # which adds up in applications with many modules. Defaults to false.
+/// target_as_tag: false
+///
+/// # Tag events with the `SENTRY_TAG_*` environment variables. Defaults to false.
+/// env_tags: false
+///
+/// # Module prefixes whose stacktrace frames are marked as in-app, or as not in-app.
+/// # Optional.
+/// in_app_include:
+///   - my_app
+/// in_app_exclude:
+///   - hyper
+///
+/// # Environment variables read once at startup and reported in a `build` context and as
+/// # tags. Unset variables are skipped. Optional.
+/// build_info:
+///   git_sha: GIT_COMMIT
+///   built_at: BUILD_TIME
+///
+/// # Attach the current `tracing` span as the `span` extra. Requires the `tracing` feature.
+/// # Defaults to false.
+/// tracing_span: false
+///
+/// # When the event's timestamp is taken: `append`, as the record is logged, or `transport`,
+/// # as the event is built for sending. Defaults to `append`.
+/// timestamp: append
+///
+/// # A PEM bundle of extra certificate authorities to trust, e.g. for a TLS-intercepting
+/// # proxy. Optional.
+/// ca_certs: /etc/ssl/corporate-ca.pem
+///
+/// # Send events again when Sentry answers with a server error or can't be reached: up to
+/// # `max_retries` times, which defaults to 3, waiting `initial_backoff`, by default `1s`,
+/// # then twice as long each time up to `max_backoff`, by default `30s`. `Retry-After`
+/// # headers are honored, unless longer than `max_backoff`. Events given up on are spooled
+/// # with `spool_dir`, or counted as send errors. Events rate limited by Sentry are dropped
+/// # until the limit expires. Events are then sent one at a time. Optional.
+/// retry:
+///   max_retries: 3
+///   initial_backoff: 1s
+///   max_backoff: 30s
+///
+/// # Write events which still can't be sent to files in this directory, and send them again
+/// # at startup, once an event gets through, and every minute. The oldest are deleted when
+/// # they take more than `max_spool_size` bytes, which defaults to 10 MiB. Optional.
+/// spool_dir: /var/spool/my_app/sentry
+/// max_spool_size: 10485760
+///
+/// # Gzip the events sent to Sentry. Defaults to false.
+/// compress_payloads: false
+///
+/// # INSECURE: don't validate the Sentry server's certificate. Defaults to false.
+/// accept_invalid_certs: false
+///
+/// # Set the event's culprit to `module_path:line`. Defaults to false.
+/// culprit_from_location: false
+///
+/// # Report where records were logged from: stacktraces, the `module_path` tag, and file and
+/// # line extras. Defaults to true.
+/// include_location: true
+///
+/// # Send trace-level records. They are dropped even under `threshold: trace` unless this is
+/// # set. Defaults to false.
+/// send_trace: false
+///
+/// # Friendly logger names for target prefixes. The longest matching prefix wins. Optional.
+/// logger_map:
+///   my_app::adapters::stripe: payments.stripe
+///
+/// # Files attached to every captured event, read when the event is captured. Optional.
+/// attach_files:
+///   - /var/lib/my_app/config-snapshot.json
+///
+/// # Attached files larger than this many bytes are skipped. Defaults to 1 MiB.
+/// max_attachment_size: 1048576
+///
+/// # Group records on a message template passed as this key-value, e.g.
+/// # `error!(msg_template = "failed to load user %s", user_id = id; "failed to load user {}", id)`.
+/// # The other key-values become the template's parameters. Optional.
+/// template_key: msg_template
+///
+/// # The fingerprint events are grouped by. Entries may use the placeholders `{target}`,
+/// # `{module_path}`, `{level}` and `{message}`; `{{ default }}` is Sentry's own grouping.
+/// # Optional.
+/// fingerprint:
+///   - "{{ default }}"
+///   - "{target}"
+///
+/// # A key-value whose value, or comma-separated list of values, overrides the fingerprint of
+/// # its record. Defaults to `sentry.fingerprint`.
+/// fingerprint_key: sentry.fingerprint
+///
+/// # An encoder whose output is used as the fingerprint, e.g. to leave out context the message
+/// # includes. Optional.
+/// fingerprint_encoder:
+///   kind: pattern
+///   pattern: "{t}: {m}"
+///
+/// # Compute the fingerprint from the message with quoted strings, UUIDs, hex ids and
+/// # integers replaced by placeholders. Defaults to false.
+/// normalize_fingerprint: false
+///
+/// # Patterns to normalize messages with instead of the built-in ones, applied in order.
+/// # Optional.
+/// normalize_patterns:
+///   - pattern: "user \\w+"
+///     replacement: "user <name>"
+///
+/// # Text added before and after every message, e.g. to tell services apart. Optional.
+/// message_prefix: "[billing] "
+/// message_suffix: ""
+///
+/// # Strip ANSI escape sequences, e.g. colors, from encoded messages. Defaults to true.
+/// strip_ansi: true
+///
+/// # Add a `sentry_log4rs` context describing the appender, for debugging the integration.
+/// # Defaults to false.
+/// self_describe: false
+///
+/// # How messages spanning several lines are reported: `raw`, as they are; `split`, the first
+/// # line as the message and the full text as the `full_message` extra; or `join`, with the
+/// # newlines escaped as `\n`. Defaults to `raw`.
+/// multiline: raw
+///
+/// # The format of the encoder's output: `text`, or `json` to use the `message` or `msg`
+/// # field of a JSON encoder's output as the message and its other fields as extras. Defaults
+/// # to `text`.
+/// message_format: text
+///
+/// # Flush the sentry client periodically, e.g. `30s` or `1m`. The appender is still flushed
+/// # when log4rs flushes it or it's dropped. Optional.
+/// flush_interval: 30s
+///
+/// # Drop records whose encoded message is empty or only whitespace. Defaults to false.
+/// skip_empty_messages: false
+///
+/// # Truncate messages longer than this many bytes; 0 disables truncation. Defaults to 8192.
+/// max_message_bytes: 8192
+///
+/// # What to do when the encoder's output isn't valid UTF-8: `lossy`, to replace invalid
+/// # sequences with U+FFFD, `replace: "?"`, to replace them with another character, or `fail`,
+/// # to fail the append. Defaults to `lossy`; `utf8` is accepted as an alias.
+/// encoding_error_policy: lossy
+///
+/// # Extras added to every event. Values may be any JSON value. Optional.
+/// extra:
+///   feature_flags:
+///     new_checkout: true
+///
+/// # The share of performance transactions sent to Sentry, between 0 and 1. Defaults to 0.
+/// traces_sample_rate: 0.1
+///
+/// # Limits on the extras of an event. Surplus extras are dropped in key order, and longer
+/// # values are truncated; either tags the event with `extras_truncated: true`. Default to 100
+/// # extras and 16384 bytes.
+/// max_extra_entries: 100
+/// max_extra_value_bytes: 16384
+///
+/// # Replace arrays and objects nested deeper than `normalize_depth` in extras with `"[Array]"`
+/// # or `"[Object]"`, and truncate strings within them to `max_value_length` bytes, before
+/// # `max_extra_value_bytes` applies. Either tags the event with `extras_truncated: true`.
+/// # Unlimited by default, like the sentry client. Optional.
+/// normalize_depth: 3
+/// max_value_length: 1024
+///
+/// # Send records within a range of levels to other Sentry projects. `min_level` and
+/// # `max_level` are inclusive and default to `trace` and `error`. The first matching route
+/// # wins; other records go to `dsn`. Optional.
+/// routes:
+///   - min_level: warn
+///     max_level: warn
+///     dsn: "YOUR_WARNINGS_DSN_HERE"
+///
+/// # Only send records from these targets and their modules; all targets if empty. Optional.
+/// include_targets: [my_app]
+///
+/// # Never send records from these targets and their modules, even if included. Optional.
+/// exclude_targets: [hyper, sqlx, want]
+///
+/// # Thresholds for target prefixes, overriding `threshold`. The longest matching prefix
+/// # wins; `off` silences a target entirely. Optional.
+/// target_thresholds:
+///   my_app::payments: warn
+///   my_app::metrics: off
+///
+/// # Regexes matched against encoded messages: records matching any `ignore_messages`
+/// # pattern are dropped and, if `only_messages` is given, so are those matching none of its
+/// # patterns. Optional.
+/// ignore_messages:
+///   - "connection reset by peer"
+/// only_messages: []
+///
+/// # Phrases which drop the records whose encoded message contains any of them, ignoring case
+/// # if `drop_if_contains_ignore_case` is set. Optional; case matters by default.
+/// drop_if_contains:
+///   - "deprecated config key"
+/// drop_if_contains_ignore_case: false
+///
+/// # Send at most this many events a minute, letting up to `burst` through at once. Dropped
+/// # events are counted, and reported on the next event sent as `rate_limited_dropped`. `burst`
+/// # defaults to `max_events_per_minute`. Optional.
+/// max_events_per_minute: 600
+/// burst: 50
+///
+/// # Send at most `per_fingerprint_limit` events per fingerprint in each
+/// # `per_fingerprint_window`, which defaults to `1m`. The next event sent reports how many were
+/// # dropped as `suppressed_count`. Optional.
+/// per_fingerprint_limit: 10
+/// per_fingerprint_window: 5m
+///
+/// # Send identical messages from a target only once in this window, e.g. `60s`, keeping
+/// # track of up to `dedup_max_entries` messages. Optional; `dedup_max_entries` defaults to 1024.
+/// dedup_window: 60s
+/// dedup_max_entries: 1024
+///
+/// # The share of records at these levels to send, between 0 and 1. Levels not listed are all
+/// # sent. Optional.
+/// level_sample_rates:
+///   warn: 0.05
+///   info: 0.01
+///
+/// # How `level_sample_rates` picks records: `random`, or `deterministic`, to decide by a hash
+/// # of the target and message so the same record is always kept or always dropped, across
+/// # runs and processes. `sampling_salt` changes which records that keeps. Defaults to
+/// # `random`.
+/// sampling: deterministic
+/// sampling_salt: "2024-q3"
+///
+/// # Send an event counting the records dropped by rate limits, deduplication, sampling,
+/// # message filters, a full queue and the sentry client this often, e.g. `15m`. Optional.
+/// report_drops_every: 15m
+///
+/// # Send the first `first` events sharing a fingerprint in each `window`, which defaults to
+/// # `1m`, then only a `then_sample` share of them, reporting how many were seen so far as
+/// # `occurrence_count`. Optional.
+/// repeat_policy:
+///   first: 5
+///   then_sample: 0.01
+///   window: 10m
+///
+/// # Keep the last `capacity` lines passing the thresholds and filters, encoded with `encoder`
+/// # and including breadcrumbs, and attach them to events at `threshold`, which
+/// # defaults to `error`, or more severe: as the `recent_logs` extra, which is subject to
+/// # `max_extra_value_bytes`, or as a `recent_logs.txt` `attachment`. `capacity` defaults to
+/// # 100 and `attach_as` to `extra`. Optional.
+/// recent_logs:
+///   capacity: 200
+///   attach_as: extra
+///   threshold: error
+///
+/// # Warn about unknown keys instead of failing, so one configuration can be shared across
+/// # versions of this crate. Defaults to false.
+/// lenient_config: false
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct SentryAppenderDeserializer;
+
+impl Deserialize for SentryAppenderDeserializer {
+    type Trait = dyn Append;
+
+    type Config = SentryAppenderConfig;
+
+    fn deserialize(
+        &self,
+        config: SentryAppenderConfig,
+        deserializers: &Deserializers,
+    ) -> anyhow::Result<Box<dyn Append>> {
+        Ok(Box::new(
+            SentryAppenderBuilder::from_config(config, deserializers)?.build(),
+        ))
+    }
+}
+
+/// One error listing every problem with a configuration, if there are any.
+pub(crate) fn config_errors(problems: Vec<String>) -> anyhow::Result<()> {
+    match problems.len() {
+        0 => Ok(()),
+        1 => anyhow::bail!("invalid sentry appender configuration: {}", problems[0]),
+        _ => anyhow::bail!(
+            "invalid sentry appender configuration:\n  - {}",
+            problems.join("\n  - ")
+        ),
+    }
+}
@@ -0,0 +1,161 @@
+//! Fingerprint templates expanded per record, and the normalization of messages for them.
+
+use std::borrow::Cow;
+
+use log::Record;
+use regex::Regex;
+
+/// Sentry's placeholder for the fingerprint it would compute itself.
+const DEFAULT: &str = "{{ default }}";
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Target,
+    ModulePath,
+    Level,
+    Message,
+}
+
+/// A single fingerprint entry, e.g. `{target}:{level}`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Template(Vec<Part>);
+
+impl Template {
+    /// Parses an entry, failing on unknown or unterminated placeholders. `{{ default }}` is
+    /// kept as is for Sentry to expand.
+    pub(crate) fn parse(template: &str) -> anyhow::Result<Template> {
+        if template == DEFAULT {
+            return Ok(Template(vec![Part::Literal(DEFAULT.to_owned())]));
+        }
+
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+            let end = rest[start..].find('}').ok_or_else(|| {
+                anyhow::anyhow!("unterminated placeholder in fingerprint `{}`", template)
+            })?;
+            parts.push(match &rest[start + 1..start + end] {
+                "target" => Part::Target,
+                "module_path" => Part::ModulePath,
+                "level" => Part::Level,
+                "message" => Part::Message,
+                other => anyhow::bail!(
+                    "unknown placeholder `{{{}}}` in fingerprint `{}`",
+                    other,
+                    template
+                ),
+            });
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_owned()));
+        }
+        Ok(Template(parts))
+    }
+
+    /// Expands the placeholders for `record`, whose encoded message is `message`.
+    pub(crate) fn expand(&self, record: &Record, message: &str) -> String {
+        let mut expanded = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Literal(literal) => expanded.push_str(literal),
+                Part::Target => expanded.push_str(record.target()),
+                Part::ModulePath => expanded.push_str(record.module_path().unwrap_or_default()),
+                Part::Level => expanded.push_str(record.level().as_str()),
+                Part::Message => expanded.push_str(message),
+            }
+        }
+        expanded
+    }
+}
+
+/// Replaces the variable parts of messages, such as ids and numbers, with placeholders so
+/// messages which differ only in those parts share a fingerprint.
+#[derive(Clone, Debug)]
+pub(crate) struct Normalizer(Vec<(Regex, String)>);
+
+impl Default for Normalizer {
+    fn default() -> Normalizer {
+        let patterns = [
+            (r#""[^"]*"|'[^']*'"#, "<str>"),
+            (
+                r"(?i)\b[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}\b",
+                "<uuid>",
+            ),
+            (
+                r"(?i)\b(?:0x[0-9a-f]+|[0-9a-f]*[0-9][0-9a-f]*[a-f][0-9a-f]*|[0-9a-f]*[a-f][0-9a-f]*[0-9][0-9a-f]*)\b",
+                "<hex>",
+            ),
+            (r"\d+", "<int>"),
+        ];
+        Normalizer(
+            patterns
+                .iter()
+                .map(|&(pattern, replacement)| {
+                    (
+                        Regex::new(pattern).expect("invalid built-in pattern"),
+                        replacement.to_owned(),
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Normalizer {
+    pub(crate) fn new(patterns: Vec<(Regex, String)>) -> Normalizer {
+        Normalizer(patterns)
+    }
+
+    /// Applies each pattern in turn to `message`.
+    pub(crate) fn normalize<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        let mut message = Cow::Borrowed(message);
+        for (pattern, replacement) in &self.0 {
+            if let Cow::Owned(replaced) = pattern.replace_all(&message, replacement.as_str()) {
+                message = Cow::Owned(replaced);
+            }
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    fn expand(template: &str, message: &str) -> String {
+        Template::parse(template).unwrap().expand(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("app")
+                .module_path(Some("app::db"))
+                .build(),
+            message,
+        )
+    }
+
+    #[test]
+    fn placeholders_are_expanded() {
+        assert_eq!(
+            expand("{target}/{module_path}:{level} {message}!", "query failed"),
+            "app/app::db:WARN query failed!"
+        );
+        assert_eq!(expand("literal", "query failed"), "literal");
+    }
+
+    #[test]
+    fn default_is_passed_through() {
+        assert_eq!(expand("{{ default }}", "query failed"), "{{ default }}");
+    }
+
+    #[test]
+    fn invalid_placeholders_fail() {
+        assert!(Template::parse("{unknown}").is_err());
+        assert!(Template::parse("{target").is_err());
+    }
+}
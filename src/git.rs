@@ -0,0 +1,22 @@
+//! Git metadata compiled into the application by its build script.
+
+/// The commit and branch a build script such as `vergen` emitted as `VERGEN_GIT_SHA` and
+/// `VERGEN_GIT_BRANCH`. Created with `git_info!`, which reads them when the application is
+/// compiled; either is `None` if it wasn't emitted.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct GitInfo {
+    pub sha: Option<&'static str>,
+    pub branch: Option<&'static str>,
+}
+
+/// Reads the `VERGEN_GIT_SHA` and `VERGEN_GIT_BRANCH` variables of the crate it's used in into
+/// a `GitInfo`, for `SentryAppenderBuilder::release_from_vergen`.
+#[macro_export]
+macro_rules! git_info {
+    () => {
+        $crate::GitInfo {
+            sha: option_env!("VERGEN_GIT_SHA"),
+            branch: option_env!("VERGEN_GIT_BRANCH"),
+        }
+    };
+}
@@ -0,0 +1,69 @@
+//! Gzip compression of envelope bodies, as Sentry accepts with `Content-Encoding: gzip`.
+
+/// The gzip header: deflate, no flags, no modification time, unknown OS.
+const HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+
+/// Compresses `data` into a gzip member.
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    let deflated = miniz_oxide::deflate::compress_to_vec(data, 6);
+    let mut gzip = Vec::with_capacity(HEADER.len() + deflated.len() + 8);
+    gzip.extend_from_slice(&HEADER);
+    gzip.extend_from_slice(&deflated);
+    gzip.extend_from_slice(&crc32(data).to_le_bytes());
+    // The size is stored modulo 2^32.
+    gzip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    gzip
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[n] = crc;
+        n += 1;
+    }
+    table
+}
+
+/// The CRC-32 gzip checks its contents with.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn compress_round_trips() {
+        let data = b"{\"event_id\":\"00000000000000000000000000000000\"}\n".repeat(20);
+        let gzip = compress(&data);
+        assert_eq!(gzip[..HEADER.len()], HEADER);
+        let (deflated, trailer) = gzip[HEADER.len()..].split_at(gzip.len() - HEADER.len() - 8);
+        assert_eq!(
+            miniz_oxide::inflate::decompress_to_vec(deflated).unwrap(),
+            data
+        );
+        assert_eq!(trailer[..4], crc32(&data).to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+    }
+}
@@ -0,0 +1,197 @@
+//! Conversions between `log` key-values and sentry values.
+
+use log::{
+    kv::{self, Key, Source, ToValue, VisitSource, VisitValue},
+    Record,
+};
+use sentry::protocol::{
+    value::{Number, Value},
+    Exception, Level as SentryLevel, LogEntry, Map,
+};
+
+/// The key-value overriding the level a record is reported at, e.g. `sentry.level = "error"`.
+pub(crate) const LEVEL_KEY: &str = "sentry.level";
+
+/// Collects the key-values of a record, preserving numbers, booleans and strings.
+pub(crate) fn collect(record: &Record) -> Map<String, Value> {
+    struct Collect(Map<String, Value>);
+
+    impl<'kvs> VisitSource<'kvs> for Collect {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+            self.0.insert(key.as_str().to_owned(), to_value(&value));
+            Ok(())
+        }
+    }
+
+    let mut collect = Collect(Map::new());
+    let _ = record.key_values().visit(&mut collect);
+    collect.0
+}
+
+/// Converts a single `log` value, falling back to its `Display` output for complex values.
+pub(crate) fn to_value(value: &kv::Value) -> Value {
+    struct Convert(Value);
+
+    impl<'v> VisitValue<'v> for Convert {
+        fn visit_any(&mut self, value: kv::Value) -> Result<(), kv::Error> {
+            self.0 = Value::String(value.to_string());
+            Ok(())
+        }
+
+        fn visit_null(&mut self) -> Result<(), kv::Error> {
+            self.0 = Value::Null;
+            Ok(())
+        }
+
+        fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+            self.0 = Value::Number(value.into());
+            Ok(())
+        }
+
+        fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+            self.0 = Value::Number(value.into());
+            Ok(())
+        }
+
+        fn visit_f64(&mut self, value: f64) -> Result<(), kv::Error> {
+            self.0 = Number::from_f64(value).map_or(Value::Null, Value::Number);
+            Ok(())
+        }
+
+        fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+            self.0 = Value::Bool(value);
+            Ok(())
+        }
+
+        fn visit_str(&mut self, value: &str) -> Result<(), kv::Error> {
+            self.0 = Value::String(value.to_owned());
+            Ok(())
+        }
+    }
+
+    let mut convert = Convert(Value::Null);
+    let _ = value.visit(&mut convert);
+    convert.0
+}
+
+/// The level named by the record's `LEVEL_KEY` key-value: one of Sentry's levels, or `warn`.
+/// Anything else is ignored.
+pub(crate) fn level(record: &Record) -> Option<SentryLevel> {
+    let level = record
+        .key_values()
+        .get(Key::from(LEVEL_KEY))?
+        .to_string()
+        .to_ascii_lowercase();
+    match level.as_str() {
+        "warn" => Some(SentryLevel::Warning),
+        level => level.parse().ok(),
+    }
+}
+
+/// Builds a log entry from the template in the key-value `template_key`, taking the record's
+/// other key-values, in order and except for `err`, `LEVEL_KEY` and `fingerprint_key`, as its
+/// parameters.
+pub(crate) fn log_entry(
+    record: &Record,
+    template_key: &str,
+    fingerprint_key: &str,
+) -> Option<LogEntry> {
+    struct Params<'a> {
+        skip: [&'a str; 4],
+        params: Vec<Value>,
+    }
+
+    impl<'kvs> VisitSource<'kvs> for Params<'_> {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+            if !self.skip.contains(&key.as_str()) {
+                self.params.push(to_value(&value));
+            }
+            Ok(())
+        }
+    }
+
+    let message = record
+        .key_values()
+        .get(Key::from(template_key))?
+        .to_string();
+    let mut params = Params {
+        skip: [template_key, fingerprint_key, LEVEL_KEY, "err"],
+        params: Vec::new(),
+    };
+    let _ = record.key_values().visit(&mut params);
+    Some(LogEntry {
+        message,
+        params: params.params,
+    })
+}
+
+/// Builds an exception chain, innermost cause first, from the record's `err` key-value.
+///
+/// An error logged as one (`error!(err:err = e; ...)`) is walked through its `source()`s,
+/// each exception typed from that error. Any other value becomes a single exception typed
+/// from its `Debug` output.
+pub(crate) fn error_chain(record: &Record) -> Option<Vec<Exception>> {
+    let err = record.key_values().get(Key::from("err"))?;
+    if let Some(err) = err.to_borrowed_error() {
+        return Some(sentry::event_from_error(err).exception.values);
+    }
+    let rendered = err.to_string();
+    Some(vec![Exception {
+        ty: sentry::parse_type_from_debug(&rendered).to_owned(),
+        value: Some(rendered),
+        ..Default::default()
+    }])
+}
+
+/// Owned key-values which can be handed to another thread and turned back into a `Source`.
+#[derive(Debug, Default)]
+pub(crate) struct OwnedKeyValues(Vec<(String, OwnedValue)>);
+
+impl OwnedKeyValues {
+    /// Copies the record's key-values, keeping them in the order they were logged.
+    pub(crate) fn new(record: &Record) -> OwnedKeyValues {
+        struct Copy(Vec<(String, OwnedValue)>);
+
+        impl<'kvs> VisitSource<'kvs> for Copy {
+            fn visit_pair(
+                &mut self,
+                key: Key<'kvs>,
+                value: kv::Value<'kvs>,
+            ) -> Result<(), kv::Error> {
+                self.0
+                    .push((key.as_str().to_owned(), OwnedValue(to_value(&value))));
+                Ok(())
+            }
+        }
+
+        let mut copy = Copy(Vec::new());
+        let _ = record.key_values().visit(&mut copy);
+        OwnedKeyValues(copy.0)
+    }
+}
+
+impl Source for OwnedKeyValues {
+    fn visit<'kvs>(&'kvs self, visitor: &mut dyn VisitSource<'kvs>) -> Result<(), kv::Error> {
+        self.0.visit(visitor)
+    }
+}
+
+#[derive(Debug)]
+struct OwnedValue(Value);
+
+impl ToValue for OwnedValue {
+    fn to_value(&self) -> kv::Value<'_> {
+        match self.0 {
+            Value::Null => kv::Value::null(),
+            Value::Bool(b) => kv::Value::from(b),
+            Value::Number(ref n) => match (n.as_u64(), n.as_i64(), n.as_f64()) {
+                (Some(n), _, _) => kv::Value::from(n),
+                (None, Some(n), _) => kv::Value::from(n),
+                (None, None, Some(n)) => kv::Value::from(n),
+                _ => kv::Value::null(),
+            },
+            Value::String(ref s) => kv::Value::from(s.as_str()),
+            ref value => kv::Value::from_display(value),
+        }
+    }
+}
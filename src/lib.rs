@@ -30,6 +30,23 @@
 //!     - sentry
 //! ```
 //!
+//! One appender both captures events and records breadcrumbs: records at `threshold` or more
+//! severe become events, and those between it and `breadcrumb_threshold` become breadcrumbs
+//! attached to the next event.
+//! ```yaml
+//! appenders:
+//!   sentry:
+//!     kind: sentry
+//!     dsn: "YOUR_SENTRY_DSN_HERE"
+//!     threshold: error
+//!     breadcrumb_threshold: debug
+//!
+//! root:
+//!   level: debug
+//!   appenders:
+//!     - sentry
+//! ```
+//!
 //! You can also constructing the configuration programmatically without using a config file:
 //!
 //! ```no_run
@@ -67,190 +84,452 @@ extern crate sentry;
 
 use derivative::Derivative;
 use log::{Level, LevelFilter, Record};
-use log4rs::{
-    append::Append,
-    config::{Deserialize, Deserializers},
-    encode::{pattern::PatternEncoder, writer::simple::SimpleWriter, Encode, EncoderConfig},
-};
+use log4rs::{append::Append, config::Deserializers, encode::Encode};
+use regex::Regex;
 use sentry::{
-    protocol::value::{Number, Value},
-    ClientInitGuard, Level as SentryLevel,
+    protocol::{value::Value, Context, Exception, Map, Stacktrace},
+    types::Uuid,
+    Client, ClientInitGuard, Hub,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 
-/// Configuration for the sentry appender.
-#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize)]
-#[serde(deny_unknown_fields)]
-pub struct SentryAppenderConfig {
-    dsn: String,
-    encoder: Option<EncoderConfig>,
-    threshold: LevelFilter,
+mod breadcrumb;
+mod builder;
+mod capture;
+mod clock;
+mod config;
+mod fingerprint;
+mod git;
+mod gzip;
+mod kv;
+mod options;
+mod periodic;
+mod ratelimit;
+mod recent;
+mod scrub;
+mod spool;
+mod stats;
+mod suppress;
+mod throttle;
+mod transaction;
+mod transport;
+mod worker;
+
+#[cfg(test)]
+pub(crate) mod tests;
+
+pub use breadcrumb::{
+    SentryBreadcrumbAppender, SentryBreadcrumbAppenderBuilder, SentryBreadcrumbAppenderConfig,
+    SentryBreadcrumbAppenderDeserializer,
+};
+pub use builder::SentryAppenderBuilder;
+pub use clock::{Clock, SystemClock};
+pub use config::{
+    AsyncConfig, NormalizePattern, RecentLogs, RepeatPolicy, RetryPolicy, Route,
+    SentryAppenderConfig, SentryAppenderDeserializer,
+};
+pub use git::GitInfo;
+pub use options::{
+    CulpritSource, EncodingErrorPolicy, LoggerSource, MessageFormat, MultilineMode, SampleRate,
+    Sampling, TimestampSource, TransactionSource,
+};
+pub use recent::AttachAs;
+pub use scrub::ScrubPreset;
+pub use stats::Stats;
+pub use suppress::{
+    RateLimitFilter, RateLimitFilterBuilder, RateLimitFilterConfig, RateLimitFilterDeserializer,
+};
+pub use transaction::TransactionGuard;
+pub use worker::QueueFullPolicy;
+
+thread_local! {
+    static LAST_EVENT_ID: Cell<Option<Uuid>> = const { Cell::new(None) };
+    static TRANSACTION: RefCell<Option<String>> = const { RefCell::new(None) };
+    static ENCODE_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns the id of the last event a sentry appender captured on the current thread.
+///
+/// Records dropped by the appender's threshold, or discarded by the sentry client, don't
+/// update the id. In async mode events are captured on the worker thread, so the logging
+/// thread never sees an id.
+pub fn last_event_id() -> Option<Uuid> {
+    LAST_EVENT_ID.with(Cell::get)
 }
 
 /// An appender which send log message to sentry.
+///
+/// A record's `sentry.level` key-value, e.g. `warn!("sentry.level" = "error"; ...)`, overrides
+/// the level it's reported at. It accepts Sentry's levels: `debug`, `info`, `warning`, `error`
+/// and `fatal`.
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct SentryAppender {
+    // Declared before `_sentry` so that the queue is drained before the client shuts down.
+    worker: Option<worker::Worker>,
+    flusher: Option<periodic::Periodic>,
+    drop_reporter: Option<periodic::Periodic>,
+    // The hub of the session started for `auto_session_tracking`.
     #[derivative(Debug = "ignore")]
-    _sentry: ClientInitGuard,
-    encoder: Box<dyn Encode>,
+    session: Option<Arc<Hub>>,
+    #[derivative(Debug = "ignore")]
+    _routes: RouteClients,
+    #[derivative(Debug = "ignore")]
+    _sentry: Option<ClientInitGuard>,
+    inner: Arc<Inner>,
+    disabled: bool,
     threshold: LevelFilter,
+    breadcrumb_threshold: LevelFilter,
+    target_thresholds: BTreeMap<String, LevelFilter>,
+    send_trace: bool,
+    include_targets: Vec<String>,
+    exclude_targets: Vec<String>,
+    sampler: suppress::Sampler,
+    rate_limiter: Option<ratelimit::RateLimiter>,
+    #[derivative(Debug = "ignore")]
+    filter: Option<RecordFilter>,
+}
+
+/// The clients created for `routes`, closed when the appender is dropped.
+struct RouteClients(Vec<Arc<Client>>);
+
+impl Drop for RouteClients {
+    fn drop(&mut self) {
+        for client in &self.0 {
+            client.close(None);
+        }
+    }
+}
+
+/// Whether `path` is `module` or within it, e.g. `sqlx::query` is within `sqlx` but `sqlxx` isn't.
+fn in_module(path: &str, module: &str) -> bool {
+    path.strip_prefix(module)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+/// How long each flush waits for the worker and the transport at most, including the one when
+/// the appender is dropped.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+type RecordFilter = Box<dyn Fn(&Record) -> bool + Send + Sync>;
+
+type RecordProcessor = Box<dyn Fn(&Record, &mut sentry::protocol::Event<'static>) + Send + Sync>;
+
+/// The part of the appender which turns records into sentry events, shared with the worker thread.
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct Inner {
+    #[derivative(Debug = "ignore")]
+    hub: Option<Arc<Hub>>,
+    encoder: Box<dyn Encode>,
+    fingerprint_encoder: Option<Box<dyn Encode>>,
+    breadcrumb_encoder: Option<Box<dyn Encode>>,
+    breadcrumb_format: breadcrumb::BreadcrumbFormat,
+    ansi_escapes: Option<Regex>,
+    contexts: Map<String, Context>,
+    redactor: Arc<scrub::Redactor>,
+    location_extras: bool,
+    include_kv: bool,
+    report_as_exception: bool,
+    exception_type: Option<String>,
+    backtrace_levels: Vec<Level>,
+    process_info: Option<Value>,
+    transaction_from: Option<TransactionSource>,
+    culprit_source: Option<CulpritSource>,
+    logger_source: LoggerSource,
+    platform: String,
+    dist: Option<String>,
+    level_tag: Option<String>,
+    target_as_tag: bool,
+    tags: Map<String, String>,
+    in_app_include: Vec<&'static str>,
+    in_app_exclude: Vec<&'static str>,
+    #[cfg(feature = "tracing")]
+    tracing_span: bool,
+    timestamp: TimestampSource,
+    culprit_from_location: bool,
+    include_location: bool,
+    logger_map: BTreeMap<String, String>,
+    attach_files: Vec<PathBuf>,
+    max_attachment_size: u64,
+    template_key: Option<String>,
+    fingerprint: Vec<fingerprint::Template>,
+    fingerprint_key: String,
+    normalizer: Option<fingerprint::Normalizer>,
+    message_prefix: Option<String>,
+    message_suffix: Option<String>,
+    multiline: MultilineMode,
+    message_format: MessageFormat,
+    skip_empty_messages: bool,
+    max_message_bytes: usize,
+    encoding_error_policy: EncodingErrorPolicy,
+    extra: Map<String, Value>,
+    max_extra_entries: usize,
+    max_extra_value_bytes: usize,
+    normalize_depth: Option<usize>,
+    max_value_length: Option<usize>,
+    routes: Vec<(Level, Level, Arc<Hub>)>,
+    ignore_messages: Vec<Regex>,
+    only_messages: Vec<Regex>,
+    drop_if_contains: Vec<String>,
+    drop_if_contains_ignore_case: bool,
+    fingerprint_throttle: Option<throttle::Throttle>,
+    dedup: Option<throttle::Throttle>,
+    repeat_policy: Option<(throttle::Throttle, u64, SampleRate)>,
+    recent_lines: Option<recent::RecentLines>,
+    #[derivative(Debug = "ignore")]
+    record_processor: Option<RecordProcessor>,
+    stats: Arc<stats::Counters>,
 }
 
 impl SentryAppender {
     /// Creates a new `SentryAppender` builder.
     pub fn builder() -> SentryAppenderBuilder {
-        SentryAppenderBuilder {
-            encoder: None,
-            dsn: String::default(),
-            threshold: None,
+        SentryAppenderBuilder::new()
+    }
+
+    /// Creates a new `SentryAppender` builder which captures events into `hub`.
+    ///
+    /// See `SentryAppenderBuilder::hub`.
+    pub fn with_hub(hub: Arc<Hub>) -> SentryAppenderBuilder {
+        SentryAppender::builder().hub(hub)
+    }
+
+    /// Sets the transaction, e.g. `POST /checkout`, of the events captured for records logged on
+    /// the current thread, taking precedence over `transaction_from`. Also sets it on the
+    /// current hub's scope, so other events captured on this thread carry it too. Pass `None`
+    /// once the operation is over to clear it.
+    pub fn set_transaction(transaction: Option<String>) {
+        Hub::current().configure_scope(|scope| scope.set_transaction(transaction.as_deref()));
+        TRANSACTION.with(|current| *current.borrow_mut() = transaction);
+    }
+
+    /// Starts a performance transaction named `name`, e.g. `POST /checkout`, with the operation
+    /// `op`, e.g. `http.server`, on the current hub. Until the returned guard is dropped, events
+    /// captured for records logged on the current thread are associated with it.
+    ///
+    /// Transactions are only sent if `traces_sample_rate` is set.
+    pub fn start_transaction(name: &str, op: &str) -> TransactionGuard {
+        TransactionGuard::start(name, op)
+    }
+
+    /// Waits up to `timeout` for queued records to be captured and for the clients to send
+    /// their events, e.g. before the process exits. Returns false if that took longer, in which
+    /// case events may be lost. The clients are left open, but the `auto_session_tracking`
+    /// session is ended.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        if let Some(ref worker) = self.worker {
+            if !worker.flush_timeout(timeout) {
+                return false;
+            }
         }
+        if let Some(ref hub) = self.session {
+            hub.end_session();
+        }
+        let mut flushed = true;
+        for client in self.inner.clients() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            flushed &= client.flush(Some(remaining));
+        }
+        flushed
+    }
+
+    /// Runs `callback` in a temporary scope of the appender's hub, configured by
+    /// `scope_config`, e.g. to tag the events of records logged within it. Scopes nest: an
+    /// inner scope starts from the outer one.
+    ///
+    /// In async mode records are captured on the worker thread, which doesn't see the scope.
+    pub fn with_scope<C, F, R>(&self, scope_config: C, callback: F) -> R
+    where
+        C: FnOnce(&mut sentry::Scope),
+        F: FnOnce() -> R,
+    {
+        self.inner.hub().with_scope(scope_config, callback)
+    }
+
+    /// Returns the client events are captured with, e.g. to capture events of your own on it,
+    /// or `None` if it or the appender is disabled, such as when the DSN is empty.
+    pub fn client(&self) -> Option<Arc<Client>> {
+        if self.disabled {
+            return None;
+        }
+        self.inner
+            .hub()
+            .client()
+            .filter(|client| client.is_enabled())
+    }
+
+    /// Returns the number of records waiting for the background worker, always 0 unless
+    /// `async_queue` is set.
+    pub fn queue_depth(&self) -> usize {
+        self.worker.as_ref().map_or(0, worker::Worker::depth)
+    }
+
+    /// Returns a snapshot of the appender's counters.
+    pub fn stats(&self) -> Stats {
+        self.inner.stats.snapshot()
+    }
+
+    /// The threshold of the longest `target_thresholds` prefix matching `target`, or the
+    /// appender's.
+    fn threshold_for(&self, target: &str) -> LevelFilter {
+        self.target_thresholds
+            .iter()
+            .filter(|(prefix, _)| in_module(target, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.threshold, |(_, &threshold)| threshold)
+    }
+
+    /// Whether `target` passes `exclude_targets` and `include_targets`.
+    fn target_allowed(&self, target: &str) -> bool {
+        let matches = |targets: &[String]| targets.iter().any(|prefix| in_module(target, prefix));
+        !matches(&self.exclude_targets)
+            && (self.include_targets.is_empty() || matches(&self.include_targets))
     }
 
     /// Creates a `Deserializers` with sentry appender mapping and the default log4rs mappings.
     ///  * Appenders
     ///     * "sentry" -> `SentryAppenderDeserializer`
+    ///     * "sentry_breadcrumb" -> `SentryBreadcrumbAppenderDeserializer`
+    ///  * Filters
+    ///     * "sentry_rate_limit" -> `RateLimitFilterDeserializer`
     ///  * log4rs default mappings.
     pub fn deserializers() -> Deserializers {
         let mut deserializers = Deserializers::new();
         deserializers.insert("sentry", SentryAppenderDeserializer);
+        deserializers.insert("sentry_breadcrumb", SentryBreadcrumbAppenderDeserializer);
+        deserializers.insert("sentry_rate_limit", RateLimitFilterDeserializer);
         deserializers
     }
 }
 
 impl Append for SentryAppender {
     fn append(&self, record: &Record) -> anyhow::Result<()> {
-        if record.level() > self.threshold {
-            // Don't send records to sentry if record's level greater than the user defined threshold.
-            // e.g. Info > Error
+        if self.disabled {
             return Ok(());
         }
 
-        let level = level_mapping(record.level());
-
-        let mut buf: Vec<u8> = Vec::new();
-        self.encoder.encode(&mut SimpleWriter(&mut buf), record)?;
-        let msg = String::from_utf8(buf)?;
-
-        let mut event = sentry::protocol::Event::new();
-        event.level = level;
-        event.message = Some(msg);
-        event.logger = Some(record.metadata().target().to_owned());
+        let now = SystemTime::now();
 
-        if let Some(file) = record.file() {
-            event
-                .extra
-                .insert("file".to_owned(), Value::String(file.to_owned()));
+        let threshold = self.threshold_for(record.target());
+        if threshold == LevelFilter::Off {
+            // An `off` target is silenced entirely, not just kept out of events.
+            self.inner.stats.dropped_threshold();
+            return Ok(());
+        }
+        let breadcrumb = record.level() > threshold;
+        if breadcrumb && record.level() > self.breadcrumb_threshold {
+            // Don't send records to sentry if record's level greater than the user defined threshold.
+            // e.g. Info > Error
+            self.inner.stats.dropped_threshold();
+            return Ok(());
         }
 
-        if let Some(line) = record.line() {
-            event
-                .extra
-                .insert("line".to_owned(), Value::Number(Number::from(line)));
+        if !breadcrumb && record.level() == Level::Trace && !self.send_trace {
+            self.inner.stats.dropped_threshold();
+            return Ok(());
         }
 
-        if let Some(module_path) = record.module_path() {
-            event
-                .tags
-                .insert("module_path".to_owned(), module_path.to_owned());
+        if !self.target_allowed(record.target()) {
+            self.inner.stats.dropped_filtered();
+            return Ok(());
         }
 
-        sentry::capture_event(event);
-        Ok(())
-    }
+        if let Some(ref filter) = self.filter {
+            if !filter(record) {
+                self.inner.stats.dropped_filtered();
+                return Ok(());
+            }
+        }
 
-    fn flush(&self) {}
-}
+        let recent_logs = self.inner.remember(record);
 
-/// A builder for `SentryAppender`s.
-pub struct SentryAppenderBuilder {
-    encoder: Option<Box<dyn Encode>>,
-    dsn: String,
-    threshold: Option<LevelFilter>,
-}
+        if breadcrumb {
+            let mut snapshot = self.inner.snapshot(record, now);
+            snapshot.breadcrumb = true;
+            return match self.worker {
+                Some(ref worker) => {
+                    if worker.push(record, snapshot) {
+                        self.inner.stats.dropped_queue_full();
+                    }
+                    Ok(())
+                }
+                None => self.inner.capture(record, snapshot),
+            };
+        }
 
-impl SentryAppenderBuilder {
-    pub fn encoder(mut self, encoder: Box<dyn Encode>) -> SentryAppenderBuilder {
-        self.encoder = Some(encoder);
-        self
-    }
+        if !self.sampler.keep(record) {
+            self.inner.stats.dropped_sampled();
+            return Ok(());
+        }
 
-    pub fn dsn(mut self, dsn: &str) -> SentryAppenderBuilder {
-        self.dsn = dsn.to_string();
-        self
-    }
+        let rate_limited_dropped = match self.rate_limiter {
+            Some(ref rate_limiter) => match rate_limiter.acquire() {
+                Some(dropped) => dropped,
+                None => {
+                    self.inner.stats.dropped_ratelimit();
+                    return Ok(());
+                }
+            },
+            None => 0,
+        };
 
-    fn dsn_string(mut self, dsn: String) -> SentryAppenderBuilder {
-        self.dsn = dsn;
-        self
-    }
+        let mut snapshot = self.inner.snapshot(record, now);
+        snapshot.rate_limited_dropped = rate_limited_dropped;
+        snapshot.recent_logs = recent_logs;
 
-    pub fn threshold(mut self, threshold: LevelFilter) -> SentryAppenderBuilder {
-        self.threshold = Some(threshold);
-        self
+        match self.worker {
+            Some(ref worker) => {
+                if worker.push(record, snapshot) {
+                    self.inner.stats.dropped_queue_full();
+                }
+                Ok(())
+            }
+            None => self.inner.capture(record, snapshot),
+        }
     }
 
-    pub fn build(self) -> SentryAppender {
-        let _sentry: ClientInitGuard = sentry::init(self.dsn);
-        SentryAppender {
-            _sentry,
-            encoder: self
-                .encoder
-                .unwrap_or_else(|| Box::new(PatternEncoder::new("{m}"))),
-            threshold: self.threshold.unwrap_or(LevelFilter::Error),
+    fn flush(&self) {
+        if let Some(ref worker) = self.worker {
+            worker.flush_timeout(FLUSH_TIMEOUT);
+        }
+        for client in self.inner.clients() {
+            client.flush(None);
         }
     }
 }
 
-/// A deserializer for the `SentryAppender`.
-///
-/// # Configuration
-///
-/// ```yaml
-/// kind: sentry
-///
-/// # The sentry DSN, e.g. "https://key@sentry.io/42"
-/// dsn: "YOUR_DSN_HERE"
-///
-/// # The log level threshold
-/// threshold: error  # overriding the logging threshold to the ERROR level
-///
-/// # The encoder to use to format output. Defaults to `kind: pattern`.
-/// encoder:
-///   kind: pattern
-/// ```
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
-pub struct SentryAppenderDeserializer;
-
-impl Deserialize for SentryAppenderDeserializer {
-    type Trait = dyn Append;
-
-    type Config = SentryAppenderConfig;
-
-    fn deserialize(
-        &self,
-        config: SentryAppenderConfig,
-        deserializers: &Deserializers,
-    ) -> anyhow::Result<Box<dyn Append>> {
-        let mut appender = SentryAppender::builder();
-
-        if let Some(encoder) = config.encoder {
-            appender = appender.encoder(deserializers.deserialize(&encoder.kind, encoder.config)?);
+impl Drop for SentryAppender {
+    fn drop(&mut self) {
+        // Send what's pending now rather than at the next `flush_interval`, if ever.
+        if !self.disabled {
+            self.shutdown(FLUSH_TIMEOUT);
         }
-
-        appender = appender.dsn_string(config.dsn);
-
-        appender = appender.threshold(config.threshold);
-
-        Ok(Box::new(appender.build()))
     }
 }
 
-fn level_mapping(level: Level) -> SentryLevel {
-    match level {
-        Level::Error => SentryLevel::Error,
-        Level::Warn => SentryLevel::Warning,
-        Level::Info => SentryLevel::Info,
-        Level::Debug => SentryLevel::Debug,
-        Level::Trace => SentryLevel::Debug,
-    }
+/// The parts of an event which have to be taken on the logging thread, even in async mode.
+#[derive(Debug, Default)]
+struct Snapshot {
+    backtrace: Option<Stacktrace>,
+    transaction: Option<String>,
+    span: Option<Value>,
+    timestamp: Option<SystemTime>,
+    // Records dropped by the rate limiter before this one.
+    rate_limited_dropped: u64,
+    // Whether the record is added as a breadcrumb rather than captured.
+    breadcrumb: bool,
+    // The recent lines to attach to the event.
+    recent_logs: Option<Vec<String>>,
+    // The chain of the record's `err` key-value, which doesn't survive being queued.
+    exceptions: Option<Vec<Exception>>,
+    // The record's message when it's a literal, which queued records no longer know.
+    template: Option<&'static str>,
 }
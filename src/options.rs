@@ -0,0 +1,271 @@
+//! Types of the options the sentry appender is configured with.
+
+use log::Record;
+use std::{convert::TryFrom, str::FromStr, thread};
+
+/// What the appender populates `event.transaction` from when nothing else sets it.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CulpritSource {
+    /// The record's module path, falling back to the target if it has none.
+    ModulePath,
+    /// The record's `file:line`, if it has a file.
+    #[serde(rename = "file:line", alias = "file_line")]
+    FileLine,
+    /// A fixed culprit.
+    Fixed(String),
+}
+
+impl CulpritSource {
+    pub(crate) fn culprit(&self, record: &Record) -> Option<String> {
+        match self {
+            CulpritSource::ModulePath => Some(
+                record
+                    .module_path()
+                    .unwrap_or_else(|| record.target())
+                    .to_owned(),
+            ),
+            CulpritSource::FileLine => record.file().map(|file| match record.line() {
+                Some(line) => format!("{}:{}", file, line),
+                None => file.to_owned(),
+            }),
+            CulpritSource::Fixed(culprit) => Some(culprit.clone()),
+        }
+    }
+}
+
+/// What the appender populates `event.logger` from.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoggerSource {
+    /// The record's target.
+    #[default]
+    Target,
+    /// The record's module path, falling back to the target if it has none.
+    ModulePath,
+    /// A fixed logger name.
+    Fixed(String),
+}
+
+impl LoggerSource {
+    pub(crate) fn logger<'a>(&'a self, record: &Record<'a>) -> &'a str {
+        match self {
+            LoggerSource::Target => record.target(),
+            LoggerSource::ModulePath => record.module_path().unwrap_or_else(|| record.target()),
+            LoggerSource::Fixed(name) => name,
+        }
+    }
+}
+
+/// Which moment the appender records as the event's timestamp.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Hash, Debug, Default, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampSource {
+    /// When the record was appended, before any encoding or queueing.
+    #[default]
+    Append,
+    /// When the event was built, which lags behind in async mode.
+    Transport,
+}
+
+/// How the appender reports messages spanning several lines.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Hash, Debug, Default, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MultilineMode {
+    /// The message as it is.
+    #[default]
+    Raw,
+    /// The first line as the message, and the full text as the `full_message` extra.
+    Split,
+    /// The message on a single line, with newlines escaped as `\n`.
+    Join,
+}
+
+impl MultilineMode {
+    /// Returns the message to report and, in `Split` mode, the full text if it was split.
+    /// Trailing newlines are dropped in `Split` and `Join` mode.
+    pub(crate) fn apply(self, msg: String) -> (String, Option<String>) {
+        let trimmed = || msg.trim_end_matches(['\r', '\n']);
+        match self {
+            MultilineMode::Raw => (msg, None),
+            MultilineMode::Split => {
+                let trimmed = trimmed();
+                match trimmed.lines().next() {
+                    Some(first) if first.len() < trimmed.len() => {
+                        (first.to_owned(), Some(trimmed.to_owned()))
+                    }
+                    _ => (trimmed.to_owned(), None),
+                }
+            }
+            MultilineMode::Join => (trimmed().replace("\r\n", "\\n").replace('\n', "\\n"), None),
+        }
+    }
+}
+
+/// A probability between 0 and 1, such as a sample rate.
+#[derive(Copy, Clone, PartialEq, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "f32", into = "f32")]
+pub struct SampleRate(f32);
+
+impl SampleRate {
+    /// Returns the rate as a number between 0 and 1.
+    pub fn get(self) -> f32 {
+        self.0
+    }
+}
+
+impl TryFrom<f32> for SampleRate {
+    type Error = anyhow::Error;
+
+    fn try_from(rate: f32) -> anyhow::Result<SampleRate> {
+        if (0.0..=1.0).contains(&rate) {
+            // Turns -0.0 into 0.0, keeping `Hash` consistent with `Eq`.
+            Ok(SampleRate(rate.abs()))
+        } else {
+            Err(anyhow::anyhow!(
+                "sample rate {} is not between 0 and 1",
+                rate
+            ))
+        }
+    }
+}
+
+impl From<SampleRate> for f32 {
+    fn from(rate: SampleRate) -> f32 {
+        rate.0
+    }
+}
+
+// Rates are never NaN, so equality is reflexive.
+impl Eq for SampleRate {}
+
+impl std::hash::Hash for SampleRate {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+/// A sample rate as read from a config file, checked by `from_config` so that a rate out of
+/// range is reported along with the configuration's other problems.
+#[derive(Copy, Clone, PartialEq, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(transparent)]
+pub(crate) struct UncheckedSampleRate(f32);
+
+impl UncheckedSampleRate {
+    /// The rate, or 0 after recording why the rate named `name` is invalid in `problems`.
+    pub(crate) fn check(self, name: &str, problems: &mut Vec<String>) -> SampleRate {
+        SampleRate::try_from(self.0).unwrap_or_else(|err| {
+            problems.push(format!("invalid `{}`: {}", name, err));
+            SampleRate::default()
+        })
+    }
+}
+
+// Config structs compare rates bitwise, so even a NaN rate equals itself.
+impl Eq for UncheckedSampleRate {}
+
+impl std::hash::Hash for UncheckedSampleRate {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+/// How records are picked by per-level sample rates.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Hash, Debug, Default, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Sampling {
+    /// Pick records at random.
+    #[default]
+    Random,
+    /// Pick records by a hash of their target and message, so identical records are always
+    /// kept or always dropped, in every process.
+    Deterministic,
+}
+
+/// What the appender does when the encoder's output isn't valid UTF-8.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Hash, Debug, Default, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum EncodingErrorPolicy {
+    /// Fail the append, losing the event.
+    #[serde(alias = "strict")]
+    Fail,
+    /// Replace invalid sequences with U+FFFD.
+    #[default]
+    Lossy,
+    /// Replace invalid sequences with the given character.
+    Replace(char),
+}
+
+/// The format of the encoder's output.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Hash, Debug, Default, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageFormat {
+    /// Plain text, used as the message.
+    #[default]
+    Text,
+    /// A JSON object, such as the output of log4rs's JSON encoder.
+    Json,
+}
+
+/// Where the appender reads `event.transaction` from.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TransactionSource {
+    /// The name of the thread logging the record.
+    ThreadName,
+    /// The MDC entry with the given key, as set with `log_mdc::insert`.
+    Mdc(String),
+}
+
+impl TransactionSource {
+    pub(crate) fn get(&self) -> Option<String> {
+        match self {
+            TransactionSource::ThreadName => thread::current().name().map(str::to_owned),
+            TransactionSource::Mdc(key) => log_mdc::get(key, |value| value.map(str::to_owned)),
+        }
+    }
+}
+
+impl FromStr for TransactionSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<TransactionSource> {
+        match s {
+            "thread_name" => Ok(TransactionSource::ThreadName),
+            _ => match s.strip_prefix("mdc:") {
+                Some(key) if !key.is_empty() => Ok(TransactionSource::Mdc(key.to_owned())),
+                _ => Err(anyhow::anyhow!(
+                    "invalid transaction source `{}`, expected `thread_name` or `mdc:<key>`",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl From<TransactionSource> for String {
+    fn from(source: TransactionSource) -> String {
+        match source {
+            TransactionSource::ThreadName => "thread_name".to_owned(),
+            TransactionSource::Mdc(key) => format!("mdc:{}", key),
+        }
+    }
+}
+
+impl TryFrom<String> for TransactionSource {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<TransactionSource> {
+        s.parse()
+    }
+}
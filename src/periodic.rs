@@ -0,0 +1,110 @@
+//! Background threads running a task periodically, such as flushing the sentry client.
+
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+struct Shared {
+    stopped: Mutex<bool>,
+    stop: Condvar,
+}
+
+/// Runs a task every `interval` on a thread of its own until dropped.
+pub(crate) struct Periodic {
+    shared: Arc<Shared>,
+    interval: Duration,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for Periodic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Periodic")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl Periodic {
+    pub(crate) fn spawn<F>(name: &str, interval: Duration, task: F) -> Periodic
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            stopped: Mutex::new(false),
+            stop: Condvar::new(),
+        });
+
+        let handle = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(name.to_owned())
+                .spawn(move || run(&shared, interval, task))
+                .unwrap_or_else(|e| panic!("failed to spawn the {} thread: {}", name, e))
+        };
+
+        Periodic {
+            shared,
+            interval,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Periodic {
+    fn drop(&mut self) {
+        *self
+            .shared
+            .stopped
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = true;
+        self.shared.stop.notify_one();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run<F: FnMut()>(shared: &Shared, interval: Duration, mut task: F) {
+    let mut next = Instant::now() + interval;
+    let mut stopped = shared.stopped.lock().unwrap_or_else(|e| e.into_inner());
+    loop {
+        // Checked before waiting too, as a stop signalled while the task ran is otherwise missed.
+        if *stopped {
+            return;
+        }
+        let now = Instant::now();
+        if now < next {
+            stopped = shared
+                .stop
+                .wait_timeout(stopped, next - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+            continue;
+        }
+
+        drop(stopped);
+        task();
+        next = Instant::now() + interval;
+        stopped = shared.stopped.lock().unwrap_or_else(|e| e.into_inner());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_stops_the_thread_without_waiting_for_the_interval() {
+        for _ in 0..100 {
+            let started = Instant::now();
+            drop(Periodic::spawn(
+                "periodic-test",
+                Duration::from_secs(3600),
+                || {},
+            ));
+            assert!(started.elapsed() < Duration::from_secs(60));
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! The token bucket limiting how many events the appender sends.
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Instant,
+};
+
+use crate::clock::Clock;
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    // Records dropped since the last one let through.
+    dropped: u64,
+}
+
+/// A token bucket refilled at `per_minute` tokens a minute, holding up to `burst` of them.
+pub(crate) struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    clock: Arc<dyn Clock>,
+    bucket: Mutex<Bucket>,
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("per_minute", &(self.per_second * 60.0))
+            .field("burst", &self.burst)
+            .finish()
+    }
+}
+
+impl RateLimiter {
+    /// The bucket starts full.
+    pub(crate) fn new(per_minute: u32, burst: u32, clock: Arc<dyn Clock>) -> RateLimiter {
+        let burst = f64::from(burst.max(1));
+        let refilled = clock.now();
+        RateLimiter {
+            per_second: f64::from(per_minute) / 60.0,
+            burst,
+            clock,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                refilled,
+                dropped: 0,
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Bucket> {
+        self.bucket.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes a token for a record. Returns the number of records dropped since the last one
+    /// let through, or `None` if there's no token left and this one has to be dropped too.
+    pub(crate) fn acquire(&self) -> Option<u64> {
+        let mut bucket = self.lock();
+        let now = self.clock.now();
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.refilled = now;
+
+        if bucket.tokens < 1.0 {
+            bucket.dropped += 1;
+            return None;
+        }
+        bucket.tokens -= 1.0;
+        Some(std::mem::take(&mut bucket.dropped))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn tokens_refill_over_time() {
+        let clock = Arc::new(MockClock::new());
+        let limiter = RateLimiter::new(60, 1, clock.clone());
+        assert_eq!(limiter.acquire(), Some(0));
+        assert_eq!(limiter.acquire(), None);
+
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(limiter.acquire(), None);
+
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(limiter.acquire(), Some(2));
+    }
+}
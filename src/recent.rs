@@ -0,0 +1,85 @@
+//! The buffer of recently logged lines attached to events.
+
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+};
+
+use log::{Level, LevelFilter};
+
+/// How the recent lines are attached to events.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Hash, Debug, Default, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachAs {
+    /// As the `recent_logs` extra, a list of lines.
+    #[default]
+    Extra,
+    /// As a `recent_logs.txt` attachment.
+    Attachment,
+}
+
+/// The last `capacity` lines logged, oldest first, and the events they're attached to.
+///
+/// Lines are encoded before the lock is taken, which is only held to move strings around.
+#[derive(Debug)]
+pub(crate) struct RecentLines {
+    capacity: usize,
+    pub(crate) attach_as: AttachAs,
+    threshold: LevelFilter,
+    lines: Mutex<VecDeque<String>>,
+}
+
+impl RecentLines {
+    pub(crate) fn new(capacity: usize, attach_as: AttachAs, threshold: LevelFilter) -> RecentLines {
+        let capacity = capacity.max(1);
+        RecentLines {
+            capacity,
+            attach_as,
+            threshold,
+            lines: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Whether the lines are attached to events at `level`.
+    pub(crate) fn attached_to(&self, level: Level) -> bool {
+        level <= self.threshold
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<String>> {
+        self.lines.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds `line`, forgetting the oldest line when full, and returns the lines, oldest first,
+    /// if `copy`.
+    pub(crate) fn push(&self, line: String, copy: bool) -> Option<Vec<String>> {
+        let mut lines = self.lock();
+        if lines.len() >= self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+        copy.then(|| lines.iter().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_lines_in_order() {
+        let recent = RecentLines::new(3, AttachAs::Extra, LevelFilter::Error);
+        for line in ["1", "2", "3", "4"] {
+            assert_eq!(recent.push(line.to_owned(), false), None);
+        }
+        assert_eq!(recent.push("5".to_owned(), true).unwrap(), ["3", "4", "5"]);
+    }
+
+    #[test]
+    fn attached_to_events_at_the_threshold() {
+        let recent = RecentLines::new(3, AttachAs::Extra, LevelFilter::Error);
+        assert!(recent.attached_to(Level::Error));
+        assert!(!recent.attached_to(Level::Warn));
+    }
+}
@@ -0,0 +1,327 @@
+//! Redaction of sensitive data, from user-supplied patterns and built-in presets.
+
+use std::{
+    borrow::Cow,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use regex::{Captures, Match, Regex};
+use sentry::protocol::{value::Value, User};
+
+/// Kinds of personal data the appender knows how to find and scrub.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrubPreset {
+    /// Email addresses, replaced with `[EMAIL]`.
+    Email,
+    /// IPv4 and IPv6 addresses, replaced with `[IP]`. Dotted numbers which look like versions,
+    /// e.g. `v1.2.3.4` or `1.2.3.4.5`, are left alone.
+    Ip,
+    /// Visa, Mastercard, American Express and Discover card numbers, optionally grouped with
+    /// either spaces or dashes, which pass the Luhn check; replaced with `[CREDIT_CARD]`.
+    CreditCard,
+}
+
+impl ScrubPreset {
+    fn rules(self) -> Vec<Rule> {
+        match self {
+            ScrubPreset::Email => vec![Rule::new(
+                r"(?i)\b[a-z0-9._%+-]+@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}\b",
+                "[EMAIL]",
+                any,
+            )],
+            ScrubPreset::Ip => vec![
+                Rule::new(r"\b(?:\d{1,3}\.){3}\d{1,3}\b", "[IP]", is_ipv4),
+                Rule::new(r"(?i)[0-9a-f]*::?[0-9a-f:.]*[0-9a-f]", "[IP]", is_ipv6),
+            ],
+            ScrubPreset::CreditCard => vec![Rule::new(
+                r"\b(?:\d[ -]?){12,18}\d\b",
+                "[CREDIT_CARD]",
+                is_card_number,
+            )],
+        }
+    }
+}
+
+/// A pattern, the marker replacing its matches, and a check each match has to pass to be
+/// replaced.
+#[derive(Clone, Debug)]
+struct Rule {
+    pattern: Regex,
+    marker: &'static str,
+    check: fn(&str, Match) -> bool,
+}
+
+impl Rule {
+    fn new(pattern: &str, marker: &'static str, check: fn(&str, Match) -> bool) -> Rule {
+        Rule {
+            pattern: Regex::new(pattern).expect("invalid built-in pattern"),
+            marker,
+            check,
+        }
+    }
+}
+
+fn any(_: &str, _: Match) -> bool {
+    true
+}
+
+/// Whether the dotted quad `m` is an address rather than part of a version or a longer
+/// dotted number.
+fn is_ipv4(text: &str, m: Match) -> bool {
+    if m.as_str().parse::<Ipv4Addr>().is_err() {
+        return false;
+    }
+    let before = &text[..m.start()];
+    let after = &text[m.end()..];
+    if before.ends_with('.') || (after.starts_with('.') && after[1..].starts_with(char::is_numeric))
+    {
+        return false;
+    }
+    let word = before
+        .trim_end()
+        .rsplit(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    !(before.ends_with(['v', 'V']) || word == "version" || word == "ver")
+}
+
+/// Whether `m` is a whole IPv6 address, rather than e.g. a path such as `std::io`.
+fn is_ipv6(text: &str, m: Match) -> bool {
+    let bounded = |c: Option<char>| !matches!(c, Some(c) if c.is_alphanumeric() || c == ':');
+    bounded(text[..m.start()].chars().next_back())
+        && bounded(text[m.end()..].chars().next())
+        && m.as_str().contains(|c: char| c.is_ascii_digit())
+        && matches!(m.as_str().parse::<Ipv6Addr>(), Ok(ip) if !ip.is_unspecified())
+}
+
+/// Whether `m` is grouped with a single kind of separator, if any, starts with the issuer
+/// prefix of a major card network, has that network's length and passes the Luhn check.
+// `u32::is_multiple_of` needs Rust 1.87.
+#[allow(clippy::manual_is_multiple_of)]
+fn is_card_number(_: &str, m: Match) -> bool {
+    let mut separators = m.as_str().chars().filter(|c| !c.is_ascii_digit());
+    if let Some(separator) = separators.next() {
+        if !separators.all(|c| c == separator) {
+            return false;
+        }
+    }
+    let digits: Vec<u32> = m.as_str().chars().filter_map(|c| c.to_digit(10)).collect();
+    let prefix = |len: usize| digits[..len].iter().fold(0, |prefix, &d| prefix * 10 + d);
+    let issued = match digits.len() {
+        // Visa
+        13 | 19 if digits[0] == 4 => true,
+        // American Express
+        15 => matches!(prefix(2), 34 | 37),
+        16 => {
+            digits[0] == 4
+                // Mastercard
+                || (51..=55).contains(&prefix(2))
+                || (2221..=2720).contains(&prefix(4))
+                // Discover
+                || prefix(4) == 6011
+                || prefix(2) == 65
+        }
+        17..=19 => prefix(4) == 6011 || prefix(2) == 65,
+        _ => false,
+    };
+    if !issued {
+        return false;
+    }
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| match (i % 2, digit * 2) {
+            (0, _) => digit,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+    sum % 10 == 0
+}
+
+/// Replaces sensitive data in the parts of an event the appender fills in, and in the user
+/// context.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Redactor {
+    rules: Vec<Rule>,
+    scrub_ip_address: bool,
+}
+
+impl Redactor {
+    /// Redacts matches of `patterns` with `[REDACTED]`, then those of `presets`.
+    pub(crate) fn new(patterns: Vec<Regex>, presets: &[ScrubPreset]) -> Redactor {
+        let mut rules: Vec<Rule> = patterns
+            .into_iter()
+            .map(|pattern| Rule {
+                pattern,
+                marker: "[REDACTED]",
+                check: any,
+            })
+            .collect();
+        let mut presets = presets.to_vec();
+        presets.dedup();
+        for preset in &presets {
+            rules.extend(preset.rules());
+        }
+        Redactor {
+            rules,
+            scrub_ip_address: presets.contains(&ScrubPreset::Ip),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Replaces every match in `text` with its rule's marker.
+    pub(crate) fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for rule in &self.rules {
+            let haystack = &*text;
+            let replaced = rule.pattern.replace_all(haystack, |caps: &Captures| {
+                let m = caps.get(0).expect("group 0 always matches");
+                if (rule.check)(haystack, m) {
+                    rule.marker.to_owned()
+                } else {
+                    m.as_str().to_owned()
+                }
+            });
+            if let Cow::Owned(redacted) = replaced {
+                if redacted != *text {
+                    text = Cow::Owned(redacted);
+                }
+            }
+        }
+        text
+    }
+
+    pub(crate) fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::String(s) => {
+                if let Cow::Owned(redacted) = self.redact(s) {
+                    *s = redacted;
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|v| self.redact_value(v)),
+            Value::Object(map) => map.values_mut().for_each(|v| self.redact_value(v)),
+            _ => {}
+        }
+    }
+
+    /// Redacts the user's fields, and drops their IP address if IPs are scrubbed.
+    pub(crate) fn redact_user(&self, user: &mut User) {
+        for value in [&mut user.id, &mut user.email, &mut user.username]
+            .iter_mut()
+            .filter_map(|field| field.as_mut())
+        {
+            if let Cow::Owned(redacted) = self.redact(value) {
+                *value = redacted;
+            }
+        }
+        user.other
+            .values_mut()
+            .for_each(|value| self.redact_value(value));
+        if self.scrub_ip_address {
+            user.ip_address = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(presets: &[ScrubPreset], text: &str) -> String {
+        Redactor::new(Vec::new(), presets).redact(text).into_owned()
+    }
+
+    #[test]
+    fn email_preset() {
+        let presets = [ScrubPreset::Email];
+        assert_eq!(
+            redact(&presets, "sent to Jane.Doe+work@mail.example.co.uk today"),
+            "sent to [EMAIL] today"
+        );
+        assert_eq!(redact(&presets, "user@localhost"), "user@localhost");
+        assert_eq!(redact(&presets, "retry @ 5s"), "retry @ 5s");
+    }
+
+    #[test]
+    fn ip_preset() {
+        let presets = [ScrubPreset::Ip];
+        assert_eq!(
+            redact(&presets, "from 192.168.1.20 and 2001:db8::1, port 443"),
+            "from [IP] and [IP], port 443"
+        );
+        for text in [
+            "upgraded to v1.2.3.4",
+            "version 10.0.19041.1",
+            "build 1.2.3.4.5",
+            "failed in std::io::copy",
+            "elapsed 12:30:45",
+            "999.1.1.1",
+        ] {
+            assert_eq!(redact(&presets, text), text);
+        }
+    }
+
+    #[test]
+    fn credit_card_preset() {
+        let presets = [ScrubPreset::CreditCard];
+        assert_eq!(
+            redact(
+                &presets,
+                "paid with 4111 1111 1111 1111 and 5500-0000-0000-0004"
+            ),
+            "paid with [CREDIT_CARD] and [CREDIT_CARD]"
+        );
+        assert_eq!(
+            redact(&presets, "order 4111111111111112"),
+            "order 4111111111111112"
+        );
+        assert_eq!(redact(&presets, "id 1234567890"), "id 1234567890");
+        assert_eq!(
+            redact(
+                &presets,
+                "amex 3782 822463 10005, discover 6011111111111117"
+            ),
+            "amex [CREDIT_CARD], discover [CREDIT_CARD]"
+        );
+        // Luhn-valid numbers without an issuer prefix, or with mixed separators, are left alone.
+        for text in [
+            "at 20240115103000000",
+            "trace 1000000000000008",
+            "4111-1111 1111-1111",
+        ] {
+            assert_eq!(redact(&presets, text), text);
+        }
+    }
+
+    #[test]
+    fn presets_compose_with_patterns() {
+        let redactor = Redactor::new(
+            vec![Regex::new(r"token=\w+").unwrap()],
+            &[ScrubPreset::Email],
+        );
+        assert_eq!(
+            redactor.redact("token=abc for bob@example.com"),
+            "[REDACTED] for [EMAIL]"
+        );
+        assert_eq!(
+            redact(&[], "token=abc for bob@example.com"),
+            "token=abc for bob@example.com"
+        );
+
+        let mut user = User {
+            email: Some("bob@example.com".to_owned()),
+            ip_address: Some(Default::default()),
+            ..Default::default()
+        };
+        Redactor::new(Vec::new(), &[ScrubPreset::Email, ScrubPreset::Ip]).redact_user(&mut user);
+        assert_eq!(user.email.as_deref(), Some("[EMAIL]"));
+        assert!(user.ip_address.is_none());
+    }
+}
@@ -0,0 +1,117 @@
+//! A directory of envelopes which couldn't be sent, kept to be sent again later.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use sentry::types::Dsn;
+
+const EXTENSION: &str = "envelope";
+
+/// Envelopes written to `dir`, oldest pruned first once they take more than `max_size` bytes.
+#[derive(Debug)]
+pub(crate) struct Spool {
+    dir: PathBuf,
+    max_size: u64,
+    written: AtomicU64,
+}
+
+impl Spool {
+    /// A spool for the envelopes sent to `dsn`, in a directory of its own within `dir`, so
+    /// envelopes are replayed to the DSN they were meant for.
+    pub(crate) fn for_dsn(dir: &Path, dsn: &Dsn, max_size: u64) -> io::Result<Spool> {
+        Spool::new(
+            &dir.join(format!("{}-{}", dsn.host(), dsn.project_id())),
+            max_size,
+        )
+    }
+
+    /// Creates `dir` if it doesn't exist.
+    fn new(dir: &Path, max_size: u64) -> io::Result<Spool> {
+        fs::create_dir_all(dir)?;
+        Ok(Spool {
+            dir: dir.to_owned(),
+            max_size,
+            written: AtomicU64::new(0),
+        })
+    }
+
+    /// Writes `envelope` to a file of its own, then prunes the oldest files. Returns how many
+    /// envelopes were pruned.
+    pub(crate) fn write(&self, envelope: &[u8]) -> io::Result<usize> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        // Zero-padded so the names sort in the order the files were written.
+        let name = format!(
+            "{:032}-{:010}",
+            nanos,
+            self.written.fetch_add(1, Ordering::Relaxed)
+        );
+        // Written under another name first so a half-written file is never replayed.
+        let partial = self.dir.join(format!("{}.partial", name));
+        fs::write(&partial, envelope)?;
+        fs::rename(&partial, self.dir.join(format!("{}.{}", name, EXTENSION)))?;
+        Ok(self.prune())
+    }
+
+    /// The spooled envelopes' files, oldest first.
+    pub(crate) fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        files.sort();
+        files
+    }
+
+    /// Removes the oldest files until the rest fit in `max_size`, returning how many were
+    /// removed.
+    fn prune(&self) -> usize {
+        let files = self.files();
+        let sizes: Vec<u64> = files
+            .iter()
+            .map(|path| fs::metadata(path).map_or(0, |metadata| metadata.len()))
+            .collect();
+        let mut total: u64 = sizes.iter().sum();
+        let mut pruned = 0;
+        for (path, size) in files.iter().zip(sizes) {
+            if total <= self.max_size {
+                break;
+            }
+            if fs::remove_file(path).is_ok() {
+                pruned += 1;
+            }
+            total -= size;
+        }
+        pruned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prunes_the_oldest_envelopes() {
+        let dir = std::env::temp_dir().join(format!("sentry-log4rs-prune-{}", std::process::id()));
+        let spool = Spool::new(&dir, 10).unwrap();
+        assert_eq!(spool.write(b"first").unwrap(), 0);
+        assert_eq!(spool.write(b"second").unwrap(), 1);
+        assert_eq!(spool.write(b"3rd").unwrap(), 0);
+        let spooled: Vec<Vec<u8>> = spool
+            .files()
+            .iter()
+            .map(|path| fs::read(path).unwrap())
+            .collect();
+        assert_eq!(spooled, [b"second".to_vec(), b"3rd".to_vec()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+//! Counters the sentry appender keeps about the records it handles.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A snapshot of the appender's counters, see `SentryAppender::stats`.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct Stats {
+    /// Events handed to the sentry client.
+    pub events_sent: u64,
+    /// Records dropped because their level is above the threshold, or trace records dropped
+    /// because `send_trace` is off.
+    pub events_dropped_threshold: u64,
+    /// Records dropped by `ignore_messages` or `only_messages`.
+    pub events_dropped_message: u64,
+    /// Records dropped by `include_targets`, `exclude_targets`, the `filter` closure or
+    /// `skip_empty_messages`.
+    pub events_dropped_filtered: u64,
+    /// Records dropped as repeats within `dedup_window`.
+    pub events_dropped_duplicate: u64,
+    /// Records sampled away by `level_sample_rates`.
+    pub events_dropped_sampled: u64,
+    /// Records dropped because the background queue was full, under the `drop_new` and
+    /// `drop_oldest` policies.
+    pub events_dropped_queue_full: u64,
+    /// Records dropped by `max_events_per_minute`, and events discarded under `retry` because
+    /// of Sentry's rate limits.
+    pub events_dropped_ratelimit: u64,
+    /// Events the sentry client discarded rather than sending, e.g. because of its sample rate
+    /// or `before_send`.
+    pub events_dropped_client: u64,
+    /// Records which couldn't be turned into events, e.g. because encoding failed, and events
+    /// which couldn't be sent under `retry`.
+    pub send_errors: u64,
+    /// Records whose encoder output wasn't the JSON object `message_format: json` expects,
+    /// and were sent as plain text instead.
+    pub invalid_json: u64,
+    /// Records whose encoder output wasn't valid UTF-8. With `encoding_error_policy: fail`
+    /// these are also counted as send errors.
+    pub invalid_utf8: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    events_sent: AtomicU64,
+    events_dropped_threshold: AtomicU64,
+    events_dropped_message: AtomicU64,
+    events_dropped_filtered: AtomicU64,
+    events_dropped_duplicate: AtomicU64,
+    events_dropped_sampled: AtomicU64,
+    events_dropped_queue_full: AtomicU64,
+    events_dropped_ratelimit: AtomicU64,
+    events_dropped_client: AtomicU64,
+    send_errors: AtomicU64,
+    invalid_json: AtomicU64,
+    invalid_utf8: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn sent(&self) {
+        self.events_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped_threshold(&self) {
+        self.events_dropped_threshold
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped_message(&self) {
+        self.events_dropped_message.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped_filtered(&self) {
+        self.events_dropped_filtered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped_duplicate(&self) {
+        self.events_dropped_duplicate
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped_sampled(&self) {
+        self.events_dropped_sampled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped_queue_full(&self) {
+        self.events_dropped_queue_full
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped_ratelimit(&self) {
+        self.events_dropped_ratelimit
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped_client(&self) {
+        self.events_dropped_client.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn send_error(&self) {
+        self.send_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn invalid_json(&self) {
+        self.invalid_json.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn invalid_utf8(&self) {
+        self.invalid_utf8.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        Stats {
+            events_sent: self.events_sent.load(Ordering::Relaxed),
+            events_dropped_threshold: self.events_dropped_threshold.load(Ordering::Relaxed),
+            events_dropped_message: self.events_dropped_message.load(Ordering::Relaxed),
+            events_dropped_filtered: self.events_dropped_filtered.load(Ordering::Relaxed),
+            events_dropped_duplicate: self.events_dropped_duplicate.load(Ordering::Relaxed),
+            events_dropped_sampled: self.events_dropped_sampled.load(Ordering::Relaxed),
+            events_dropped_queue_full: self.events_dropped_queue_full.load(Ordering::Relaxed),
+            events_dropped_ratelimit: self.events_dropped_ratelimit.load(Ordering::Relaxed),
+            events_dropped_client: self.events_dropped_client.load(Ordering::Relaxed),
+            send_errors: self.send_errors.load(Ordering::Relaxed),
+            invalid_json: self.invalid_json.load(Ordering::Relaxed),
+            invalid_utf8: self.invalid_utf8.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,439 @@
+//! Sampling, rate limiting and deduplication of records, shared by the appender and the
+//! `sentry_rate_limit` filter.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use log::{Level, Record};
+use log4rs::{
+    config::{Deserialize, Deserializers},
+    filter::{Filter, Response},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    config::{default_dedup_max_entries, deserialize_duration, serialize_duration},
+    ratelimit::RateLimiter,
+    throttle::Throttle,
+    SampleRate, Sampling,
+};
+
+/// Picks the records kept by per-level sample rates.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Sampler {
+    rates: BTreeMap<Level, SampleRate>,
+    sampling: Sampling,
+    salt: String,
+}
+
+impl Sampler {
+    pub(crate) fn new(
+        rates: BTreeMap<Level, SampleRate>,
+        sampling: Sampling,
+        salt: String,
+    ) -> Sampler {
+        Sampler {
+            rates,
+            sampling,
+            salt,
+        }
+    }
+
+    /// Whether `record` is kept by the sample rate for its level. Levels without one are
+    /// always kept.
+    pub(crate) fn keep(&self, record: &Record) -> bool {
+        match self.rates.get(&record.level()) {
+            Some(rate) if rate.get() < 1.0 => self.sample(record) < rate.get(),
+            _ => true,
+        }
+    }
+
+    /// A number between 0 and 1 which `record` is kept for if it's below the sample rate.
+    fn sample(&self, record: &Record) -> f32 {
+        match self.sampling {
+            Sampling::Random => rand::random(),
+            Sampling::Deterministic => {
+                stable_fraction(&[&self.salt, record.target(), &record.args().to_string()])
+            }
+        }
+    }
+}
+
+/// A number between 0 and 1 derived from a hash of `parts` which is the same in every process
+/// and on every platform, unlike that of `DefaultHasher`.
+fn stable_fraction(parts: &[&str]) -> f32 {
+    // FNV-1a, with a byte which can't occur in UTF-8 between parts.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for &byte in part.as_bytes().iter().chain(&[0xff]) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    // Mix the bits so the top ones are uniform even for short inputs.
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// A log4rs filter applying the appender's sampling, rate limiting and deduplication to any
+/// appender.
+///
+/// Records it suppresses are rejected; the others are left to the next filter.
+#[derive(Debug)]
+pub struct RateLimitFilter {
+    sampler: Sampler,
+    dedup: Option<Throttle>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl RateLimitFilter {
+    /// Creates a new `RateLimitFilter` builder.
+    pub fn builder() -> RateLimitFilterBuilder {
+        RateLimitFilterBuilder {
+            max_events_per_minute: None,
+            burst: None,
+            dedup_window: None,
+            dedup_max_entries: default_dedup_max_entries(),
+            level_sample_rates: BTreeMap::new(),
+            sampling: Sampling::default(),
+            sampling_salt: String::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl Filter for RateLimitFilter {
+    fn filter(&self, record: &Record) -> Response {
+        if !self.sampler.keep(record) {
+            return Response::Reject;
+        }
+        if let Some(ref dedup) = self.dedup {
+            if dedup
+                .check(&(record.target(), record.args().to_string()))
+                .is_none()
+            {
+                return Response::Reject;
+            }
+        }
+        if let Some(ref rate_limiter) = self.rate_limiter {
+            if rate_limiter.acquire().is_none() {
+                return Response::Reject;
+            }
+        }
+        Response::Neutral
+    }
+}
+
+/// A builder for `RateLimitFilter`s.
+#[derive(Clone, Debug)]
+pub struct RateLimitFilterBuilder {
+    max_events_per_minute: Option<u32>,
+    burst: Option<u32>,
+    dedup_window: Option<Duration>,
+    dedup_max_entries: usize,
+    level_sample_rates: BTreeMap<Level, SampleRate>,
+    sampling: Sampling,
+    sampling_salt: String,
+    clock: Arc<dyn Clock>,
+}
+
+impl RateLimitFilterBuilder {
+    /// Lets through at most `max_events_per_minute` records a minute.
+    pub fn max_events_per_minute(mut self, max_events_per_minute: u32) -> RateLimitFilterBuilder {
+        self.max_events_per_minute = Some(max_events_per_minute);
+        self
+    }
+
+    /// Sets how many records `max_events_per_minute` lets through at once. Defaults to
+    /// `max_events_per_minute`.
+    pub fn burst(mut self, burst: u32) -> RateLimitFilterBuilder {
+        self.burst = Some(burst);
+        self
+    }
+
+    /// Lets through identical messages from a target only once in `window`.
+    pub fn dedup_window(mut self, window: Duration) -> RateLimitFilterBuilder {
+        self.dedup_window = Some(window);
+        self
+    }
+
+    /// Sets how many distinct messages `dedup_window` keeps track of. Defaults to 1024.
+    pub fn dedup_max_entries(mut self, max_entries: usize) -> RateLimitFilterBuilder {
+        self.dedup_max_entries = max_entries;
+        self
+    }
+
+    /// Lets through only a share of the records at `level`.
+    pub fn level_sample_rate(mut self, level: Level, rate: SampleRate) -> RateLimitFilterBuilder {
+        self.level_sample_rates.insert(level, rate);
+        self
+    }
+
+    /// Sets how `level_sample_rate` picks the records to let through. Defaults to `Random`.
+    pub fn sampling(mut self, sampling: Sampling) -> RateLimitFilterBuilder {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Sets the salt mixed into the hash used by `Sampling::Deterministic`.
+    pub fn sampling_salt(mut self, salt: &str) -> RateLimitFilterBuilder {
+        self.sampling_salt = salt.to_owned();
+        self
+    }
+
+    /// Sets the clock read by `max_events_per_minute` and `dedup_window`. Defaults to
+    /// `SystemClock`.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> RateLimitFilterBuilder {
+        self.clock = clock;
+        self
+    }
+
+    /// Consumes the `RateLimitFilterBuilder`, producing a `RateLimitFilter`.
+    pub fn build(self) -> RateLimitFilter {
+        let burst = self.burst;
+        let dedup_max_entries = self.dedup_max_entries;
+        let clock = self.clock;
+        RateLimitFilter {
+            sampler: Sampler::new(self.level_sample_rates, self.sampling, self.sampling_salt),
+            dedup: self
+                .dedup_window
+                .map(|window| Throttle::new(1, window, dedup_max_entries, clock.clone())),
+            rate_limiter: self.max_events_per_minute.map(|max_events_per_minute| {
+                RateLimiter::new(
+                    max_events_per_minute,
+                    burst.unwrap_or(max_events_per_minute),
+                    clock,
+                )
+            }),
+        }
+    }
+}
+
+/// Configuration for the `sentry_rate_limit` filter, with the same keys as the appender.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitFilterConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_events_per_minute: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    burst: Option<u32>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    dedup_window: Option<Duration>,
+    #[serde(default = "default_dedup_max_entries")]
+    dedup_max_entries: usize,
+    #[serde(default)]
+    level_sample_rates: BTreeMap<Level, SampleRate>,
+    #[serde(default)]
+    sampling: Sampling,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sampling_salt: Option<String>,
+}
+
+/// A deserializer for the `RateLimitFilter`.
+///
+/// # Configuration
+///
+/// ```yaml
+/// kind: sentry_rate_limit
+///
+/// # Let through at most this many records a minute, up to `burst` at once. `burst` defaults
+/// # to `max_events_per_minute`. Optional.
+/// max_events_per_minute: 600
+/// burst: 50
+///
+/// # Let through identical messages from a target only once in this window, keeping track of
+/// # up to `dedup_max_entries` messages. Optional; `dedup_max_entries` defaults to 1024.
+/// dedup_window: 60s
+/// dedup_max_entries: 1024
+///
+/// # The share of records at these levels to let through, picked `random`ly or
+/// # `deterministic`ally. Levels not listed are all let through. Optional.
+/// level_sample_rates:
+///   warn: 0.05
+/// sampling: random
+/// sampling_salt: "2024-q3"
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct RateLimitFilterDeserializer;
+
+impl Deserialize for RateLimitFilterDeserializer {
+    type Trait = dyn Filter;
+
+    type Config = RateLimitFilterConfig;
+
+    fn deserialize(
+        &self,
+        config: RateLimitFilterConfig,
+        _: &Deserializers,
+    ) -> anyhow::Result<Box<dyn Filter>> {
+        let mut filter = RateLimitFilter::builder();
+
+        if let Some(max_events_per_minute) = config.max_events_per_minute {
+            if max_events_per_minute == 0 {
+                anyhow::bail!("`max_events_per_minute` must be greater than zero");
+            }
+            filter = filter.max_events_per_minute(max_events_per_minute);
+        }
+
+        if let Some(burst) = config.burst {
+            filter = filter.burst(burst);
+        }
+
+        if let Some(window) = config.dedup_window {
+            if window.is_zero() {
+                anyhow::bail!("`dedup_window` must be greater than zero");
+            }
+            filter = filter.dedup_window(window);
+        }
+
+        filter = filter.dedup_max_entries(config.dedup_max_entries);
+
+        for (&level, &rate) in &config.level_sample_rates {
+            filter = filter.level_sample_rate(level, rate);
+        }
+
+        filter = filter.sampling(config.sampling);
+
+        if let Some(ref salt) = config.sampling_salt {
+            filter = filter.sampling_salt(salt);
+        }
+
+        Ok(Box::new(filter.build()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    fn rates(rates: &[(Level, f32)]) -> BTreeMap<Level, SampleRate> {
+        rates
+            .iter()
+            .map(|&(level, rate)| (level, SampleRate::try_from(rate).unwrap()))
+            .collect()
+    }
+
+    /// How many of `n` records at `level`, with distinct messages, `sampler` keeps.
+    fn kept(sampler: &Sampler, level: Level, n: usize) -> usize {
+        (0..n)
+            .filter(|i| {
+                sampler.keep(
+                    &Record::builder()
+                        .level(level)
+                        .target("app")
+                        .args(format_args!("request {} failed", i))
+                        .build(),
+                )
+            })
+            .count()
+    }
+
+    #[test]
+    fn level_sample_rates_keep_their_share() {
+        let sampler = Sampler::new(
+            rates(&[(Level::Warn, 0.05), (Level::Info, 0.5)]),
+            Sampling::Random,
+            String::new(),
+        );
+        let warn = kept(&sampler, Level::Warn, 10_000);
+        assert!((350..650).contains(&warn), "kept {} warnings", warn);
+        let info = kept(&sampler, Level::Info, 10_000);
+        assert!((4700..5300).contains(&info), "kept {} infos", info);
+        assert_eq!(kept(&sampler, Level::Error, 1000), 1000);
+
+        let sampler = Sampler::new(
+            rates(&[(Level::Error, 0.0)]),
+            Sampling::Random,
+            String::new(),
+        );
+        assert_eq!(kept(&sampler, Level::Error, 1000), 0);
+    }
+
+    #[test]
+    fn deterministic_sampling_is_stable() {
+        let sampler = |salt: &str| {
+            Sampler::new(
+                rates(&[(Level::Warn, 0.2)]),
+                Sampling::Deterministic,
+                salt.to_owned(),
+            )
+        };
+        let decisions = |sampler: &Sampler| -> Vec<bool> {
+            (0..1000)
+                .map(|i| {
+                    sampler.keep(
+                        &Record::builder()
+                            .level(Level::Warn)
+                            .target("app")
+                            .args(format_args!("request {} failed", i))
+                            .build(),
+                    )
+                })
+                .collect()
+        };
+        let first = decisions(&sampler(""));
+        assert_eq!(first, decisions(&sampler("")));
+        let share = first.iter().filter(|&&kept| kept).count();
+        assert!((140..260).contains(&share), "kept {} of 1000", share);
+        assert_ne!(first, decisions(&sampler("2024-q3")));
+        assert_eq!(kept(&sampler(""), Level::Error, 100), 100);
+
+        // The hash doesn't depend on the process, so it's pinned across runs and platforms.
+        assert_eq!(stable_fraction(&["", "app", "request 1 failed"]), 0.2463724);
+    }
+
+    #[test]
+    fn filter_from_yaml_rejects_suppressed_records() {
+        let raw: log4rs::config::RawConfig = serde_yaml::from_str(
+            r#"
+appenders:
+  email:
+    kind: console
+    filters:
+      - kind: sentry_rate_limit
+        max_events_per_minute: 2
+        dedup_window: 1m
+        level_sample_rates:
+          warn: 0.0
+"#,
+        )
+        .unwrap();
+        let (appenders, errors) = raw.appenders_lossy(&crate::SentryAppender::deserializers());
+        assert!(errors.is_empty(), "{:?}", errors);
+        let filter = &appenders[0].filters()[0];
+        let decide = |level, msg: &str| {
+            filter.filter(
+                &Record::builder()
+                    .level(level)
+                    .target("app")
+                    .args(format_args!("{}", msg))
+                    .build(),
+            )
+        };
+        let decisions = [
+            decide(Level::Warn, "sampled out"),
+            decide(Level::Error, "first"),
+            decide(Level::Error, "first"),
+            decide(Level::Error, "second"),
+            decide(Level::Error, "third"),
+        ];
+        assert!(matches!(
+            decisions,
+            [
+                Response::Reject,
+                Response::Neutral,
+                Response::Reject,
+                Response::Neutral,
+                Response::Reject
+            ]
+        ));
+    }
+}
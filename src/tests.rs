@@ -0,0 +1,2908 @@
+use super::*;
+use crate::{
+    builder::{fallback_os_context, MIN_RETRY_BACKOFF},
+    capture::ENCODE_BUFFER_CAPACITY,
+};
+use log4rs::encode::pattern::PatternEncoder;
+use sentry::{
+    protocol::{Event, Frame, Level as SentryLevel},
+    test::TestTransport,
+    ClientOptions, Transport,
+};
+use std::convert::TryFrom;
+
+/// A hub whose client keeps the events it's given in the returned transport.
+pub(crate) fn test_hub() -> (Arc<Hub>, Arc<TestTransport>) {
+    let transport = TestTransport::new();
+    let options = ClientOptions {
+        dsn: Some("https://public@sentry.invalid/1".parse().unwrap()),
+        transport: Some(Arc::new(transport.clone())),
+        ..Default::default()
+    };
+    let hub = Arc::new(Hub::new(Some(Arc::new(options.into())), Default::default()));
+    (hub, transport)
+}
+
+/// Builds an appender capturing into a test hub, logs to it with `log`, and returns the
+/// events it captured.
+fn capture(
+    builder: SentryAppenderBuilder,
+    log: impl FnOnce(&SentryAppender),
+) -> Vec<Event<'static>> {
+    let (hub, transport) = test_hub();
+    let appender = builder.hub(hub).build();
+    log(&appender);
+    appender.flush();
+    transport.fetch_and_clear_events()
+}
+
+/// Appends a record at `level` from the module `module`, with a location in it.
+fn append_from(appender: &SentryAppender, level: Level, module: &str, msg: &str) {
+    appender
+        .append(
+            &Record::builder()
+                .level(level)
+                .target(module)
+                .module_path(Some(module))
+                .file(Some("src/module.rs"))
+                .line(Some(42))
+                .args(format_args!("{}", msg))
+                .build(),
+        )
+        .unwrap();
+}
+
+fn frame<'a>(event: &'a Event<'static>) -> &'a Frame {
+    &event.stacktrace.as_ref().expect("no stacktrace").frames[0]
+}
+
+fn append(appender: &SentryAppender, level: Level, msg: &str) {
+    append_from(appender, level, "app::module", msg)
+}
+
+/// Appends a record carrying the key-values `kvs`.
+fn append_kv(
+    appender: &SentryAppender,
+    level: Level,
+    msg: &str,
+    kvs: &[(&str, &dyn log::kv::ToValue)],
+) {
+    appender
+        .append(
+            &Record::builder()
+                .level(level)
+                .target("app::module")
+                .args(format_args!("{}", msg))
+                .key_values(&kvs)
+                .build(),
+        )
+        .unwrap();
+}
+
+fn messages<'a>(events: &'a [Event<'static>]) -> Vec<&'a str> {
+    events
+        .iter()
+        .map(|event| event.message.as_deref().unwrap_or_default())
+        .collect()
+}
+
+/// Holds up encoding until it's opened, e.g. to fill the queue of the worker.
+#[derive(Debug, Default)]
+struct Gate {
+    open: std::sync::Mutex<bool>,
+    opened: std::sync::Condvar,
+}
+
+impl Gate {
+    fn open(&self) {
+        *self.open.lock().unwrap() = true;
+        self.opened.notify_all();
+    }
+}
+
+/// An encoder writing the message once its gate is open, and panicking on the message
+/// `panic`.
+#[derive(Debug)]
+struct GateEncoder(Arc<Gate>);
+
+impl GateEncoder {
+    fn opened() -> GateEncoder {
+        let gate = Arc::new(Gate::default());
+        gate.open();
+        GateEncoder(gate)
+    }
+}
+
+impl Encode for GateEncoder {
+    fn encode(&self, w: &mut dyn log4rs::encode::Write, record: &Record) -> anyhow::Result<()> {
+        let mut open = self.0.open.lock().unwrap();
+        while !*open {
+            open = self.0.opened.wait(open).unwrap();
+        }
+        drop(open);
+        if record.args().to_string() == "panic" {
+            panic!("encoder panicked");
+        }
+        write!(w, "{}", record.args())?;
+        Ok(())
+    }
+}
+
+/// Appends `first` and waits for the worker to pick it up, so that it's stuck in a closed
+/// gate and the following records stay queued.
+fn occupy_worker(appender: &SentryAppender, first: &str) {
+    append(appender, Level::Error, first);
+    while appender.queue_depth() > 0 {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn capture_with_full_queue(policy: QueueFullPolicy) -> Vec<Event<'static>> {
+    let gate = Arc::new(Gate::default());
+    capture(
+        SentryAppender::builder()
+            .encoder(Box::new(GateEncoder(gate.clone())))
+            .async_queue(2)
+            .queue_full(policy),
+        |appender| {
+            occupy_worker(appender, "1");
+            for msg in ["2", "3", "4", "5"] {
+                append(appender, Level::Error, msg);
+            }
+            assert_eq!(appender.queue_depth(), 2);
+            gate.open();
+        },
+    )
+}
+
+#[test]
+fn full_queue_drops_new_records() {
+    let events = capture_with_full_queue(QueueFullPolicy::DropNew);
+    assert_eq!(messages(&events), ["1", "2", "3"]);
+}
+
+#[test]
+fn full_queue_drops_oldest_records() {
+    let events = capture_with_full_queue(QueueFullPolicy::DropOldest);
+    assert_eq!(messages(&events), ["1", "4", "5"]);
+}
+
+#[test]
+fn full_queue_blocks_until_worker_makes_room() {
+    let gate = Arc::new(Gate::default());
+    let events = capture(
+        SentryAppender::builder()
+            .encoder(Box::new(GateEncoder(gate.clone())))
+            .async_queue(2)
+            .queue_full(QueueFullPolicy::Block),
+        |appender| {
+            occupy_worker(appender, "1");
+            std::thread::scope(|scope| {
+                let logger = scope.spawn(|| {
+                    for msg in ["2", "3", "4", "5"] {
+                        append(appender, Level::Error, msg);
+                    }
+                });
+                while appender.queue_depth() < 2 {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                std::thread::sleep(Duration::from_millis(50));
+                assert!(!logger.is_finished());
+                gate.open();
+            });
+        },
+    );
+    assert_eq!(messages(&events), ["1", "2", "3", "4", "5"]);
+}
+
+#[test]
+fn flush_waits_for_queued_records() {
+    let expected: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+    let events = capture(
+        SentryAppender::builder()
+            .encoder(Box::new(GateEncoder::opened()))
+            .async_queue(1000),
+        |appender| {
+            for msg in &expected {
+                append(appender, Level::Error, msg);
+            }
+        },
+    );
+    assert_eq!(messages(&events), expected);
+}
+
+#[test]
+fn no_records_lost_below_queue_size() {
+    let (threads, per_thread) = (8, 100);
+    let mut dropped = None;
+    let events = capture(SentryAppender::builder().async_queue(1024), |appender| {
+        std::thread::scope(|scope| {
+            for thread in 0..threads {
+                scope.spawn(move || {
+                    for i in 0..per_thread {
+                        append(appender, Level::Error, &format!("{}-{}", thread, i));
+                    }
+                });
+            }
+        });
+        appender.flush();
+        dropped = Some(appender.stats().events_dropped_queue_full);
+    });
+    assert_eq!(dropped, Some(0));
+    let mut messages = messages(&events);
+    messages.sort_unstable();
+    messages.dedup();
+    assert_eq!(messages.len(), threads * per_thread);
+}
+
+#[test]
+fn worker_survives_a_panicking_encoder() {
+    let events = capture(
+        SentryAppender::builder()
+            .encoder(Box::new(GateEncoder::opened()))
+            .async_queue(1)
+            .queue_full(QueueFullPolicy::Block),
+        |appender| {
+            append(appender, Level::Error, "panic");
+            append(appender, Level::Error, "after");
+        },
+    );
+    assert_eq!(messages(&events), ["after"]);
+}
+
+#[test]
+fn in_app_prefixes_match_whole_modules() {
+    let events = capture(
+        SentryAppender::builder()
+            .in_app_exclude("my_app")
+            .in_app_include("my_app::core"),
+        |appender| {
+            append_from(appender, Level::Error, "my_app::core::db", "included");
+            append_from(appender, Level::Error, "my_app::web", "excluded");
+            append_from(appender, Level::Error, "my_application", "other crate");
+            append_from(appender, Level::Error, "my_app::core_ext", "other module");
+        },
+    );
+    let in_app: Vec<Option<bool>> = events.iter().map(|event| frame(event).in_app).collect();
+    assert_eq!(in_app, [Some(true), Some(false), Some(true), Some(false)]);
+}
+
+#[test]
+fn in_app_prefixes_are_leaked_once() {
+    let first = SentryAppender::builder().in_app_include("my_app::core");
+    let second = SentryAppender::builder().in_app_exclude("my_app::core");
+    assert!(std::ptr::eq(
+        first.in_app_include[0],
+        second.in_app_exclude[0]
+    ));
+}
+
+#[test]
+fn template_params_keep_source_order_in_async_mode() {
+    let log = |appender: &SentryAppender| {
+        let kvs = [("template", "{} before {}"), ("zeta", "z"), ("alpha", "a")];
+        appender
+            .append(
+                &Record::builder()
+                    .level(Level::Error)
+                    .args(format_args!("zeta before alpha"))
+                    .key_values(&kvs)
+                    .build(),
+            )
+            .unwrap();
+    };
+    let params = |events: Vec<Event<'static>>| {
+        events[0]
+            .logentry
+            .as_ref()
+            .expect("no logentry")
+            .params
+            .clone()
+    };
+
+    let builder = || SentryAppender::builder().template_key("template");
+    let sync = params(capture(builder(), log));
+    let async_ = params(capture(builder().async_queue(8), log));
+    assert_eq!(sync, [Value::from("z"), Value::from("a")]);
+    assert_eq!(async_, sync);
+}
+
+#[test]
+fn from_config_enables_compression() {
+    let config: SentryAppenderConfig = serde_yaml::from_str(
+        r#"
+dsn: "https://public@sentry.invalid/1"
+compress_payloads: true
+"#,
+    )
+    .unwrap();
+    let builder = SentryAppenderBuilder::from_config(config, &Deserializers::default()).unwrap();
+    assert!(builder.compress_payloads);
+}
+
+#[test]
+fn from_config_reports_every_invalid_value() {
+    let config: SentryAppenderConfig = serde_yaml::from_str(
+        r#"
+dsn: "https://public@sentry.invalid/1"
+traces_sample_rate: 1.5
+level_sample_rates:
+  warn: -0.5
+repeat_policy:
+  first: 3
+  then_sample: 2.0
+fingerprint: ["{unknown}"]
+normalize_patterns:
+  - pattern: "("
+    replacement: ""
+"#,
+    )
+    .unwrap();
+    let err = SentryAppenderBuilder::from_config(config, &Deserializers::default())
+        .err()
+        .expect("invalid config accepted")
+        .to_string();
+    for name in [
+        "traces_sample_rate",
+        "level_sample_rates.warn",
+        "repeat_policy.then_sample",
+        "fingerprint",
+        "normalize pattern",
+    ] {
+        assert!(err.contains(name), "`{}` not reported in: {}", name, err);
+    }
+}
+
+#[test]
+fn build_ignores_invalid_values_which_try_build_rejects() {
+    let builder = || {
+        SentryAppender::builder()
+            .dsn("not a dsn")
+            .flush_interval(Duration::ZERO)
+            .max_events_per_minute(0)
+            .retry(3, Duration::ZERO, Duration::ZERO)
+    };
+    let err = builder().try_build().expect_err("invalid options accepted");
+    assert!(err.to_string().contains("flush_interval"), "{}", err);
+
+    let fixed = builder().without_invalid_values();
+    assert!(fixed.problems().is_empty(), "{:?}", fixed.problems());
+    assert_eq!(fixed.dsn, "");
+    assert_eq!(fixed.flush_interval, None);
+    assert_eq!(fixed.max_events_per_minute, None);
+    let backoff = fixed.retry.unwrap();
+    assert_eq!(
+        (backoff.initial, backoff.max),
+        (MIN_RETRY_BACKOFF, MIN_RETRY_BACKOFF)
+    );
+
+    let appender = builder().build();
+    assert!(appender.client().is_none());
+}
+
+#[test]
+fn build_context_names_rustc_and_target() {
+    let events = capture(
+        SentryAppender::builder().include_build_context(true),
+        |appender| append(appender, Level::Error, "boom"),
+    );
+    let rust = match events[0].contexts.get("rust") {
+        Some(Context::Runtime(rust)) => rust,
+        context => panic!("unexpected rust context {:?}", context),
+    };
+    assert_eq!(rust.name.as_deref(), Some("rustc"));
+    assert!(rust.version.as_deref().is_some_and(|v| !v.is_empty()));
+    let target = rust.other["target"].as_str().unwrap();
+    assert!(target.starts_with(std::env::consts::ARCH));
+    assert_eq!(target.contains("linux"), cfg!(target_os = "linux"));
+    assert_eq!(target.contains("windows"), cfg!(target_os = "windows"));
+}
+
+#[test]
+fn last_event_id_is_the_captured_event() {
+    let mut id = None;
+    let events = capture(SentryAppender::builder(), |appender| {
+        append(appender, Level::Error, "boom");
+        id = last_event_id();
+    });
+    let id = id.expect("no event id");
+    assert!(!id.is_nil());
+    assert_eq!(id, events[0].event_id);
+}
+
+#[test]
+fn default_contexts_add_os_and_device() {
+    let events = capture(
+        SentryAppender::builder().default_contexts(true),
+        |appender| append(appender, Level::Error, "boom"),
+    );
+    assert!(matches!(events[0].contexts.get("os"), Some(Context::Os(_))));
+    assert!(matches!(
+        events[0].contexts.get("device"),
+        Some(Context::Device(_))
+    ));
+
+    let events = capture(SentryAppender::builder(), |appender| {
+        append(appender, Level::Error, "boom")
+    });
+    assert!(!events[0].contexts.contains_key("os"));
+}
+
+#[test]
+fn os_context_falls_back_to_compile_time_os() {
+    match fallback_os_context() {
+        Context::Os(os) => assert_eq!(os.name.as_deref(), Some(std::env::consts::OS)),
+        context => panic!("unexpected os context {:?}", context),
+    }
+}
+
+#[test]
+fn redact_patterns_redact_messages() {
+    let events = capture(
+        SentryAppender::builder().redact_pattern(Regex::new(r"token=\w+").unwrap()),
+        |appender| append(appender, Level::Error, "login with token=abc123 failed"),
+    );
+    assert_eq!(messages(&events), ["login with [REDACTED] failed"]);
+
+    // Messages are redacted before they're truncated, which would cut the secret short.
+    let events = capture(
+        SentryAppender::builder()
+            .redact_pattern(Regex::new(r"token=\w+").unwrap())
+            .max_message_bytes(20),
+        |appender| append(appender, Level::Error, "login with token=abc123 failed"),
+    );
+    assert_eq!(
+        messages(&events),
+        ["login with [REDACTED… [truncated 8 bytes]"]
+    );
+    assert_eq!(events[0].extra["message_bytes"], 30);
+
+    // So are templates and transactions taken from the MDC.
+    let events = capture(
+        SentryAppender::builder()
+            .redact_pattern(Regex::new(r"token=\w+").unwrap())
+            .template_key("template")
+            .transaction_from("mdc:request_path".parse().unwrap()),
+        |appender| {
+            std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    log_mdc::insert("request_path", "/login?token=abc123");
+                    append_kv(
+                        appender,
+                        Level::Error,
+                        "login with token=abc123 failed",
+                        &[("template", &"login with token=abc123 {}")],
+                    );
+                });
+            })
+        },
+    );
+    assert_eq!(
+        events[0].logentry.as_ref().unwrap().message,
+        "login with [REDACTED] {}"
+    );
+    assert_eq!(events[0].transaction.as_deref(), Some("/login?[REDACTED]"));
+}
+
+#[test]
+fn location_becomes_a_stacktrace_frame() {
+    let events = capture(SentryAppender::builder(), |appender| {
+        append_from(appender, Level::Error, "app::db", "with location");
+        appender
+            .append(
+                &Record::builder()
+                    .level(Level::Error)
+                    .args(format_args!("without location"))
+                    .build(),
+            )
+            .unwrap();
+    });
+    let frame = frame(&events[0]);
+    assert_eq!(frame.filename.as_deref(), Some("src/module.rs"));
+    assert_eq!(frame.lineno, Some(42));
+    assert_eq!(frame.module.as_deref(), Some("app::db"));
+    assert!(!events[0].extra.contains_key("file"));
+    assert!(events[1].stacktrace.is_none());
+}
+
+#[test]
+fn include_kv_adds_the_record_as_an_extra() {
+    let events = capture(SentryAppender::builder().include_kv(true), |appender| {
+        append_kv(
+            appender,
+            Level::Error,
+            "payment failed",
+            &[("order_id", &42), ("retry", &true)],
+        )
+    });
+    let record = events[0].extra["log_record"].as_object().unwrap();
+    assert_eq!(record["args"], "payment failed");
+    assert_eq!(record["level"], "ERROR");
+    assert_eq!(record["target"], "app::module");
+    assert_eq!(record["kv"]["order_id"], 42);
+    assert_eq!(record["kv"]["retry"], true);
+}
+
+#[test]
+fn log_record_keeps_literal_messages_as_the_template() {
+    let log = |appender: &SentryAppender| {
+        let order_id = 42;
+        for args in [
+            format_args!("payment failed"),
+            format_args!("payment {} failed", order_id),
+        ] {
+            appender
+                .append(&Record::builder().level(Level::Error).args(args).build())
+                .unwrap();
+        }
+    };
+    for builder in [
+        SentryAppender::builder().include_kv(true),
+        SentryAppender::builder().include_kv(true).async_queue(8),
+    ] {
+        let events = capture(builder, log);
+        assert_eq!(events[0].extra["log_record"]["template"], "payment failed");
+        assert!(events[1].extra["log_record"].get("template").is_none());
+    }
+}
+
+#[test]
+fn report_as_exception_keeps_the_message() {
+    let events = capture(
+        SentryAppender::builder().report_as_exception(true),
+        |appender| append_from(appender, Level::Error, "app::db", "connection lost"),
+    );
+    let exception = &events[0].exception.values[0];
+    assert_eq!(exception.ty, "app::db");
+    assert_eq!(exception.value.as_deref(), Some("connection lost"));
+    assert_eq!(exception.module.as_deref(), Some("app::db"));
+    assert!(exception.stacktrace.is_some());
+    assert_eq!(events[0].message.as_deref(), Some("connection lost"));
+
+    let events = capture(
+        SentryAppender::builder()
+            .report_as_exception(true)
+            .exception_type("DbError"),
+        |appender| append(appender, Level::Error, "connection lost"),
+    );
+    assert_eq!(events[0].exception.values[0].ty, "DbError");
+}
+
+#[test]
+fn session_is_tracked_for_the_lifetime_of_the_appender() {
+    let sessions = |auto_session_tracking: bool| {
+        let transport = TestTransport::new();
+        let options = ClientOptions {
+            dsn: Some("https://public@sentry.invalid/1".parse().unwrap()),
+            release: Some("my-app@1.0.0".into()),
+            transport: Some(Arc::new(transport.clone())),
+            ..Default::default()
+        };
+        let hub = Arc::new(Hub::new(Some(Arc::new(options.into())), Default::default()));
+        let appender = SentryAppender::builder()
+            .auto_session_tracking(auto_session_tracking)
+            .hub(hub)
+            .build();
+        append(&appender, Level::Error, "boom");
+        drop(appender);
+        transport
+            .fetch_and_clear_envelopes()
+            .iter()
+            .flat_map(|envelope| envelope.items())
+            .filter_map(|item| match item {
+                sentry::protocol::EnvelopeItem::SessionUpdate(session) => {
+                    Some((session.status, session.errors))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        sessions(true),
+        [
+            (sentry::protocol::SessionStatus::Ok, 1),
+            (sentry::protocol::SessionStatus::Exited, 1),
+        ]
+    );
+    assert!(sessions(false).is_empty());
+}
+
+#[inline(never)]
+fn log_from_caller(appender: &SentryAppender, level: Level) {
+    append(appender, level, "boom");
+}
+
+#[test]
+fn backtraces_are_captured_for_configured_levels() {
+    let events = capture(
+        SentryAppender::builder()
+            .threshold(LevelFilter::Warn)
+            .backtrace_levels(vec![Level::Error]),
+        |appender| {
+            log_from_caller(appender, Level::Error);
+            log_from_caller(appender, Level::Warn);
+        },
+    );
+    let functions: Vec<&str> = events[0]
+        .stacktrace
+        .as_ref()
+        .unwrap()
+        .frames
+        .iter()
+        .filter_map(|frame| frame.function.as_deref())
+        .collect();
+    assert!(
+        functions.iter().any(|f| f.contains("log_from_caller")),
+        "{:?}",
+        functions
+    );
+    // Warnings only get the frame of their location.
+    assert_eq!(events[1].stacktrace.as_ref().unwrap().frames.len(), 1);
+}
+
+#[derive(Debug)]
+struct NestedError(&'static str, Option<Box<NestedError>>);
+
+impl std::fmt::Display for NestedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for NestedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.1.as_deref().map(|e| e as _)
+    }
+}
+
+#[test]
+fn err_key_value_becomes_an_exception_chain() {
+    let err = NestedError(
+        "save failed",
+        Some(Box::new(NestedError(
+            "write failed",
+            Some(Box::new(NestedError("disk full", None))),
+        ))),
+    );
+    let log = |appender: &SentryAppender| {
+        // What `error!(err:err = err; "could not save")` logs.
+        append_kv(
+            appender,
+            Level::Error,
+            "could not save",
+            &[("err", &log::kv::Value::from_dyn_error(&err))],
+        );
+        append(appender, Level::Error, "no error");
+    };
+    for builder in [
+        SentryAppender::builder(),
+        SentryAppender::builder().async_queue(8),
+    ] {
+        let events = capture(builder, log);
+        let chain: Vec<(&str, Option<&str>)> = events[0]
+            .exception
+            .values
+            .iter()
+            .map(|exception| (exception.ty.as_str(), exception.value.as_deref()))
+            .collect();
+        assert_eq!(
+            chain,
+            [
+                ("NestedError", Some("disk full")),
+                ("NestedError", Some("write failed")),
+                ("NestedError", Some("save failed")),
+            ]
+        );
+        assert!(events[1].exception.values.is_empty());
+        assert!(events[1].stacktrace.is_some());
+    }
+}
+
+fn config(yaml: &str) -> SentryAppenderConfig {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+#[test]
+fn unknown_fields_fail_unless_lenient() {
+    let strict = config("dsn: \"\"\nfuture_option: 1\n");
+    let err = SentryAppenderBuilder::from_config(strict, &Deserializers::default())
+        .err()
+        .expect("unknown field accepted");
+    assert!(err.to_string().contains("future_option"), "{}", err);
+
+    let lenient = config("dsn: \"\"\nfuture_option: 1\nlenient_config: true\n");
+    assert!(SentryAppenderBuilder::from_config(lenient, &Deserializers::default()).is_ok());
+}
+
+#[test]
+fn process_info_has_the_pid() {
+    let events = capture(
+        SentryAppender::builder().capture_process_info(true),
+        |appender| append(appender, Level::Error, "boom"),
+    );
+    let process = &events[0].extra["process"];
+    assert_eq!(process["pid"], std::process::id());
+    assert!(process["exe"].is_string());
+}
+
+#[test]
+fn transaction_from_thread_name_or_mdc() {
+    let events = capture(
+        SentryAppender::builder().transaction_from(TransactionSource::ThreadName),
+        |appender| {
+            std::thread::scope(|scope| {
+                std::thread::Builder::new()
+                    .name("billing-worker".to_owned())
+                    .spawn_scoped(scope, || append(appender, Level::Error, "boom"))
+                    .unwrap();
+            })
+        },
+    );
+    assert_eq!(events[0].transaction.as_deref(), Some("billing-worker"));
+
+    let events = capture(
+        SentryAppender::builder().transaction_from("mdc:request_path".parse().unwrap()),
+        |appender| {
+            std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    append(appender, Level::Error, "no entry");
+                    log_mdc::insert("request_path", "/checkout");
+                    append(appender, Level::Error, "with entry");
+                });
+            })
+        },
+    );
+    let transactions: Vec<Option<&str>> = events
+        .iter()
+        .map(|event| event.transaction.as_deref())
+        .collect();
+    assert_eq!(transactions, [None, Some("/checkout")]);
+
+    let events = capture(SentryAppender::builder(), |appender| {
+        append(appender, Level::Error, "boom")
+    });
+    assert_eq!(events[0].transaction, None);
+}
+
+#[test]
+fn logger_source_picks_event_logger() {
+    let logger = |source: LoggerSource| {
+        let events = capture(
+            SentryAppender::builder().logger_source(source),
+            |appender| {
+                appender
+                    .append(
+                        &Record::builder()
+                            .level(Level::Error)
+                            .target("audit")
+                            .module_path(Some("app::billing"))
+                            .args(format_args!("boom"))
+                            .build(),
+                    )
+                    .unwrap()
+            },
+        );
+        events[0].logger.clone()
+    };
+    assert_eq!(logger(LoggerSource::Target).as_deref(), Some("audit"));
+    assert_eq!(
+        logger(LoggerSource::ModulePath).as_deref(),
+        Some("app::billing")
+    );
+    assert_eq!(
+        logger(LoggerSource::Fixed("my-service".to_owned())).as_deref(),
+        Some("my-service")
+    );
+}
+
+#[test]
+fn level_tag_for_every_level() {
+    let levels = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+    let events = capture(
+        SentryAppender::builder()
+            .threshold(LevelFilter::Trace)
+            .send_trace(true)
+            .level_tag(true),
+        |appender| {
+            for level in levels {
+                append(appender, level, "boom");
+            }
+        },
+    );
+    let tags: Vec<&str> = events
+        .iter()
+        .map(|event| &*event.tags["log_level"])
+        .collect();
+    assert_eq!(tags, ["error", "warn", "info", "debug", "trace"]);
+
+    let events = capture(SentryAppender::builder(), |appender| {
+        append(appender, Level::Error, "boom")
+    });
+    assert!(!events[0].tags.contains_key("log_level"));
+}
+
+#[test]
+fn env_tags_come_from_prefixed_variables() {
+    std::env::set_var("SENTRY_TAG_Deploy Region", "eu-west-1");
+    std::env::set_var("SENTRY_TAG_EMPTY_VALUE", "");
+    let events = capture(SentryAppender::builder().env_tags(true), |appender| {
+        append(appender, Level::Error, "boom")
+    });
+    assert_eq!(events[0].tags["deploy_region"], "eu-west-1");
+    assert!(!events[0].tags.contains_key("empty_value"));
+
+    let events = capture(SentryAppender::builder(), |appender| {
+        append(appender, Level::Error, "boom")
+    });
+    assert!(!events[0].tags.contains_key("deploy_region"));
+}
+
+#[test]
+fn build_info_is_read_once_at_build() {
+    std::env::set_var("SENTRY_LOG4RS_TEST_GIT_SHA", "abc1234");
+    std::env::remove_var("SENTRY_LOG4RS_TEST_UNSET");
+    let events = capture(
+        SentryAppender::builder()
+            .build_info("git_sha", "SENTRY_LOG4RS_TEST_GIT_SHA")
+            .build_info("build_time", "SENTRY_LOG4RS_TEST_UNSET"),
+        |appender| {
+            std::env::set_var("SENTRY_LOG4RS_TEST_GIT_SHA", "def5678");
+            append(appender, Level::Error, "boom");
+        },
+    );
+    assert_eq!(events[0].tags["git_sha"], "abc1234");
+    assert!(!events[0].tags.contains_key("build_time"));
+    match events[0].contexts.get("build") {
+        Some(Context::Other(build)) => {
+            assert_eq!(build["git_sha"], "abc1234");
+            assert!(!build.contains_key("build_time"));
+        }
+        context => panic!("unexpected build context {:?}", context),
+    }
+}
+
+#[test]
+fn dsn_takes_str_and_string() {
+    let dsn = "https://public@sentry.invalid/1";
+    let builder = SentryAppender::builder().dsn(dsn);
+    assert_eq!(builder.dsn, dsn);
+    let builder = SentryAppender::builder().dsn(dsn.to_owned());
+    assert_eq!(builder.dsn, dsn);
+}
+
+/// A subscriber keeping just enough state for `Span::current` to work.
+#[cfg(feature = "tracing")]
+#[derive(Default)]
+struct CurrentSpan {
+    spans: std::sync::Mutex<Vec<&'static tracing::Metadata<'static>>>,
+    entered: std::sync::Mutex<Vec<tracing::Id>>,
+}
+
+#[cfg(feature = "tracing")]
+impl tracing::Subscriber for CurrentSpan {
+    fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::Id {
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(span.metadata());
+        tracing::Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, _: &tracing::Id, _: &tracing::span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &tracing::Id, _: &tracing::Id) {}
+
+    fn event(&self, _: &tracing::Event<'_>) {}
+
+    fn enter(&self, span: &tracing::Id) {
+        self.entered.lock().unwrap().push(span.clone());
+    }
+
+    fn exit(&self, _: &tracing::Id) {
+        self.entered.lock().unwrap().pop();
+    }
+
+    fn current_span(&self) -> tracing_core::span::Current {
+        match self.entered.lock().unwrap().last() {
+            Some(id) => {
+                let metadata = self.spans.lock().unwrap()[id.into_u64() as usize - 1];
+                tracing_core::span::Current::new(id.clone(), metadata)
+            }
+            None => tracing_core::span::Current::none(),
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_span_is_attached() {
+    let events = tracing::subscriber::with_default(CurrentSpan::default(), || {
+        capture(SentryAppender::builder().tracing_span(true), |appender| {
+            let span = tracing::info_span!("handle_request", user_id = 7);
+            span.in_scope(|| append(appender, Level::Error, "inside"));
+            append(appender, Level::Error, "outside");
+        })
+    });
+    let span = &events[0].extra["span"];
+    assert_eq!(span["name"], "handle_request");
+    assert_eq!(span["fields"][0], "user_id");
+    assert!(!events[1].extra.contains_key("span"));
+}
+
+/// Logs a record while the worker is held up, returning the event along with the moments
+/// just after it was logged and just before the worker was let go.
+fn capture_held_up(timestamp: TimestampSource) -> (Event<'static>, SystemTime, SystemTime) {
+    let gate = Arc::new(Gate::default());
+    let mut logged = SystemTime::UNIX_EPOCH;
+    let mut released = SystemTime::UNIX_EPOCH;
+    let events = capture(
+        SentryAppender::builder()
+            .encoder(Box::new(GateEncoder(gate.clone())))
+            .async_queue(10)
+            .timestamp(timestamp),
+        |appender| {
+            append(appender, Level::Error, "held up");
+            logged = SystemTime::now();
+            std::thread::sleep(Duration::from_millis(50));
+            released = SystemTime::now();
+            gate.open();
+        },
+    );
+    (events.into_iter().next().unwrap(), logged, released)
+}
+
+#[test]
+fn timestamp_is_taken_at_append_time() {
+    let (event, logged, _) = capture_held_up(TimestampSource::Append);
+    assert!(event.timestamp <= logged);
+}
+
+#[test]
+fn transport_timestamp_is_taken_when_captured() {
+    let (event, _, released) = capture_held_up(TimestampSource::Transport);
+    assert!(event.timestamp >= released);
+}
+
+const TEST_CA: &str = "-----BEGIN CERTIFICATE-----
+MIIBezCCASGgAwIBAgIURMCvyRt0aVKpPdpcgCXh+I+Yah8wCgYIKoZIzj0EAwIw
+EjEQMA4GA1UEAwwHdGVzdC1jYTAgFw0yNjEwMTUxMDE2MzVaGA8yMTI2MDkyMTEw
+MTYzNVowEjEQMA4GA1UEAwwHdGVzdC1jYTBZMBMGByqGSM49AgEGCCqGSM49AwEH
+A0IABBH9M7U4iW6yEnubnwJ/D7sWGpoiHtFITJ6u7gzuP10VDyB8UjIHYhND6k0w
+IanBDC46HnwKsFXSY1woCewm7tajUzBRMB0GA1UdDgQWBBSXYbG4zXYr1YLT+rVx
+LLUWyAmRXDAfBgNVHSMEGDAWgBSXYbG4zXYr1YLT+rVxLLUWyAmRXDAPBgNVHRMB
+Af8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIQDpRr/YTwCiXkUsj8P+QsHGAS6+
+Pqb3OzZd1AaFTUD8VwIgQSpfiZXpLyDK6XV3j0JSyL7Zxd63YsWNUUFA7rIfKfk=
+-----END CERTIFICATE-----
+";
+
+#[test]
+fn ca_certs_are_read_from_the_bundle() {
+    let path = std::env::temp_dir().join(format!("sentry-log4rs-ca-{}.pem", std::process::id()));
+    std::fs::write(&path, TEST_CA).unwrap();
+    let builder = SentryAppender::builder().ca_certs(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(builder.unwrap().ca_certs.len(), 1);
+}
+
+#[test]
+fn missing_ca_certs_are_an_error() {
+    let err = SentryAppender::builder()
+        .ca_certs("/nonexistent/ca.pem")
+        .err()
+        .expect("missing bundle accepted");
+    assert!(err.to_string().contains("/nonexistent/ca.pem"), "{}", err);
+
+    let err = SentryAppenderBuilder::from_config(
+        config("dsn: \"\"\nca_certs: /nonexistent/ca.pem\n"),
+        &Deserializers::default(),
+    )
+    .err()
+    .expect("missing bundle accepted");
+    assert!(err.to_string().contains("/nonexistent/ca.pem"), "{}", err);
+}
+
+#[test]
+fn culprit_is_taken_from_the_location() {
+    let events = capture(
+        SentryAppender::builder().culprit_from_location(true),
+        |appender| {
+            append_from(appender, Level::Error, "app::db", "query failed");
+            append_kv(appender, Level::Error, "no location", &[]);
+        },
+    );
+    assert_eq!(events[0].culprit.as_deref(), Some("app::db:42"));
+    assert_eq!(events[1].culprit.as_deref(), Some("app::module"));
+}
+
+#[test]
+fn custom_hub_events_do_not_reach_the_global_hub() {
+    let mut events = Vec::new();
+    let global = sentry::test::with_captured_events(|| {
+        events = capture(SentryAppender::builder(), |appender| {
+            append(appender, Level::Error, "isolated");
+        });
+    });
+    assert_eq!(messages(&events), ["isolated"]);
+    assert!(global.is_empty());
+}
+
+#[test]
+fn include_location_false_omits_the_location() {
+    let builder = || {
+        SentryAppender::builder()
+            .location_extras(true)
+            .culprit_from_location(true)
+            .backtrace_levels(vec![Level::Error])
+    };
+    let events = capture(builder().include_location(false), |appender| {
+        append_from(appender, Level::Error, "app::db", "query failed");
+    });
+    let event = &events[0];
+    assert!(event.stacktrace.is_none());
+    assert!(event.culprit.is_none());
+    assert!(!event.tags.contains_key("module_path"));
+    assert!(!event.extra.contains_key("file"));
+    assert!(!event.extra.contains_key("line"));
+
+    let events = capture(builder(), |appender| {
+        append_from(appender, Level::Error, "app::db", "query failed");
+    });
+    let event = &events[0];
+    assert!(event.stacktrace.is_some());
+    assert_eq!(event.culprit.as_deref(), Some("app::db:42"));
+    assert_eq!(event.tags["module_path"], "app::db");
+    assert_eq!(event.extra["file"], "src/module.rs");
+    assert_eq!(event.extra["line"], 42);
+}
+
+#[test]
+fn trace_records_are_dropped_unless_send_trace() {
+    let from_config =
+        |yaml| SentryAppenderBuilder::from_config(config(yaml), &Deserializers::default()).unwrap();
+    let events = capture(from_config("dsn: \"\"\nthreshold: trace\n"), |appender| {
+        append(appender, Level::Trace, "trace");
+        append(appender, Level::Debug, "debug");
+    });
+    assert_eq!(messages(&events), ["debug"]);
+
+    let events = capture(
+        from_config("dsn: \"\"\nthreshold: trace\nsend_trace: true\n"),
+        |appender| append(appender, Level::Trace, "trace"),
+    );
+    assert_eq!(messages(&events), ["trace"]);
+}
+
+#[test]
+fn logger_map_prefers_the_longest_prefix() {
+    let events = capture(
+        SentryAppender::builder()
+            .logger_map("app", "app")
+            .logger_map("app::adapters::stripe", "payments.stripe"),
+        |appender| {
+            append_from(appender, Level::Error, "app::adapters::stripe::client", "a");
+            append_from(appender, Level::Error, "app::adapters::paypal", "b");
+            append_from(appender, Level::Error, "other::module", "c");
+        },
+    );
+    let loggers: Vec<_> = events.iter().map(|e| e.logger.as_deref()).collect();
+    assert_eq!(
+        loggers,
+        [Some("payments.stripe"), Some("app"), Some("other::module")]
+    );
+}
+
+#[test]
+fn attach_file_is_included_with_the_event() {
+    let dir = std::env::temp_dir();
+    let small = dir.join(format!("sentry-log4rs-small-{}.txt", std::process::id()));
+    let large = dir.join(format!("sentry-log4rs-large-{}.txt", std::process::id()));
+    std::fs::write(&small, "config snapshot").unwrap();
+    std::fs::write(&large, [b'x'; 64]).unwrap();
+
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .attach_file(&small)
+        .attach_file(&large)
+        .max_attachment_size(32)
+        .hub(hub)
+        .build();
+    append(&appender, Level::Error, "with attachment");
+    appender.flush();
+    std::fs::remove_file(&small).unwrap();
+    std::fs::remove_file(&large).unwrap();
+
+    let envelopes = transport.fetch_and_clear_envelopes();
+    let attachments: Vec<_> = envelopes[0]
+        .items()
+        .filter_map(|item| match item {
+            sentry::protocol::EnvelopeItem::Attachment(attachment) => Some(attachment),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(attachments[0].buffer, b"config snapshot");
+}
+
+#[test]
+fn stats_count_threshold_drops() {
+    let (hub, _transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .threshold(LevelFilter::Error)
+        .hub(hub)
+        .build();
+    append(&appender, Level::Warn, "dropped");
+    append(&appender, Level::Info, "dropped");
+    append(&appender, Level::Error, "sent");
+    let stats = appender.stats();
+    assert_eq!(stats.events_dropped_threshold, 2);
+    assert_eq!(stats.events_sent, 1);
+}
+
+fn fingerprint<'a>(event: &'a Event<'static>) -> Vec<&'a str> {
+    event.fingerprint.iter().map(|part| part.as_ref()).collect()
+}
+
+#[test]
+fn normalized_messages_share_a_fingerprint() {
+    let sent = [
+        "request 9f3a7b2c timed out after 1523ms for \"alice\"",
+        "request 0x1e77 timed out after 87ms for 'bob'",
+        "request deadbeef42 timed out after 2ms for \"\"",
+    ];
+    let events = capture(
+        SentryAppender::builder().normalize_fingerprint(true),
+        |appender| {
+            for msg in sent {
+                append(appender, Level::Error, msg);
+            }
+        },
+    );
+    assert_eq!(messages(&events), sent);
+    for event in &events {
+        assert_eq!(
+            fingerprint(event),
+            ["request <hex> timed out after <int>ms for <str>"]
+        );
+    }
+}
+
+#[test]
+fn normalize_patterns_replace_the_built_in_ones() {
+    let events = capture(
+        SentryAppender::builder()
+            .normalize_fingerprint(true)
+            .normalize_pattern(Regex::new(r"user \w+").unwrap(), "user <name>"),
+        |appender| append(appender, Level::Error, "user alice failed 3 times"),
+    );
+    assert_eq!(fingerprint(&events[0]), ["user <name> failed 3 times"]);
+}
+
+#[test]
+fn logger_source_is_read_from_config() {
+    let source = |yaml| config(yaml).logger_source;
+    assert_eq!(source("dsn: \"\"\n"), LoggerSource::Target);
+    assert_eq!(
+        source("dsn: \"\"\nlogger_source: module_path\n"),
+        LoggerSource::ModulePath
+    );
+    assert_eq!(
+        source("dsn: \"\"\nlogger_source:\n  fixed: billing\n"),
+        LoggerSource::Fixed("billing".to_owned())
+    );
+}
+
+#[test]
+fn config_converts_into_an_appender() {
+    let config = config(
+        r#"
+dsn: ""
+threshold: warn
+encoder:
+  kind: pattern
+  pattern: "{l}: {m}"
+level_tag: severity
+logger_source:
+  fixed: billing
+"#,
+    );
+    let builder = SentryAppenderBuilder::try_from(config.clone()).unwrap();
+    let events = capture(builder, |appender| {
+        append(appender, Level::Info, "dropped");
+        append(appender, Level::Warn, "disk almost full");
+    });
+    assert_eq!(messages(&events), ["WARN: disk almost full"]);
+    assert_eq!(events[0].tags["severity"], "warn");
+    assert_eq!(events[0].logger.as_deref(), Some("billing"));
+
+    assert!(SentryAppender::try_from(config).is_ok());
+}
+
+#[test]
+fn prefix_and_suffix_wrap_the_message() {
+    let builder = || {
+        SentryAppender::builder()
+            .message_prefix("[billing] ")
+            .message_suffix(" (eu-west)")
+    };
+    let events = capture(builder(), |appender| {
+        append(appender, Level::Error, "charge failed")
+    });
+    assert_eq!(messages(&events), ["[billing] charge failed (eu-west)"]);
+
+    let events = capture(builder().max_message_bytes(16), |appender| {
+        append(appender, Level::Error, "charge failed")
+    });
+    assert_eq!(
+        messages(&events),
+        ["[billing] charge… [truncated 17 bytes]"]
+    );
+}
+
+#[test]
+fn fingerprint_key_overrides_the_fingerprint() {
+    let events = capture(
+        SentryAppender::builder()
+            .include_kv(true)
+            .fingerprint(vec!["{target}".to_owned()])
+            .unwrap(),
+        |appender| {
+            append_kv(
+                appender,
+                Level::Error,
+                "single",
+                &[("sentry.fingerprint", &"db")],
+            );
+            append_kv(
+                appender,
+                Level::Error,
+                "list",
+                &[("sentry.fingerprint", &"db, timeout"), ("user", &7)],
+            );
+            append_kv(appender, Level::Error, "absent", &[("user", &7)]);
+        },
+    );
+    assert_eq!(fingerprint(&events[0]), ["db"]);
+    assert_eq!(fingerprint(&events[1]), ["db", "timeout"]);
+    assert_eq!(fingerprint(&events[2]), ["app::module"]);
+    for event in &events {
+        let kv = event.extra["log_record"]["kv"].as_object().unwrap();
+        assert!(!kv.contains_key("sentry.fingerprint"));
+    }
+}
+
+#[test]
+fn fingerprint_encoder_leaves_out_the_context() {
+    let events = capture(
+        SentryAppender::builder()
+            .encoder(Box::new(PatternEncoder::new("[{X(request_id)}] {m}")))
+            .fingerprint_encoder(Box::new(PatternEncoder::new("{t}: {m}"))),
+        |appender| {
+            std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    for request_id in ["r1", "r2"] {
+                        log_mdc::insert("request_id", request_id);
+                        append(appender, Level::Error, "charge failed");
+                    }
+                });
+            })
+        },
+    );
+    assert_eq!(
+        messages(&events),
+        ["[r1] charge failed", "[r2] charge failed"]
+    );
+    for event in &events {
+        assert_eq!(fingerprint(event), ["app::module: charge failed"]);
+    }
+}
+
+#[test]
+fn fingerprints_are_redacted() {
+    let events = capture(
+        SentryAppender::builder()
+            .redact_pattern(Regex::new(r"token=\w+").unwrap())
+            .fingerprint_encoder(Box::new(PatternEncoder::new("{m}"))),
+        |appender| {
+            append(appender, Level::Error, "login with token=abc123 failed");
+            append_kv(
+                appender,
+                Level::Error,
+                "login failed",
+                &[("sentry.fingerprint", &"login, token=abc123")],
+            );
+        },
+    );
+    assert_eq!(fingerprint(&events[0]), ["login with [REDACTED] failed"]);
+    assert_eq!(fingerprint(&events[1]), ["login", "[REDACTED]"]);
+}
+
+#[test]
+fn filter_drops_records_it_rejects() {
+    let events = capture(
+        SentryAppender::builder()
+            .threshold(LevelFilter::Warn)
+            .filter(Box::new(|record| {
+                assert!(record.level() <= Level::Warn);
+                record.args().to_string().contains("panic")
+            })),
+        |appender| {
+            append(appender, Level::Error, "worker panicked");
+            append(appender, Level::Error, "connection reset");
+            append(appender, Level::Info, "panic below the threshold");
+        },
+    );
+    assert_eq!(messages(&events), ["worker panicked"]);
+}
+
+#[test]
+fn self_describe_adds_the_crate_version() {
+    let events = capture(
+        SentryAppender::builder()
+            .self_describe(true)
+            .threshold(LevelFilter::Warn),
+        |appender| append(appender, Level::Error, "boom"),
+    );
+    let context = match &events[0].contexts["sentry_log4rs"] {
+        Context::Other(context) => context,
+        other => panic!("unexpected context {:?}", other),
+    };
+    assert_eq!(context["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(context["threshold"], "WARN");
+    assert_eq!(context["async"], false);
+
+    let events = capture(SentryAppender::builder(), |appender| {
+        append(appender, Level::Error, "boom")
+    });
+    assert!(!events[0].contexts.contains_key("sentry_log4rs"));
+}
+
+/// A pattern encoder writing highlighted output, as it would to a terminal.
+#[derive(Debug)]
+struct Colored(PatternEncoder);
+
+impl Encode for Colored {
+    fn encode(&self, w: &mut dyn log4rs::encode::Write, record: &Record) -> anyhow::Result<()> {
+        self.0
+            .encode(&mut log4rs::encode::writer::ansi::AnsiWriter(w), record)
+    }
+}
+
+#[test]
+fn ansi_escapes_are_stripped() {
+    let colored = || Box::new(Colored(PatternEncoder::new("{h({l})} {m}")));
+    let events = capture(SentryAppender::builder().encoder(colored()), |appender| {
+        append(appender, Level::Error, "défaut 错误");
+        append(
+            appender,
+            Level::Error,
+            "\u{1b}]8;;https://example.com\u{7}link\u{1b}]8;;\u{7}",
+        );
+        append(appender, Level::Error, "lone \u{1b} escape, \u{1b}é kept");
+    });
+    assert_eq!(
+        messages(&events),
+        [
+            "ERROR défaut 错误",
+            "ERROR link",
+            "ERROR lone \u{1b} escape, \u{1b}é kept"
+        ]
+    );
+
+    let events = capture(
+        SentryAppender::builder()
+            .encoder(colored())
+            .strip_ansi(false),
+        |appender| append(appender, Level::Error, "boom"),
+    );
+    assert!(messages(&events)[0].contains("\u{1b}["));
+}
+
+#[test]
+fn set_transaction_names_the_events_transaction() {
+    let events = capture(SentryAppender::builder(), |appender| {
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                SentryAppender::set_transaction(Some("POST /checkout".to_owned()));
+                append(appender, Level::Error, "during");
+                SentryAppender::set_transaction(None);
+                append(appender, Level::Error, "after");
+            });
+        })
+    });
+    let transactions: Vec<Option<&str>> = events
+        .iter()
+        .map(|event| event.transaction.as_deref())
+        .collect();
+    assert_eq!(transactions, [Some("POST /checkout"), None]);
+}
+
+#[test]
+fn multiline_modes() {
+    let multiline = |mode| {
+        capture(SentryAppender::builder().multiline(mode), |appender| {
+            append(appender, Level::Error, "config:\n  a: 1\r\n  b: 2\n");
+            append(appender, Level::Error, "single line\n");
+        })
+    };
+
+    let events = multiline(MultilineMode::Raw);
+    assert_eq!(
+        messages(&events),
+        ["config:\n  a: 1\r\n  b: 2\n", "single line\n"]
+    );
+    assert!(!events[0].extra.contains_key("full_message"));
+
+    let events = multiline(MultilineMode::Split);
+    assert_eq!(messages(&events), ["config:", "single line"]);
+    assert_eq!(events[0].extra["full_message"], "config:\n  a: 1\r\n  b: 2");
+    assert!(!events[1].extra.contains_key("full_message"));
+
+    let events = multiline(MultilineMode::Join);
+    assert_eq!(
+        messages(&events),
+        ["config:\\n  a: 1\\n  b: 2", "single line"]
+    );
+}
+
+#[test]
+fn json_output_becomes_a_structured_event() {
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .encoder(Box::new(log4rs::encode::json::JsonEncoder::new()))
+        .message_format(MessageFormat::Json)
+        .hub(hub)
+        .build();
+    append_from(&appender, Level::Error, "app::db", "query failed");
+    let events = transport.fetch_and_clear_events();
+    assert_eq!(messages(&events), ["query failed"]);
+    let extra = &events[0].extra;
+    assert_eq!(extra["module_path"], "app::db");
+    assert_eq!(extra["file"], "src/module.rs");
+    assert_eq!(extra["line"], 42);
+    assert!(extra.contains_key("thread"));
+    assert!(!extra.contains_key("message"));
+    assert!(!extra.contains_key("level"));
+    assert!(!extra.contains_key("target"));
+    assert_eq!(appender.stats().invalid_json, 0);
+
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .message_format(MessageFormat::Json)
+        .hub(hub)
+        .build();
+    append(&appender, Level::Error, "not json");
+    assert_eq!(messages(&transport.fetch_and_clear_events()), ["not json"]);
+    assert_eq!(appender.stats().invalid_json, 1);
+}
+
+/// A transport holding on to envelopes until it's flushed, sending each flush's worth as
+/// a batch.
+#[derive(Default)]
+struct BufferedTransport {
+    pending: std::sync::Mutex<Vec<sentry::Envelope>>,
+    batches: std::sync::Mutex<Vec<Vec<sentry::Envelope>>>,
+}
+
+impl BufferedTransport {
+    fn batch_sizes(&self) -> Vec<usize> {
+        self.batches.lock().unwrap().iter().map(Vec::len).collect()
+    }
+}
+
+impl Transport for BufferedTransport {
+    fn send_envelope(&self, envelope: sentry::Envelope) {
+        self.pending.lock().unwrap().push(envelope);
+    }
+
+    fn flush(&self, _timeout: Duration) -> bool {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if !pending.is_empty() {
+            self.batches.lock().unwrap().push(pending);
+        }
+        true
+    }
+}
+
+fn buffered_hub() -> (Arc<Hub>, Arc<BufferedTransport>) {
+    let transport = Arc::new(BufferedTransport::default());
+    let options = ClientOptions {
+        dsn: Some("https://public@sentry.invalid/1".parse().unwrap()),
+        transport: Some(Arc::new(transport.clone())),
+        ..Default::default()
+    };
+    let hub = Arc::new(Hub::new(Some(Arc::new(options.into())), Default::default()));
+    (hub, transport)
+}
+
+#[test]
+fn flush_interval_flushes_without_flush() {
+    let (hub, transport) = buffered_hub();
+    let appender = SentryAppender::builder()
+        .flush_interval(Duration::from_millis(10))
+        .hub(hub)
+        .build();
+    append(&appender, Level::Error, "boom");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while transport.batch_sizes().is_empty() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(transport.batch_sizes(), [1]);
+}
+
+#[test]
+fn empty_messages_are_skipped() {
+    let log = |appender: &SentryAppender| {
+        append(appender, Level::Error, "");
+        append(appender, Level::Error, " \n\t");
+        append(appender, Level::Error, "boom");
+    };
+    let events = capture(SentryAppender::builder().skip_empty_messages(true), log);
+    assert_eq!(messages(&events), ["boom"]);
+
+    let events = capture(SentryAppender::builder(), log);
+    assert_eq!(events.len(), 3);
+}
+
+#[test]
+fn long_messages_are_truncated_at_a_char_boundary() {
+    let oversized = "x".repeat(1024 * 1024);
+    let events = capture(SentryAppender::builder(), |appender| {
+        append(appender, Level::Error, &oversized);
+        append(appender, Level::Error, &"y".repeat(8 * 1024));
+    });
+    assert_eq!(
+        events[0].message.as_deref(),
+        Some(
+            format!(
+                "{}… [truncated {} bytes]",
+                &oversized[..8192],
+                1024 * 1024 - 8192
+            )
+            .as_str()
+        )
+    );
+    assert_eq!(events[0].extra["message_bytes"], 1024 * 1024);
+    assert_eq!(events[1].message.as_ref().unwrap().len(), 8 * 1024);
+    assert!(!events[1].extra.contains_key("message_bytes"));
+
+    // The 4-byte emoji straddles the limit, so it's dropped as a whole.
+    let events = capture(SentryAppender::builder().max_message_bytes(6), |appender| {
+        append(appender, Level::Error, "abcd😀ef")
+    });
+    assert_eq!(messages(&events), ["abcd… [truncated 6 bytes]"]);
+    assert_eq!(events[0].extra["message_bytes"], 10);
+
+    let events = capture(SentryAppender::builder().max_message_bytes(0), |appender| {
+        append(appender, Level::Error, &oversized)
+    });
+    assert_eq!(events[0].message.as_ref().unwrap().len(), oversized.len());
+}
+
+/// An encoder writing the message followed by bytes which aren't valid UTF-8.
+#[derive(Debug)]
+struct InvalidUtf8Encoder;
+
+impl Encode for InvalidUtf8Encoder {
+    fn encode(&self, w: &mut dyn log4rs::encode::Write, record: &Record) -> anyhow::Result<()> {
+        write!(w, "{} ", record.args())?;
+        w.write_all(b"\xff\xfe")?;
+        Ok(())
+    }
+}
+
+#[test]
+fn invalid_utf8_is_delivered_unless_strict() {
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .encoder(Box::new(InvalidUtf8Encoder))
+        .hub(hub)
+        .build();
+    append(&appender, Level::Error, "bytes");
+    assert_eq!(
+        messages(&transport.fetch_and_clear_events()),
+        ["bytes \u{fffd}\u{fffd}"]
+    );
+    assert_eq!(appender.stats().invalid_utf8, 1);
+
+    let policy = config("dsn: \"\"\nutf8: strict\n").encoding_error_policy;
+    assert_eq!(policy, EncodingErrorPolicy::Fail);
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .encoder(Box::new(InvalidUtf8Encoder))
+        .encoding_error_policy(policy)
+        .hub(hub)
+        .build();
+    let record = Record::builder()
+        .level(Level::Error)
+        .args(format_args!("bytes"))
+        .build();
+    assert!(appender.append(&record).is_err());
+    assert!(transport.fetch_and_clear_events().is_empty());
+    assert_eq!(appender.stats().invalid_utf8, 1);
+}
+
+#[test]
+fn static_extras_coexist_with_the_location() {
+    let config = config(
+        r#"
+dsn: ""
+location_extras: true
+extra:
+  build: 1234
+  flags:
+    checkout_v2: true
+  line: 1
+"#,
+    );
+    let builder = SentryAppenderBuilder::try_from(config).unwrap();
+    let events = capture(builder, |appender| append(appender, Level::Error, "boom"));
+    let extra = &events[0].extra;
+    assert_eq!(extra["build"], 1234);
+    assert_eq!(extra["flags"]["checkout_v2"], true);
+    assert_eq!(extra["file"], "src/module.rs");
+    assert_eq!(extra["line"], 42);
+}
+
+#[test]
+fn traces_sample_rate_is_forwarded() {
+    let rate = SampleRate::try_from(0.25).unwrap();
+    let options = SentryAppender::builder()
+        .traces_sample_rate(rate)
+        .client_options();
+    assert_eq!(options.traces_sample_rate, 0.25);
+}
+
+#[test]
+fn events_are_associated_with_the_transaction() {
+    let options = ClientOptions {
+        traces_sample_rate: 1.0,
+        ..Default::default()
+    };
+    let envelopes = sentry::test::with_captured_envelopes_options(
+        || {
+            let appender = SentryAppender::builder().hub(Hub::current()).build();
+            let guard = SentryAppender::start_transaction("POST /checkout", "http.server");
+            append(&appender, Level::Error, "charge failed");
+            guard.finish();
+        },
+        options,
+    );
+    let event = envelopes[0].event().expect("no event");
+    let transaction = envelopes[1].items().find_map(|item| match item {
+        sentry::protocol::EnvelopeItem::Transaction(transaction) => Some(transaction),
+        _ => None,
+    });
+    let transaction = transaction.expect("no transaction");
+    assert_eq!(transaction.name.as_deref(), Some("POST /checkout"));
+    assert_eq!(event.transaction.as_deref(), Some("POST /checkout"));
+    let trace_id = |contexts: &BTreeMap<String, Context>| match contexts.get("trace") {
+        Some(Context::Trace(trace)) => trace.trace_id,
+        other => panic!("unexpected trace context {:?}", other),
+    };
+    assert_eq!(trace_id(&event.contexts), trace_id(&transaction.contexts));
+}
+
+#[test]
+fn scrub_patterns_cover_message_extras_and_tags() {
+    let config = config(
+        r#"
+dsn: ""
+include_kv: true
+extra:
+  auth: "Bearer abc.def"
+scrub:
+  - "Bearer [\\w.]+"
+  - "key-\\w+"
+"#,
+    );
+    let builder = SentryAppenderBuilder::try_from(config)
+        .unwrap()
+        .target_as_tag(true);
+    let events = capture(builder, |appender| {
+        append_kv(
+            appender,
+            Level::Error,
+            "sent Bearer abc.def with key-123",
+            &[("api_key", &"key-456")],
+        );
+        append_from(appender, Level::Error, "key-789", "boom");
+    });
+    let event = &events[0];
+    assert_eq!(messages(&events)[0], "sent [REDACTED] with [REDACTED]");
+    assert_eq!(event.extra["auth"], "[REDACTED]");
+    assert_eq!(event.extra["log_record"]["kv"]["api_key"], "[REDACTED]");
+    assert_eq!(events[1].tags["target"], "[REDACTED]");
+}
+
+#[test]
+fn invalid_scrub_patterns_are_named() {
+    let err = SentryAppenderBuilder::from_config(
+        config("dsn: \"\"\nscrub: [\"token=(\"]\n"),
+        &Deserializers::default(),
+    )
+    .err()
+    .expect("invalid pattern accepted");
+    assert!(err.to_string().contains("token=("), "{}", err);
+}
+
+#[test]
+fn extras_are_limited_in_key_order() {
+    let builder = || {
+        SentryAppender::builder()
+            .extra("d", 4)
+            .extra("b", "a rather long value")
+            .extra("c", 3)
+            .extra("a", 1)
+            .max_extra_entries(2)
+            .max_extra_value_bytes(8)
+    };
+    for _ in 0..2 {
+        let events = capture(builder(), |appender| append(appender, Level::Error, "boom"));
+        let extra = &events[0].extra;
+        assert_eq!(extra.keys().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(extra["a"], 1);
+        assert_eq!(extra["b"], "a rather… [truncated 11 bytes]");
+        assert_eq!(events[0].tags["extras_truncated"], "true");
+    }
+
+    let events = capture(SentryAppender::builder().extra("a", 1), |appender| {
+        append(appender, Level::Error, "boom")
+    });
+    assert!(!events[0].tags.contains_key("extras_truncated"));
+}
+
+#[test]
+fn routes_send_levels_to_their_own_dsn() {
+    let (warn_dsn, warn_requests) = transport::tests::serve(&["200 OK"]);
+    let (error_dsn, error_requests) = transport::tests::serve(&["200 OK"]);
+    let events = capture(
+        SentryAppender::builder()
+            .threshold(LevelFilter::Info)
+            .route(Level::Warn, Level::Warn, warn_dsn)
+            .route(Level::Error, Level::Error, error_dsn),
+        |appender| {
+            append(appender, Level::Warn, "disk almost full");
+            append(appender, Level::Error, "charge failed");
+            append(appender, Level::Info, "unrouted");
+        },
+    );
+    assert_eq!(messages(&events), ["unrouted"]);
+    for (requests, message) in [
+        (warn_requests, "disk almost full"),
+        (error_requests, "charge failed"),
+    ] {
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let body = String::from_utf8_lossy(&requests[0].1);
+        assert!(body.contains(message), "{}", body);
+    }
+}
+
+#[test]
+fn encoding_error_policies() {
+    let policy = |yaml| config(yaml).encoding_error_policy;
+    assert_eq!(policy("dsn: \"\"\n"), EncodingErrorPolicy::Lossy);
+    assert_eq!(
+        policy("dsn: \"\"\nencoding_error_policy: fail\n"),
+        EncodingErrorPolicy::Fail
+    );
+    assert_eq!(
+        policy("dsn: \"\"\nencoding_error_policy:\n  replace: \"?\"\n"),
+        EncodingErrorPolicy::Replace('?')
+    );
+
+    let capture_with = |policy| {
+        let (hub, transport) = test_hub();
+        let appender = SentryAppender::builder()
+            .encoder(Box::new(InvalidUtf8Encoder))
+            .encoding_error_policy(policy)
+            .hub(hub)
+            .build();
+        let record = Record::builder()
+            .level(Level::Error)
+            .args(format_args!("bytes"))
+            .build();
+        let result = appender.append(&record);
+        (result, transport.fetch_and_clear_events())
+    };
+    let (result, events) = capture_with(EncodingErrorPolicy::Fail);
+    assert!(result.is_err());
+    assert!(events.is_empty());
+    let (_, events) = capture_with(EncodingErrorPolicy::Lossy);
+    assert_eq!(messages(&events), ["bytes \u{fffd}\u{fffd}"]);
+    let (_, events) = capture_with(EncodingErrorPolicy::Replace('?'));
+    assert_eq!(messages(&events), ["bytes ??"]);
+}
+
+#[test]
+fn default_pattern_is_used_without_an_encoder() {
+    let events = capture(
+        SentryAppender::builder().default_pattern("{l} {t} - {m}"),
+        |appender| append(appender, Level::Error, "boom"),
+    );
+    assert_eq!(messages(&events), ["ERROR app::module - boom"]);
+
+    let events = capture(
+        SentryAppender::builder()
+            .default_pattern("{l} {t} - {m}")
+            .encoder(Box::new(PatternEncoder::new("{m}!"))),
+        |appender| append(appender, Level::Error, "boom"),
+    );
+    assert_eq!(messages(&events), ["boom!"]);
+
+    let events = capture(SentryAppender::builder(), |appender| {
+        append(appender, Level::Error, "boom")
+    });
+    assert_eq!(messages(&events), ["boom"]);
+}
+
+#[test]
+fn include_and_exclude_targets() {
+    let events = capture(
+        SentryAppender::builder()
+            .encoder(Box::new(GateEncoder::opened()))
+            .include_targets(vec!["app".to_owned(), "sqlx".to_owned()])
+            .exclude_targets(vec!["app::noisy".to_owned(), "sqlx".to_owned()]),
+        |appender| {
+            append_from(appender, Level::Error, "app::db", "app::db");
+            append_from(appender, Level::Error, "app::noisy::pool", "panic");
+            append_from(appender, Level::Error, "sqlx::query", "panic");
+            append_from(appender, Level::Error, "sqlxx", "sqlxx");
+            append_from(appender, Level::Error, "hyper", "hyper");
+        },
+    );
+    assert_eq!(messages(&events), ["app::db"]);
+
+    let events = capture(
+        SentryAppender::builder().exclude_targets(vec!["sqlx".to_owned()]),
+        |appender| {
+            append_from(appender, Level::Error, "sqlx::query", "sqlx::query");
+            append_from(appender, Level::Error, "sqlxx", "sqlxx");
+            append_from(appender, Level::Error, "hyper", "hyper");
+        },
+    );
+    assert_eq!(messages(&events), ["sqlxx", "hyper"]);
+}
+
+#[test]
+fn client_is_only_returned_when_enabled() {
+    let (hub, _transport) = test_hub();
+    let appender = SentryAppender::builder().hub(hub.clone()).build();
+    let client = appender.client().expect("no client");
+    assert!(Arc::ptr_eq(&client, &hub.client().unwrap()));
+
+    let appender = SentryAppender::builder().enabled(false).hub(hub).build();
+    assert!(appender.client().is_none());
+
+    let disabled = Hub::new(
+        Some(Arc::new(ClientOptions::default().into())),
+        Default::default(),
+    );
+    let appender = SentryAppender::builder().hub(Arc::new(disabled)).build();
+    assert!(appender.client().is_none());
+}
+
+#[test]
+fn target_thresholds_prefer_the_longest_prefix() {
+    let config = config(
+        r#"
+dsn: ""
+threshold: error
+breadcrumb_threshold: info
+target_thresholds:
+  app: warn
+  app::payments: info
+  app::payments::retry: off
+"#,
+    );
+    let builder = SentryAppenderBuilder::try_from(config).unwrap();
+    let events = capture(builder, |appender| {
+        append_from(
+            appender,
+            Level::Info,
+            "app::payments::card",
+            "payments info",
+        );
+        append_from(
+            appender,
+            Level::Debug,
+            "app::payments::card",
+            "payments debug",
+        );
+        append_from(appender, Level::Warn, "app::db", "app warn");
+        append_from(appender, Level::Info, "app::db", "app info");
+        append_from(
+            appender,
+            Level::Error,
+            "app::payments::retry",
+            "retry error",
+        );
+        append_from(appender, Level::Warn, "hyper", "hyper warn");
+        append_from(appender, Level::Error, "hyper", "hyper error");
+    });
+    assert_eq!(
+        messages(&events),
+        ["payments info", "app warn", "hyper error"]
+    );
+    // Records of an `off` target don't become breadcrumbs either.
+    let breadcrumbs: Vec<_> = events[2]
+        .breadcrumbs
+        .iter()
+        .map(|breadcrumb| breadcrumb.message.as_deref().unwrap_or_default())
+        .collect();
+    assert_eq!(breadcrumbs, ["app info", "hyper warn"]);
+}
+
+#[test]
+fn release_from_vergen_sets_release_and_tags() {
+    let git = GitInfo {
+        sha: Some("4f2c1e9"),
+        branch: Some("main"),
+    };
+    let builder = SentryAppender::builder().release_from_vergen(git);
+    assert_eq!(builder.client_options().release.as_deref(), Some("4f2c1e9"));
+    let events = capture(builder, |appender| append(appender, Level::Error, "boom"));
+    assert_eq!(events[0].tags["git_commit"], "4f2c1e9");
+    assert_eq!(events[0].tags["git_branch"], "main");
+
+    let builder = SentryAppender::builder()
+        .release("my-app@1.4.2")
+        .release_from_vergen(GitInfo::default());
+    assert_eq!(
+        builder.client_options().release.as_deref(),
+        Some("my-app@1.4.2")
+    );
+    let events = capture(builder, |appender| append(appender, Level::Error, "boom"));
+    assert!(!events[0].tags.contains_key("git_commit"));
+    assert!(!events[0].tags.contains_key("git_branch"));
+}
+
+#[test]
+fn ignore_and_only_messages() {
+    let filters = config(
+        r#"
+dsn: ""
+ignore_messages:
+  - "connection reset by peer"
+only_messages:
+  - "^(connection|charge)"
+"#,
+    );
+    let (hub, transport) = test_hub();
+    let appender = SentryAppenderBuilder::try_from(filters)
+        .unwrap()
+        .hub(hub)
+        .build();
+    for msg in [
+        "connection reset by peer",
+        "connection refused",
+        "charge failed",
+        "disk full",
+    ] {
+        append(&appender, Level::Error, msg);
+    }
+    assert_eq!(
+        messages(&transport.fetch_and_clear_events()),
+        ["connection refused", "charge failed"]
+    );
+    assert_eq!(appender.stats().events_dropped_message, 2);
+
+    let err = SentryAppenderBuilder::from_config(
+        config("dsn: \"\"\nignore_messages: [\"reset (\"]\n"),
+        &Deserializers::default(),
+    )
+    .err()
+    .expect("invalid pattern accepted");
+    assert!(err.to_string().contains("ignore_messages"), "{}", err);
+}
+
+#[test]
+fn level_key_value_overrides_the_level() {
+    let events = capture(
+        SentryAppender::builder()
+            .threshold(LevelFilter::Warn)
+            .include_kv(true),
+        |appender| {
+            append_kv(
+                appender,
+                Level::Warn,
+                "escalated",
+                &[("sentry.level", &"error")],
+            );
+            append_kv(
+                appender,
+                Level::Warn,
+                "as warn",
+                &[("sentry.level", &"WARN")],
+            );
+            append_kv(
+                appender,
+                Level::Warn,
+                "unknown",
+                &[("sentry.level", &"loud")],
+            );
+        },
+    );
+    let levels: Vec<SentryLevel> = events.iter().map(|event| event.level).collect();
+    assert_eq!(
+        levels,
+        [
+            SentryLevel::Error,
+            SentryLevel::Warning,
+            SentryLevel::Warning
+        ]
+    );
+    assert!(!events[0].extra["log_record"]["kv"]
+        .as_object()
+        .unwrap()
+        .contains_key("sentry.level"));
+}
+
+#[test]
+fn rate_limit_caps_events_after_the_burst() {
+    let clock = Arc::new(clock::MockClock::new());
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .max_events_per_minute(60)
+        .burst(3)
+        .clock(clock.clone())
+        .hub(hub)
+        .build();
+    for msg in ["1", "2", "3", "4", "5"] {
+        append(&appender, Level::Error, msg);
+    }
+    let events = transport.fetch_and_clear_events();
+    assert_eq!(messages(&events), ["1", "2", "3"]);
+    assert!(events
+        .iter()
+        .all(|event| !event.extra.contains_key("rate_limited_dropped")));
+    assert_eq!(appender.stats().events_dropped_ratelimit, 2);
+
+    clock.advance(Duration::from_secs(1));
+    append(&appender, Level::Error, "6");
+    append(&appender, Level::Error, "7");
+    let events = transport.fetch_and_clear_events();
+    assert_eq!(messages(&events), ["6"]);
+    assert_eq!(events[0].extra["rate_limited_dropped"], 2);
+    assert_eq!(appender.stats().events_dropped_ratelimit, 3);
+}
+
+#[test]
+fn events_the_client_discards_are_counted_apart_from_rate_limits() {
+    let options = ClientOptions {
+        dsn: Some("https://public@sentry.invalid/1".parse().unwrap()),
+        transport: Some(Arc::new(TestTransport::new())),
+        before_send: Some(Arc::new(|_| None)),
+        ..Default::default()
+    };
+    let hub = Arc::new(Hub::new(Some(Arc::new(options.into())), Default::default()));
+    let appender = SentryAppender::builder().hub(hub).build();
+    append(&appender, Level::Error, "discarded");
+    let stats = appender.stats();
+    assert_eq!(stats.events_dropped_client, 1);
+    assert_eq!(stats.events_dropped_ratelimit, 0);
+    assert_eq!(stats.events_sent, 0);
+}
+
+#[test]
+fn disabled_client_skips_encoding() {
+    for builder in [
+        SentryAppender::builder(),
+        SentryAppender::builder().enabled(false),
+    ] {
+        let appender = builder.encoder(Box::new(GateEncoder::opened())).build();
+        append(&appender, Level::Error, "panic");
+        assert_eq!(appender.stats().events_sent, 0);
+    }
+}
+
+#[test]
+fn fingerprints_have_independent_budgets() {
+    let clock = Arc::new(clock::MockClock::new());
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .per_fingerprint_limit(2)
+        .per_fingerprint_window(Duration::from_secs(300))
+        .clock(clock.clone())
+        .hub(hub)
+        .build();
+    for msg in ["a", "b", "a", "a", "b", "a"] {
+        append(&appender, Level::Error, msg);
+    }
+    assert_eq!(
+        messages(&transport.fetch_and_clear_events()),
+        ["a", "b", "a", "b"]
+    );
+
+    clock.advance(Duration::from_secs(300));
+    append(&appender, Level::Error, "a");
+    append(&appender, Level::Error, "b");
+    let events = transport.fetch_and_clear_events();
+    assert_eq!(messages(&events), ["a", "b"]);
+    assert_eq!(events[0].extra["suppressed_count"], 2);
+    assert!(!events[1].extra.contains_key("suppressed_count"));
+}
+
+#[test]
+fn dedup_window_drops_repeats() {
+    let clock = Arc::new(clock::MockClock::new());
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .dedup_window(Duration::from_secs(60))
+        .dedup_max_entries(2)
+        .clock(clock.clone())
+        .hub(hub)
+        .build();
+    for msg in ["retry failed", "retry failed", "retry failed"] {
+        append(&appender, Level::Error, msg);
+    }
+    assert_eq!(
+        messages(&transport.fetch_and_clear_events()),
+        ["retry failed"]
+    );
+    assert_eq!(appender.stats().events_dropped_duplicate, 2);
+
+    clock.advance(Duration::from_secs(60));
+    append(&appender, Level::Error, "retry failed");
+    let events = transport.fetch_and_clear_events();
+    assert_eq!(messages(&events), ["retry failed"]);
+    assert_eq!(events[0].extra["duplicate_count"], 2);
+
+    // Each new message evicts the least recently seen one, which is then sent again.
+    for msg in ["a", "b", "retry failed", "a"] {
+        clock.advance(Duration::from_secs(1));
+        append(&appender, Level::Error, msg);
+    }
+    assert_eq!(
+        messages(&transport.fetch_and_clear_events()),
+        ["a", "b", "retry failed", "a"]
+    );
+}
+
+#[test]
+fn full_queue_counts_dropped_records() {
+    for policy in [QueueFullPolicy::DropNew, QueueFullPolicy::DropOldest] {
+        let gate = Arc::new(Gate::default());
+        let (hub, _transport) = test_hub();
+        let appender = SentryAppender::builder()
+            .encoder(Box::new(GateEncoder(gate.clone())))
+            .async_queue(2)
+            .queue_full(policy)
+            .hub(hub)
+            .build();
+        occupy_worker(&appender, "1");
+        for msg in ["2", "3", "4", "5"] {
+            append(&appender, Level::Error, msg);
+        }
+        assert_eq!(appender.queue_depth(), 2);
+        assert_eq!(appender.stats().events_dropped_queue_full, 2);
+        gate.open();
+        appender.flush();
+        assert_eq!(appender.queue_depth(), 0);
+        assert_eq!(appender.stats().events_sent, 3);
+    }
+}
+
+#[test]
+fn with_scope_tags_events_logged_within() {
+    let events = capture(SentryAppender::builder(), |appender| {
+        appender.with_scope(
+            |scope| scope.set_tag("tenant", "acme"),
+            || {
+                appender.with_scope(
+                    |scope| scope.set_tag("job", "import"),
+                    || append(appender, Level::Error, "nested"),
+                );
+                append(appender, Level::Error, "inside");
+            },
+        );
+        append(appender, Level::Error, "outside");
+    });
+    let tags: Vec<(Option<&str>, Option<&str>)> = events
+        .iter()
+        .map(|event| {
+            let tag = |key| event.tags.get(key).map(String::as_str);
+            (tag("tenant"), tag("job"))
+        })
+        .collect();
+    assert_eq!(
+        tags,
+        [
+            (Some("acme"), Some("import")),
+            (Some("acme"), None),
+            (None, None)
+        ]
+    );
+}
+
+#[test]
+fn sampled_out_records_are_counted() {
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .threshold(LevelFilter::Warn)
+        .level_sample_rate(Level::Warn, SampleRate::try_from(0.0).unwrap())
+        .hub(hub)
+        .build();
+    append(&appender, Level::Warn, "sampled out");
+    append(&appender, Level::Error, "kept");
+    assert_eq!(messages(&transport.fetch_and_clear_events()), ["kept"]);
+    assert_eq!(appender.stats().events_dropped_sampled, 1);
+}
+
+#[test]
+fn target_is_only_tagged_when_enabled() {
+    let events = capture(SentryAppender::builder().target_as_tag(true), |appender| {
+        append_from(appender, Level::Error, "app::db", "boom")
+    });
+    assert_eq!(events[0].tags["target"], "app::db");
+
+    let events = capture(SentryAppender::builder(), |appender| {
+        append_from(appender, Level::Error, "app::db", "boom")
+    });
+    assert!(!events[0].tags.contains_key("target"));
+}
+
+#[test]
+fn drops_are_summarized_periodically() {
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .ignore_message(Regex::new("benign").unwrap())
+        .exclude_targets(vec!["hyper".to_owned()])
+        .filter(Box::new(|record| record.target() != "app::noisy"))
+        .skip_empty_messages(true)
+        .dedup_window(Duration::from_secs(60))
+        .report_drops_every(Duration::from_millis(20))
+        .hub(hub)
+        .build();
+    for msg in ["benign", "benign", "retry failed", "retry failed", " "] {
+        append(&appender, Level::Error, msg);
+    }
+    append_from(&appender, Level::Error, "hyper::client", "excluded");
+    append_from(&appender, Level::Error, "app::noisy", "filtered");
+    assert_eq!(appender.stats().events_dropped_filtered, 3);
+    let mut events = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while events.len() < 2 && Instant::now() < deadline {
+        events.extend(transport.fetch_and_clear_events());
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(
+        messages(&events),
+        ["retry failed", "sentry-log4rs dropped 6 records"]
+    );
+    let summary = &events[1];
+    assert_eq!(summary.logger.as_deref(), Some("sentry_log4rs"));
+    assert_eq!(summary.level, SentryLevel::Info);
+    for (reason, count) in [
+        ("rate_limited", 0),
+        ("deduped", 1),
+        ("sampled", 0),
+        ("filtered", 5),
+        ("queue_full", 0),
+        ("client", 0),
+    ] {
+        assert_eq!(summary.extra[reason], count, "{}", reason);
+    }
+
+    // Nothing more was dropped, so nothing more is reported.
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(transport.fetch_and_clear_events().is_empty());
+}
+
+#[test]
+fn config_round_trips_through_yaml() {
+    let original = config(
+        r#"
+dsn: "https://public@sentry.invalid/1"
+encoder:
+  kind: pattern
+  pattern: "{l} {m}"
+threshold: warn
+target_thresholds:
+  app::payments: info
+logger_source:
+  fixed: billing
+culprit_source: module_path
+encoding_error_policy:
+  replace: "?"
+retry:
+  max_retries: 3
+  initial_backoff: 1s
+  max_backoff: 30s
+dedup_window: 1m
+level_sample_rates:
+  warn: 0.05
+repeat_policy:
+  first: 5
+  then_sample: 0.01
+  window: 10m
+routes:
+  - min_level: warn
+    max_level: warn
+    dsn: "https://other@sentry.invalid/2"
+extra:
+  flags:
+    checkout_v2: true
+"#,
+    );
+    let yaml = serde_yaml::to_string(&original).unwrap();
+    assert_eq!(config(&yaml), original);
+
+    let redacted = original.to_redacted_yaml().unwrap();
+    assert!(!redacted.contains("public@"), "{}", redacted);
+    assert!(!redacted.contains("other@"), "{}", redacted);
+    assert!(redacted.contains("sentry.invalid/1"), "{}", redacted);
+}
+
+#[test]
+fn platform_defaults_to_rust() {
+    let events = capture(SentryAppender::builder(), |appender| {
+        append(appender, Level::Error, "boom")
+    });
+    assert_eq!(events[0].platform, "rust");
+
+    let builder = SentryAppenderBuilder::try_from(config("dsn: \"\"\nplatform: native\n"));
+    let events = capture(builder.unwrap(), |appender| {
+        append(appender, Level::Error, "boom")
+    });
+    assert_eq!(events[0].platform, "native");
+}
+
+#[test]
+fn culprit_source_sets_the_transaction() {
+    let transaction = |yaml: &str| {
+        let source = config(&format!("dsn: \"\"\nculprit_source: {}\n", yaml)).culprit_source;
+        let events = capture(
+            SentryAppender::builder().culprit_source(source.unwrap()),
+            |appender| {
+                append_from(appender, Level::Error, "app::db", "with location");
+                append_kv(appender, Level::Error, "without location", &[]);
+            },
+        );
+        events
+            .into_iter()
+            .map(|event| event.transaction)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        transaction("\"file:line\""),
+        [Some("src/module.rs:42".to_owned()), None]
+    );
+    assert_eq!(
+        transaction("module_path"),
+        [Some("app::db".to_owned()), Some("app::module".to_owned())]
+    );
+    assert_eq!(
+        transaction("\n  fixed: checkout"),
+        [Some("checkout".to_owned()), Some("checkout".to_owned())]
+    );
+    assert_eq!(config("dsn: \"\"\n").culprit_source, None);
+}
+
+#[test]
+fn threshold_defaults_to_error() {
+    let log = |appender: &SentryAppender| {
+        append(appender, Level::Warn, "warn");
+        append(appender, Level::Error, "error");
+    };
+    let events = capture(SentryAppender::builder(), log);
+    assert_eq!(messages(&events), ["error"]);
+    let builder = SentryAppenderBuilder::try_from(config("dsn: \"\"\n")).unwrap();
+    let events = capture(builder, log);
+    assert_eq!(messages(&events), ["error"]);
+}
+
+#[test]
+fn threshold_off_disables_the_appender() {
+    let builder = SentryAppenderBuilder::try_from(config("dsn: \"\"\nthreshold: off\n"))
+        .unwrap()
+        .encoder(Box::new(GateEncoder::opened()));
+    let (hub, transport) = test_hub();
+    let appender = builder.hub(hub).build();
+    append(&appender, Level::Error, "panic");
+    assert!(appender.client().is_none());
+    assert!(transport.fetch_and_clear_events().is_empty());
+}
+
+#[test]
+fn enabled_false_disables_the_appender() {
+    let enabled = |yaml: &str| config(&format!("dsn: \"\"\nenabled: {}\n", yaml)).enabled;
+    assert!(config("dsn: \"\"\n").enabled);
+    assert!(!enabled("false"));
+    assert!(!enabled("\"${SENTRY_LOG4RS_TEST_UNSET:-false}\""));
+    std::env::set_var("SENTRY_LOG4RS_TEST_ENABLED", "true");
+    assert!(enabled("\"${SENTRY_LOG4RS_TEST_ENABLED}\""));
+    let err = serde_yaml::from_str::<SentryAppenderConfig>("dsn: \"\"\nenabled: maybe\n")
+        .expect_err("invalid value accepted");
+    assert!(err.to_string().contains("maybe"), "{}", err);
+
+    let builder = SentryAppenderBuilder::try_from(config("dsn: \"\"\nenabled: false\n"))
+        .unwrap()
+        .encoder(Box::new(GateEncoder::opened()));
+    let (hub, transport) = test_hub();
+    let appender = builder.hub(hub).build();
+    append(&appender, Level::Error, "panic");
+    assert!(transport.fetch_and_clear_events().is_empty());
+}
+
+#[test]
+fn repeat_policy_samples_after_the_first_occurrences() {
+    let clock = Arc::new(clock::MockClock::new());
+    let repeats = |then_sample: f32| {
+        let (hub, transport) = test_hub();
+        let appender = SentryAppender::builder()
+            .repeat_policy(
+                3,
+                SampleRate::try_from(then_sample).unwrap(),
+                Duration::from_secs(600),
+            )
+            .clock(clock.clone())
+            .hub(hub)
+            .build();
+        for _ in 0..5 {
+            append(&appender, Level::Error, "retry failed");
+        }
+        append(&appender, Level::Error, "other");
+        (appender, transport.fetch_and_clear_events())
+    };
+
+    let (appender, events) = repeats(0.0);
+    assert_eq!(
+        messages(&events),
+        ["retry failed", "retry failed", "retry failed", "other"]
+    );
+    assert!(events
+        .iter()
+        .all(|event| !event.extra.contains_key("occurrence_count")));
+    assert_eq!(appender.stats().events_dropped_sampled, 2);
+
+    let (_, events) = repeats(1.0);
+    let counts: Vec<Option<&Value>> = events
+        .iter()
+        .map(|event| event.extra.get("occurrence_count"))
+        .collect();
+    assert_eq!(
+        counts,
+        [None, None, None, Some(&4.into()), Some(&5.into()), None]
+    );
+}
+
+#[test]
+fn shutdown_reports_whether_it_finished_in_time() {
+    let gate = Arc::new(Gate::default());
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .encoder(Box::new(GateEncoder(gate.clone())))
+        .async_queue(4)
+        .hub(hub)
+        .build();
+    occupy_worker(&appender, "1");
+    append(&appender, Level::Error, "2");
+    assert!(!appender.shutdown(Duration::from_millis(50)));
+
+    gate.open();
+    assert!(appender.shutdown(Duration::from_secs(5)));
+    assert_eq!(messages(&transport.fetch_and_clear_events()), ["1", "2"]);
+}
+
+#[test]
+fn level_tag_key_names_the_level_tag() {
+    let events = capture(
+        SentryAppender::builder().level_tag_key("severity"),
+        |appender| append(appender, Level::Error, "boom"),
+    );
+    assert_eq!(
+        events[0].tags.get("severity").map(String::as_str),
+        Some("error")
+    );
+    assert!(!events[0].tags.contains_key("log_level"));
+}
+
+#[test]
+fn records_below_the_threshold_become_breadcrumbs() {
+    let events = capture(
+        SentryAppender::builder().breadcrumb_threshold(LevelFilter::Info),
+        |appender| {
+            append(appender, Level::Debug, "too quiet");
+            append(appender, Level::Info, "loading");
+            append(appender, Level::Warn, "slow");
+            append(appender, Level::Error, "boom");
+        },
+    );
+    assert_eq!(messages(&events), ["boom"]);
+    let breadcrumbs: Vec<_> = events[0]
+        .breadcrumbs
+        .iter()
+        .map(|breadcrumb| (breadcrumb.message.as_deref(), breadcrumb.level))
+        .collect();
+    assert_eq!(
+        breadcrumbs,
+        [
+            (Some("loading"), sentry::Level::Info),
+            (Some("slow"), sentry::Level::Warning),
+        ]
+    );
+}
+
+/// An encoder writing the message and then failing.
+#[derive(Debug)]
+struct FailingEncoder;
+
+impl Encode for FailingEncoder {
+    fn encode(&self, w: &mut dyn log4rs::encode::Write, record: &Record) -> anyhow::Result<()> {
+        write!(w, "{}", record.args())?;
+        Err(anyhow::anyhow!("encoder failed"))
+    }
+}
+
+#[test]
+fn partial_encoder_output_is_captured() {
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .encoder(Box::new(FailingEncoder))
+        .hub(hub)
+        .build();
+    append(&appender, Level::Error, "half written");
+    assert!(appender
+        .append(
+            &Record::builder()
+                .level(Level::Error)
+                .args(format_args!(""))
+                .build()
+        )
+        .is_err());
+    appender.flush();
+
+    let events = transport.fetch_and_clear_events();
+    assert_eq!(messages(&events), ["half written"]);
+    assert_eq!(
+        events[0].extra.get("encode_incomplete"),
+        Some(&Value::Bool(true))
+    );
+}
+
+#[test]
+fn breadcrumbs_take_their_encoder_category_and_type() {
+    let events = capture(
+        SentryAppender::builder()
+            .encoder(Box::new(PatternEncoder::new("{d} {l} {m}")))
+            .breadcrumb_threshold(LevelFilter::Info)
+            .breadcrumb_encoder(Box::new(PatternEncoder::new("{m}")))
+            .breadcrumb_category("{module_path}/{level}")
+            .breadcrumb_type_for("app::db", "query"),
+        |appender| {
+            append_from(appender, Level::Info, "app::db::pool", "select 1");
+            append_from(appender, Level::Warn, "app::web", "slow request");
+            append(appender, Level::Error, "boom");
+        },
+    );
+    let breadcrumbs: Vec<_> = events[0]
+        .breadcrumbs
+        .iter()
+        .map(|breadcrumb| {
+            (
+                breadcrumb.category.as_deref(),
+                breadcrumb.ty.as_str(),
+                breadcrumb.message.as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        breadcrumbs,
+        [
+            (Some("app::db::pool/info"), "query", Some("select 1")),
+            (Some("app::web/warn"), "default", Some("slow request")),
+        ]
+    );
+}
+
+#[test]
+fn breadcrumb_category_defaults_to_the_target() {
+    let events = capture(
+        SentryAppender::builder()
+            .breadcrumb_threshold(LevelFilter::Info)
+            .breadcrumb_type("http"),
+        |appender| {
+            append_from(appender, Level::Info, "app::client", "GET /");
+            append(appender, Level::Error, "boom");
+        },
+    );
+    let breadcrumb = &events[0].breadcrumbs[0];
+    assert_eq!(breadcrumb.category.as_deref(), Some("app::client"));
+    assert_eq!(breadcrumb.ty, "http");
+}
+
+#[test]
+fn messages_containing_a_denied_phrase_are_dropped() {
+    let log = |appender: &SentryAppender| {
+        append(appender, Level::Error, "Connection reset by peer");
+        append(appender, Level::Error, "CONNECTION RESET by peer");
+        append(appender, Level::Error, "disk full");
+    };
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder()
+        .drop_if_contains("Connection reset")
+        .hub(hub)
+        .build();
+    log(&appender);
+    appender.flush();
+    assert_eq!(
+        messages(&transport.fetch_and_clear_events()),
+        ["CONNECTION RESET by peer", "disk full"]
+    );
+    assert_eq!(appender.stats().events_dropped_message, 1);
+
+    let builder = SentryAppenderBuilder::from_config(
+        config(
+            "dsn: \"\"\ndrop_if_contains: [\"connection reset\"]\ndrop_if_contains_ignore_case: true\n",
+        ),
+        &Deserializers::default(),
+    )
+    .unwrap();
+    assert_eq!(messages(&capture(builder, log)), ["disk full"]);
+}
+
+#[test]
+fn record_processor_sees_the_record() {
+    let events = capture(
+        SentryAppender::builder().record_processor(Box::new(|record, event| {
+            let component = record.target().split("::").nth(1).unwrap_or_default();
+            event
+                .tags
+                .insert("component".to_owned(), component.to_owned());
+        })),
+        |appender| append_from(appender, Level::Error, "app::billing::invoice", "boom"),
+    );
+    assert_eq!(
+        events[0].tags.get("component").map(String::as_str),
+        Some("billing")
+    );
+}
+
+#[test]
+fn breadcrumbs_carry_the_record_key_values() {
+    let with_kv = |include_kv| {
+        capture(
+            SentryAppender::builder()
+                .breadcrumb_threshold(LevelFilter::Info)
+                .include_kv(include_kv),
+            |appender| {
+                append_kv(
+                    appender,
+                    Level::Info,
+                    "request done",
+                    &[
+                        ("status", &200),
+                        ("path", &"/orders"),
+                        ("sentry.tag.region", &"eu"),
+                    ],
+                );
+                append(appender, Level::Error, "boom");
+            },
+        )
+    };
+
+    let events = with_kv(true);
+    let data = &events[0].breadcrumbs[0].data;
+    assert_eq!(data.get("status"), Some(&200.into()));
+    assert_eq!(data.get("path"), Some(&"/orders".into()));
+    assert!(!data.contains_key("sentry.tag.region"));
+
+    assert!(with_kv(false)[0].breadcrumbs[0].data.is_empty());
+}
+
+#[test]
+fn recent_logs_are_attached_to_error_events() {
+    let events = capture(
+        SentryAppender::builder()
+            .threshold(LevelFilter::Warn)
+            .breadcrumb_threshold(LevelFilter::Info)
+            .exclude_targets(vec!["hyper".to_owned()])
+            .recent_logs(3, AttachAs::Extra, LevelFilter::Error)
+            .encoder(Box::new(PatternEncoder::new("{l} {m}"))),
+        |appender| {
+            append(appender, Level::Info, "connected");
+            // Records dropped by the thresholds or filters aren't kept.
+            append(appender, Level::Debug, "reading");
+            append_from(appender, Level::Warn, "hyper::client", "retrying");
+            append(appender, Level::Warn, "slow");
+            append(appender, Level::Error, "boom");
+        },
+    );
+    assert_eq!(messages(&events), ["WARN slow", "ERROR boom"]);
+    assert!(!events[0].extra.contains_key("recent_logs"));
+    assert_eq!(
+        events[1].extra.get("recent_logs"),
+        Some(&Value::from(vec![
+            "INFO connected",
+            "WARN slow",
+            "ERROR boom"
+        ]))
+    );
+}
+
+#[test]
+fn one_config_captures_events_and_records_breadcrumbs() {
+    let builder = SentryAppenderBuilder::from_config(
+        config("dsn: \"\"\nthreshold: error\nbreadcrumb_threshold: debug\n"),
+        &Deserializers::default(),
+    )
+    .unwrap();
+    let events = capture(builder, |appender| {
+        append(appender, Level::Debug, "cache miss");
+        append(appender, Level::Error, "boom");
+    });
+    assert_eq!(messages(&events), ["boom"]);
+    assert_eq!(
+        events[0].breadcrumbs[0].message.as_deref(),
+        Some("cache miss")
+    );
+}
+
+#[test]
+fn breadcrumb_levels_are_mapped_above_the_minimum() {
+    let builder = SentryAppenderBuilder::from_config(
+        config(
+            "dsn: \"\"\nbreadcrumb_threshold: debug\nbreadcrumb_level_map:\n  warn: fatal\n  debug: info\n",
+        ),
+        &Deserializers::default(),
+    )
+    .unwrap();
+    let events = capture(builder, |appender| {
+        append(appender, Level::Trace, "spam");
+        append(appender, Level::Debug, "debug");
+        append(appender, Level::Info, "info");
+        append(appender, Level::Warn, "warn");
+        append(appender, Level::Error, "boom");
+    });
+    let breadcrumbs: Vec<_> = events[0]
+        .breadcrumbs
+        .iter()
+        .map(|breadcrumb| (breadcrumb.message.as_deref(), breadcrumb.level))
+        .collect();
+    assert_eq!(
+        breadcrumbs,
+        [
+            (Some("debug"), SentryLevel::Info),
+            (Some("info"), SentryLevel::Info),
+            (Some("warn"), SentryLevel::Fatal),
+        ]
+    );
+
+    assert!(serde_yaml::from_str::<SentryAppenderConfig>(
+        "dsn: \"\"\nbreadcrumb_level_map:\n  warn: loud\n"
+    )
+    .is_err());
+}
+
+#[test]
+fn encode_buffer_is_reused() {
+    let capacity = || ENCODE_BUFFER.with(|buf| buf.borrow().capacity());
+    let (hub, transport) = test_hub();
+    let appender = SentryAppender::builder().hub(hub).build();
+    append(&appender, Level::Error, "request 1000 failed");
+    let warm = capacity();
+    for i in 0..100 {
+        append(&appender, Level::Error, &format!("request {} failed", i));
+    }
+    assert_eq!(capacity(), warm);
+
+    append(
+        &appender,
+        Level::Error,
+        &"x".repeat(4 * ENCODE_BUFFER_CAPACITY),
+    );
+    assert!(capacity() <= ENCODE_BUFFER_CAPACITY);
+    appender.flush();
+    assert_eq!(transport.fetch_and_clear_events().len(), 102);
+}
+
+#[test]
+fn encode_buffers_are_per_thread() {
+    let (hub, transport) = test_hub();
+    let appender = Arc::new(SentryAppender::builder().hub(hub).build());
+    let threads: Vec<_> = (0..4)
+        .map(|thread| {
+            let appender = appender.clone();
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    append(
+                        &appender,
+                        Level::Error,
+                        &format!("thread {} line {}", thread, i),
+                    );
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    appender.flush();
+
+    let events = transport.fetch_and_clear_events();
+    let mut received = messages(&events);
+    received.sort_unstable();
+    let mut expected: Vec<String> = (0..4)
+        .flat_map(|thread| (0..50).map(move |i| format!("thread {} line {}", thread, i)))
+        .collect();
+    expected.sort_unstable();
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn max_value_length_truncates_extra_strings() {
+    let events = capture(
+        SentryAppender::builder()
+            .include_kv(true)
+            .max_value_length(8)
+            .normalize_depth(1)
+            .extra("build", "0123456789abcdef"),
+        |appender| {
+            append_kv(
+                appender,
+                Level::Error,
+                "boom",
+                &[("query", &"select * from orders")],
+            )
+        },
+    );
+    let extra = &events[0].extra;
+    assert_eq!(extra["build"], "01234567… [truncated 8 bytes]");
+    assert_eq!(extra["log_record"]["kv"], "[Object]");
+    assert_eq!(
+        events[0].tags.get("extras_truncated").map(String::as_str),
+        Some("true")
+    );
+
+    let events = capture(
+        SentryAppender::builder().extra("build", "0123456789abcdef"),
+        |appender| append(appender, Level::Error, "boom"),
+    );
+    assert_eq!(events[0].extra["build"], "0123456789abcdef");
+}
+
+#[test]
+fn events_are_sent_in_batches_between_flushes() {
+    let (hub, transport) = buffered_hub();
+    let appender = SentryAppender::builder()
+        .flush_interval(Duration::from_secs(3600))
+        .hub(hub)
+        .build();
+    for msg in ["1", "2", "3"] {
+        append(&appender, Level::Error, msg);
+    }
+    assert!(transport.batch_sizes().is_empty());
+    appender.flush();
+    append(&appender, Level::Error, "4");
+    append(&appender, Level::Error, "5");
+
+    let dropped = Instant::now();
+    drop(appender);
+    assert!(dropped.elapsed() < Duration::from_secs(5));
+    assert_eq!(transport.batch_sizes(), [3, 2]);
+}
+
+#[test]
+fn dist_is_set_on_events() {
+    let builder = SentryAppenderBuilder::from_config(
+        config("dsn: \"\"\nrelease: app@1.0.0\ndist: x86_64\n"),
+        &Deserializers::default(),
+    )
+    .unwrap();
+    let events = capture(builder, |appender| append(appender, Level::Error, "boom"));
+    assert_eq!(events[0].dist.as_deref(), Some("x86_64"));
+
+    let events = capture(SentryAppender::builder(), |appender| {
+        append(appender, Level::Error, "boom")
+    });
+    assert_eq!(events[0].dist, None);
+}
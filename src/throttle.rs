@@ -0,0 +1,137 @@
+//! Limits on how often events sharing a key, such as a fingerprint, are sent.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::clock::Clock;
+
+struct Entry {
+    window_start: Instant,
+    last_seen: Instant,
+    sent: u32,
+    // Events suppressed since the last one sent.
+    suppressed: u64,
+    // Events seen in the current window.
+    seen: u64,
+}
+
+/// Lets through up to `limit` events per key in each `window`, remembering at most `capacity`
+/// keys. When it's full, the least recently seen key is forgotten.
+pub(crate) struct Throttle {
+    limit: u32,
+    window: Duration,
+    capacity: usize,
+    clock: Arc<dyn Clock>,
+    entries: Mutex<HashMap<u64, Entry>>,
+}
+
+impl std::fmt::Debug for Throttle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Throttle")
+            .field("limit", &self.limit)
+            .field("window", &self.window)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl Throttle {
+    pub(crate) fn new(
+        limit: u32,
+        window: Duration,
+        capacity: usize,
+        clock: Arc<dyn Clock>,
+    ) -> Throttle {
+        Throttle {
+            limit: limit.max(1),
+            window,
+            capacity: capacity.max(1),
+            clock,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<u64, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Counts an event with `key` and passes its entry to `f`, starting a new window if the
+    /// last one is over.
+    fn with_entry<K: Hash + ?Sized, T>(&self, key: &K, f: impl FnOnce(&mut Entry) -> T) -> T {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let key = hasher.finish();
+
+        let now = self.clock.now();
+        let mut entries = self.lock();
+        if !entries.contains_key(&key) && entries.len() >= self.capacity {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_seen)
+                .map(|(&key, _)| key);
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        let entry = entries.entry(key).or_insert(Entry {
+            window_start: now,
+            last_seen: now,
+            sent: 0,
+            suppressed: 0,
+            seen: 0,
+        });
+        entry.last_seen = now;
+        if now.duration_since(entry.window_start) >= self.window {
+            entry.window_start = now;
+            entry.sent = 0;
+            entry.seen = 0;
+        }
+        entry.seen += 1;
+        f(entry)
+    }
+
+    /// Returns how many events with `key`, including this one, were seen in the current window.
+    pub(crate) fn count<K: Hash + ?Sized>(&self, key: &K) -> u64 {
+        self.with_entry(key, |entry| entry.seen)
+    }
+
+    /// Counts an event with `key`. Returns the number of events with that key suppressed since
+    /// the last one let through, or `None` if this one has to be suppressed too.
+    pub(crate) fn check<K: Hash + ?Sized>(&self, key: &K) -> Option<u64> {
+        self.with_entry(key, |entry| {
+            if entry.sent >= self.limit {
+                entry.suppressed += 1;
+                return None;
+            }
+            entry.sent += 1;
+            Some(std::mem::take(&mut entry.suppressed))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn window_expires() {
+        let clock = Arc::new(MockClock::new());
+        let throttle = Throttle::new(2, Duration::from_secs(60), 16, clock.clone());
+        assert_eq!(throttle.check("key"), Some(0));
+        assert_eq!(throttle.check("key"), Some(0));
+        assert_eq!(throttle.check("key"), None);
+
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(throttle.check("key"), None);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(throttle.check("key"), Some(2));
+        assert_eq!(throttle.count("key"), 2);
+    }
+}
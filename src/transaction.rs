@@ -0,0 +1,66 @@
+//! Performance transactions wrapping a unit of work.
+
+use sentry::{Hub, TransactionContext, TransactionOrSpan};
+
+use crate::SentryAppender;
+
+/// A transaction started by `SentryAppender::start_transaction`, finished when the guard is
+/// dropped.
+///
+/// While the guard is alive, events captured on the current thread are associated with the
+/// transaction.
+#[must_use = "the transaction is finished as soon as the guard is dropped"]
+pub struct TransactionGuard {
+    transaction: Option<sentry::Transaction>,
+    previous_span: Option<TransactionOrSpan>,
+}
+
+impl std::fmt::Debug for TransactionGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransactionGuard").finish_non_exhaustive()
+    }
+}
+
+impl TransactionGuard {
+    pub(crate) fn start(name: &str, op: &str) -> TransactionGuard {
+        let hub = Hub::current();
+        let transaction = hub.start_transaction(TransactionContext::new(name, op));
+        let previous_span = hub.configure_scope(|scope| {
+            let previous_span = scope.get_span();
+            scope.set_span(Some(transaction.clone().into()));
+            previous_span
+        });
+        SentryAppender::set_transaction(Some(name.to_owned()));
+        TransactionGuard {
+            transaction: Some(transaction),
+            previous_span,
+        }
+    }
+
+    /// Sets the transaction's status, e.g. to mark it as failed.
+    pub fn set_status(&self, status: sentry::protocol::SpanStatus) {
+        if let Some(ref transaction) = self.transaction {
+            transaction.set_status(status);
+        }
+    }
+
+    /// Finishes the transaction now rather than when the guard is dropped.
+    pub fn finish(mut self) {
+        self.finish_transaction();
+    }
+
+    fn finish_transaction(&mut self) {
+        if let Some(transaction) = self.transaction.take() {
+            transaction.finish();
+            let previous_span = self.previous_span.take();
+            Hub::current().configure_scope(|scope| scope.set_span(previous_span));
+            SentryAppender::set_transaction(None);
+        }
+    }
+}
+
+impl Drop for TransactionGuard {
+    fn drop(&mut self) {
+        self.finish_transaction();
+    }
+}
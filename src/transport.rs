@@ -0,0 +1,620 @@
+//! A transport which sends envelopes again when Sentry fails to take them, and spools them
+//! to disk while it can't be reached.
+
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+use reqwest::{
+    header::{CONTENT_ENCODING, RETRY_AFTER},
+    StatusCode,
+};
+use sentry::{protocol::EnvelopeItem, ClientOptions, Envelope, Transport};
+
+use crate::{gzip, spool::Spool, stats};
+
+/// How many times, and how far apart, an envelope is sent again after a transient failure.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub(crate) struct Backoff {
+    pub(crate) max_retries: u32,
+    pub(crate) initial: Duration,
+    pub(crate) max: Duration,
+}
+
+impl Backoff {
+    /// How long to wait before retry `retry`, counting from 0: `retry_after` if the server
+    /// asked for it, or `initial` doubled for each earlier retry, up to `max`. `None` once the
+    /// retries are used up, or if the server asked to wait longer than `max`.
+    fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if retry >= self.max_retries {
+            return None;
+        }
+        match retry_after {
+            Some(delay) if delay > self.max => None,
+            Some(delay) => Some(delay),
+            None => Some(
+                self.initial
+                    .saturating_mul(1u32.checked_shl(retry).unwrap_or(u32::MAX))
+                    .min(self.max),
+            ),
+        }
+    }
+}
+
+/// What came of sending an envelope once.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Outcome {
+    Sent,
+    /// Worth trying again, after the delay the server asked for if it did.
+    Transient(Option<Duration>),
+    /// Sentry is rate limiting the project; see `RateLimits` for how long.
+    RateLimited,
+    Rejected,
+}
+
+impl Outcome {
+    fn from_response(status: StatusCode, retry_after: Option<&str>) -> Outcome {
+        if status.is_success() {
+            Outcome::Sent
+        } else if status == StatusCode::TOO_MANY_REQUESTS {
+            Outcome::RateLimited
+        } else if status.is_server_error() {
+            Outcome::Transient(retry_after.and_then(parse_retry_after))
+        } else {
+            Outcome::Rejected
+        }
+    }
+}
+
+/// How long a `429` without rate limit headers stops sending.
+const DEFAULT_RATE_LIMIT: Duration = Duration::from_secs(60);
+
+/// Until when Sentry doesn't take envelopes, as a whole or items of some data categories, as
+/// told by its `X-Sentry-Rate-Limits` and `Retry-After` headers.
+#[derive(Debug, Default)]
+struct RateLimits {
+    all: Option<Instant>,
+    categories: HashMap<String, Instant>,
+}
+
+impl RateLimits {
+    /// Takes the limits of a response: those of an `X-Sentry-Rate-Limits` header, or else,
+    /// for a `429`, `Retry-After` or `DEFAULT_RATE_LIMIT` for everything.
+    fn update(&mut self, status: StatusCode, rate_limits: Option<&str>, retry_after: Option<&str>) {
+        let now = Instant::now();
+        if let Some(header) = rate_limits {
+            // `<seconds>:<category>;<category>...:<scope>[:<reason>]`, comma separated. No
+            // categories means all of them.
+            for limit in header.split(',') {
+                let mut parts = limit.trim().split(':');
+                let seconds = match parts.next().and_then(parse_retry_after) {
+                    Some(seconds) => seconds,
+                    None => continue,
+                };
+                let until = now + seconds;
+                match parts.next().unwrap_or_default() {
+                    "" => self.all = Some(until),
+                    categories => {
+                        for category in categories.split(';') {
+                            self.categories.insert(category.to_owned(), until);
+                        }
+                    }
+                }
+            }
+        } else if status == StatusCode::TOO_MANY_REQUESTS {
+            let delay = retry_after
+                .and_then(parse_retry_after)
+                .unwrap_or(DEFAULT_RATE_LIMIT);
+            self.all = Some(now + delay);
+        }
+    }
+
+    /// Whether items of `category`, or with `None` any items, can't be sent right now.
+    fn limited(&self, category: Option<&str>) -> bool {
+        let now = Instant::now();
+        self.all.is_some_and(|until| until > now)
+            || category
+                .and_then(|category| self.categories.get(category))
+                .is_some_and(|&until| until > now)
+    }
+
+    /// Removes the items which can't be sent right now, returning `None` if that's all of them.
+    fn filter(&self, envelope: Envelope) -> Option<Envelope> {
+        envelope.filter(|item| {
+            let category = match item {
+                EnvelopeItem::Event(_) => "error",
+                EnvelopeItem::Transaction(_) => "transaction",
+                EnvelopeItem::SessionUpdate(_) | EnvelopeItem::SessionAggregates(_) => "session",
+                EnvelopeItem::Attachment(_) => "attachment",
+                EnvelopeItem::Profile(_) => "profile",
+                _ => return !self.limited(None),
+            };
+            !self.limited(Some(category))
+        })
+    }
+}
+
+/// Parses a `Retry-After` header, either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    match value.trim().parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => {
+            Some(Duration::from_secs(seconds.ceil() as u64))
+        }
+        Ok(_) => None,
+        Err(_) => httpdate::parse_http_date(value.trim())
+            .ok()
+            .map(|at| at.duration_since(SystemTime::now()).unwrap_or_default()),
+    }
+}
+
+enum Task {
+    Send(Envelope),
+    Flush(SyncSender<()>),
+    Shutdown,
+}
+
+/// How often spooled envelopes are sent again while Sentry can't be reached.
+const REPLAY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Sends envelopes to one DSN, retrying and spooling them.
+struct Sender {
+    client: reqwest::Client,
+    url: String,
+    auth: String,
+    backoff: Backoff,
+    spool: Option<Spool>,
+    compress: bool,
+    stats: Arc<stats::Counters>,
+    stopping: Arc<AtomicBool>,
+    rate_limits: Mutex<RateLimits>,
+}
+
+impl Sender {
+    fn rate_limits(&self) -> MutexGuard<'_, RateLimits> {
+        self.rate_limits.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sends `body` once, gzipped if `compress` is set, taking note of the rate limits in the
+    /// response.
+    async fn post(&self, body: &[u8]) -> Outcome {
+        let request = self
+            .client
+            .post(&self.url)
+            .header("X-Sentry-Auth", &self.auth);
+        let request = if self.compress {
+            request
+                .header(CONTENT_ENCODING, "gzip")
+                .body(gzip::compress(body))
+        } else {
+            request.body(body.to_vec())
+        };
+        match request.send().await {
+            Ok(response) => {
+                let header = |name: &str| {
+                    response
+                        .headers()
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                };
+                let (rate_limits, retry_after) =
+                    (header("X-Sentry-Rate-Limits"), header(RETRY_AFTER.as_str()));
+                self.rate_limits()
+                    .update(response.status(), rate_limits, retry_after);
+                Outcome::from_response(response.status(), retry_after)
+            }
+            Err(_) => Outcome::Transient(None),
+        }
+    }
+
+    /// Sends `body`, retrying as `backoff` allows, then spools it if it still couldn't be
+    /// sent. Once it's sent, so are the spooled envelopes.
+    async fn send(&self, body: &[u8]) {
+        let mut retry = 0;
+        loop {
+            match self.post(body).await {
+                Outcome::Sent => return self.replay().await,
+                Outcome::RateLimited => return self.stats.dropped_ratelimit(),
+                Outcome::Rejected => return self.stats.send_error(),
+                Outcome::Transient(retry_after) => match self.backoff.delay(retry, retry_after) {
+                    Some(delay) if sleep(delay, &self.stopping).await => retry += 1,
+                    _ => return self.give_up(body),
+                },
+            }
+        }
+    }
+
+    /// Spools `body` if there's a spool, counting the envelopes pruned to make room for it as
+    /// send errors; otherwise counts `body` as one.
+    fn give_up(&self, body: &[u8]) {
+        let spool = match self.spool {
+            Some(ref spool) => spool,
+            None => return self.stats.send_error(),
+        };
+        match spool.write(body) {
+            Ok(pruned) => {
+                for _ in 0..pruned {
+                    self.stats.send_error();
+                }
+            }
+            Err(err) => {
+                eprintln!("log4rs: failed to spool a sentry event: {}", err);
+                self.stats.send_error();
+            }
+        }
+    }
+
+    /// Sends the spooled envelopes once each, oldest first, stopping at the first one which
+    /// fails transiently or is rate limited. The others are removed from the spool.
+    async fn replay(&self) {
+        let spool = match self.spool {
+            Some(ref spool) => spool,
+            None => return,
+        };
+        for path in spool.files() {
+            if self.stopping.load(Ordering::SeqCst) || self.rate_limits().limited(Some("error")) {
+                return;
+            }
+            let body = match fs::read(&path) {
+                Ok(body) => body,
+                Err(_) => continue,
+            };
+            match self.post(&body).await {
+                Outcome::Sent => {}
+                Outcome::Transient(_) | Outcome::RateLimited => return,
+                Outcome::Rejected => self.stats.send_error(),
+            }
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+/// A transport like `ReqwestHttpTransport` which retries envelopes failing with a server
+/// error or a network error, as `Backoff` allows. Envelopes it gives up on are written to the
+/// spool if there is one, to be sent again at startup, once an envelope gets through, and
+/// every `REPLAY_INTERVAL`; otherwise they're counted as send errors, like those rejected
+/// outright.
+///
+/// Like `ReqwestHttpTransport`, it drops the envelopes, or items of them, Sentry is rate
+/// limiting, rather than sending them again.
+///
+/// Envelopes are sent one at a time on a thread of its own, so later ones wait for the
+/// retries of earlier ones.
+pub(crate) struct RetryTransport {
+    sender: SyncSender<Task>,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RetryTransport {
+    pub(crate) fn new(
+        options: &ClientOptions,
+        client: reqwest::Client,
+        backoff: Backoff,
+        spool: Option<Spool>,
+        compress: bool,
+        stats: Arc<stats::Counters>,
+    ) -> RetryTransport {
+        let dsn = options.dsn.as_ref().expect("the transport needs a DSN");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let sender = Sender {
+            client,
+            url: dsn.envelope_api_url().to_string(),
+            auth: dsn.to_auth(Some(&options.user_agent)).to_string(),
+            backoff,
+            spool,
+            compress,
+            stats,
+            stopping: shutdown.clone(),
+            rate_limits: Mutex::default(),
+        };
+
+        let (tasks, receiver) = sync_channel(30);
+        let handle = thread::Builder::new()
+            .name("sentry-log4rs-transport".into())
+            .spawn(move || {
+                let runtime = match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(runtime) => runtime,
+                    Err(err) => {
+                        eprintln!("log4rs: failed to start the sentry transport: {}", err);
+                        return;
+                    }
+                };
+                runtime.block_on(async move {
+                    sender.replay().await;
+                    loop {
+                        let envelope = match receiver.recv_timeout(REPLAY_INTERVAL) {
+                            Ok(Task::Send(envelope)) => envelope,
+                            Ok(Task::Flush(done)) => {
+                                done.send(()).ok();
+                                continue;
+                            }
+                            Ok(Task::Shutdown) | Err(RecvTimeoutError::Disconnected) => return,
+                            Err(RecvTimeoutError::Timeout) => {
+                                sender.replay().await;
+                                continue;
+                            }
+                        };
+                        let envelope = match sender.rate_limits().filter(envelope) {
+                            Some(envelope) => envelope,
+                            None => {
+                                sender.stats.dropped_ratelimit();
+                                continue;
+                            }
+                        };
+                        let mut body = Vec::new();
+                        if envelope.to_writer(&mut body).is_err() {
+                            sender.stats.send_error();
+                            continue;
+                        }
+                        sender.send(&body).await;
+                    }
+                })
+            })
+            .ok();
+
+        RetryTransport {
+            sender: tasks,
+            shutdown,
+            handle,
+        }
+    }
+}
+
+/// Waits for `delay`, or until `stopping` is set. Returns whether the whole delay passed.
+async fn sleep(delay: Duration, stopping: &AtomicBool) -> bool {
+    let deadline = Instant::now() + delay;
+    loop {
+        if stopping.load(Ordering::SeqCst) {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        tokio::time::sleep((deadline - now).min(Duration::from_millis(100))).await;
+    }
+}
+
+impl Transport for RetryTransport {
+    fn send_envelope(&self, envelope: Envelope) {
+        // Like sentry's transports, drop the envelope rather than block the caller.
+        let _ = self.sender.try_send(Task::Send(envelope));
+    }
+
+    fn flush(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let (done, flushed) = sync_channel(1);
+        let mut task = Task::Flush(done);
+        // The queue may be full while earlier envelopes are retried; waiting for room counts
+        // against the timeout too.
+        loop {
+            match self.sender.try_send(task) {
+                Ok(()) => break,
+                Err(TrySendError::Full(returned)) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return false;
+                    }
+                    task = returned;
+                    thread::sleep(remaining.min(Duration::from_millis(10)));
+                }
+                Err(TrySendError::Disconnected(_)) => return false,
+            }
+        }
+        flushed
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .is_ok()
+    }
+
+    fn shutdown(&self, timeout: Duration) -> bool {
+        self.flush(timeout)
+    }
+}
+
+impl Drop for RetryTransport {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        let _ = self.sender.send(Task::Shutdown);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    use sentry::protocol::{Event, Transaction};
+
+    use super::*;
+
+    /// A request received by `serve`: its header lines, lowercased, and its body.
+    pub(crate) type Request = (Vec<String>, Vec<u8>);
+
+    /// Answers the `n`th request with `responses[n]`, a status and headers, or the last one,
+    /// and keeps the requests.
+    pub(crate) fn serve(responses: &'static [&'static str]) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dsn = format!("http://public@{}/1", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = BufReader::new(stream.unwrap());
+                let mut headers = Vec::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).unwrap();
+                    let line = line.trim_end().to_ascii_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    headers.push(line);
+                }
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).unwrap();
+                let n = {
+                    let mut received = received.lock().unwrap();
+                    received.push((headers, body));
+                    received.len() - 1
+                };
+                let response = responses[n.min(responses.len() - 1)];
+                write!(
+                    stream.get_mut(),
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    response
+                )
+                .unwrap();
+            }
+        });
+        (dsn, requests)
+    }
+
+    fn transport(dsn: &str, compress: bool, stats: &Arc<stats::Counters>) -> RetryTransport {
+        spooling_transport(dsn, compress, None, stats)
+    }
+
+    fn spooling_transport(
+        dsn: &str,
+        compress: bool,
+        spool: Option<Spool>,
+        stats: &Arc<stats::Counters>,
+    ) -> RetryTransport {
+        let options = ClientOptions {
+            dsn: Some(dsn.parse().unwrap()),
+            ..Default::default()
+        };
+        let backoff = Backoff {
+            max_retries: 3,
+            initial: Duration::from_millis(10),
+            max: Duration::from_secs(1),
+        };
+        RetryTransport::new(
+            &options,
+            reqwest::Client::new(),
+            backoff,
+            spool,
+            compress,
+            stats.clone(),
+        )
+    }
+
+    #[test]
+    fn retries_until_sent() {
+        let (dsn, requests) = serve(&[
+            "500 Internal Server Error",
+            "503 Service Unavailable",
+            "200 OK",
+        ]);
+        let stats = Arc::new(stats::Counters::default());
+        let transport = transport(&dsn, false, &stats);
+
+        transport.send_envelope(Event::default().into());
+        assert!(transport.flush(Duration::from_secs(5)));
+        assert_eq!(requests.lock().unwrap().len(), 3);
+        assert_eq!(stats.snapshot().send_errors, 0);
+    }
+
+    #[test]
+    fn rate_limited_categories_are_not_sent() {
+        let (dsn, requests) = serve(&[
+            "429 Too Many Requests\r\nX-Sentry-Rate-Limits: 60:error:project",
+            "200 OK",
+        ]);
+        let stats = Arc::new(stats::Counters::default());
+        let transport = transport(&dsn, false, &stats);
+
+        transport.send_envelope(Event::default().into());
+        transport.send_envelope(Event::default().into());
+        assert!(transport.flush(Duration::from_secs(5)));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert_eq!(stats.snapshot().events_dropped_ratelimit, 2);
+
+        transport.send_envelope(Transaction::default().into());
+        assert!(transport.flush(Duration::from_secs(5)));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn compressed_payloads_are_gzipped() {
+        let (dsn, requests) = serve(&["200 OK"]);
+        let stats = Arc::new(stats::Counters::default());
+        let transport = transport(&dsn, true, &stats);
+
+        let envelope: Envelope = Event::default().into();
+        let mut expected = Vec::new();
+        envelope.to_writer(&mut expected).unwrap();
+        transport.send_envelope(envelope);
+        assert!(transport.flush(Duration::from_secs(5)));
+
+        let requests = requests.lock().unwrap();
+        let (headers, body) = &requests[0];
+        assert!(headers.contains(&"content-encoding: gzip".to_owned()));
+        assert_eq!(body[..2], [0x1f, 0x8b]);
+        let deflated = &body[10..body.len() - 8];
+        assert_eq!(
+            miniz_oxide::inflate::decompress_to_vec(deflated).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn retry_after_limits_everything() {
+        let mut limits = RateLimits::default();
+        limits.update(StatusCode::TOO_MANY_REQUESTS, None, Some("30"));
+        assert!(limits.limited(None));
+        assert!(limits.filter(Transaction::default().into()).is_none());
+
+        let mut limits = RateLimits::default();
+        limits.update(StatusCode::OK, Some("0:error:project"), None);
+        assert!(!limits.limited(Some("error")));
+    }
+
+    #[test]
+    fn spools_what_it_gives_up_on_and_replays_it() {
+        let (dsn, requests) = serve(&[
+            "500 Internal Server Error",
+            "500 Internal Server Error",
+            "500 Internal Server Error",
+            "500 Internal Server Error",
+            "200 OK",
+        ]);
+        let dir = std::env::temp_dir().join(format!("sentry-log4rs-spool-{}", std::process::id()));
+        let spool = || Spool::for_dsn(&dir, &dsn.parse().unwrap(), 1 << 20).unwrap();
+        let stats = Arc::new(stats::Counters::default());
+        let transport = spooling_transport(&dsn, false, Some(spool()), &stats);
+
+        transport.send_envelope(Event::default().into());
+        assert!(transport.flush(Duration::from_secs(5)));
+        assert_eq!(requests.lock().unwrap().len(), 4);
+        assert_eq!(spool().files().len(), 1);
+        assert_eq!(stats.snapshot().send_errors, 0);
+
+        transport.send_envelope(Event::default().into());
+        assert!(transport.flush(Duration::from_secs(5)));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 6);
+        assert_eq!(requests[5].1, requests[0].1);
+        assert!(spool().files().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
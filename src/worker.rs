@@ -0,0 +1,244 @@
+//! The background worker used by the sentry appender in async mode.
+
+use std::{
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::{Level, Record};
+
+use crate::{kv::OwnedKeyValues, Inner, Snapshot};
+
+/// What to do with a record when the background queue is full.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Hash, Debug, Default, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueFullPolicy {
+    /// Discard the oldest queued record to make room for the new one.
+    DropOldest,
+    /// Discard the new record.
+    #[default]
+    DropNew,
+    /// Block the logging thread until the worker makes room.
+    Block,
+}
+
+/// An owned copy of the parts of a `Record` the appender needs.
+struct Job {
+    level: Level,
+    target: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    args: String,
+    key_values: OwnedKeyValues,
+    snapshot: Option<Snapshot>,
+}
+
+impl Job {
+    fn new(record: &Record, snapshot: Snapshot) -> Job {
+        Job {
+            level: record.level(),
+            target: record.target().to_owned(),
+            module_path: record.module_path().map(str::to_owned),
+            file: record.file().map(str::to_owned),
+            line: record.line(),
+            args: record.args().to_string(),
+            key_values: OwnedKeyValues::new(record),
+            snapshot: Some(snapshot),
+        }
+    }
+
+    fn with_record<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Record) -> R,
+    {
+        f(&Record::builder()
+            .level(self.level)
+            .target(&self.target)
+            .module_path(self.module_path.as_deref())
+            .file(self.file.as_deref())
+            .line(self.line)
+            .args(format_args!("{}", self.args))
+            .key_values(&self.key_values)
+            .build())
+    }
+}
+
+struct State {
+    queue: VecDeque<Job>,
+    busy: bool,
+    shutdown: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // Signalled when a job is queued or the worker should shut down.
+    pending: Condvar,
+    // Signalled when a job is taken off the queue or finished.
+    progress: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A background thread encoding queued records and capturing them.
+pub(crate) struct Worker {
+    shared: Arc<Shared>,
+    capacity: usize,
+    policy: QueueFullPolicy,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for Worker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Worker")
+            .field("capacity", &self.capacity)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl Worker {
+    /// Starts the worker. When it's dropped, it finishes the record it's capturing and discards
+    /// the queued ones, so wait for them with `flush_timeout` first.
+    pub(crate) fn spawn(inner: Arc<Inner>, capacity: usize, policy: QueueFullPolicy) -> Worker {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::with_capacity(capacity),
+                busy: false,
+                shutdown: false,
+            }),
+            pending: Condvar::new(),
+            progress: Condvar::new(),
+        });
+
+        let handle = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("sentry-log4rs".to_owned())
+                .spawn(move || run(&shared, &inner))
+                .expect("failed to spawn the sentry-log4rs worker thread")
+        };
+
+        Worker {
+            shared,
+            capacity: capacity.max(1),
+            policy,
+            handle: Some(handle),
+        }
+    }
+
+    /// Queues a record, applying the queue-full policy if there is no room for it. Returns
+    /// whether a record, this one or a queued one, was dropped.
+    pub(crate) fn push(&self, record: &Record, snapshot: Snapshot) -> bool {
+        let job = Job::new(record, snapshot);
+        let mut state = self.shared.lock();
+        let mut dropped = false;
+        while state.queue.len() >= self.capacity {
+            match self.policy {
+                QueueFullPolicy::DropNew => return true,
+                QueueFullPolicy::DropOldest => {
+                    state.queue.pop_front();
+                    dropped = true;
+                }
+                QueueFullPolicy::Block => {
+                    state = self
+                        .shared
+                        .progress
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+            }
+        }
+        state.queue.push_back(job);
+        self.shared.pending.notify_one();
+        dropped
+    }
+
+    /// The number of records waiting to be captured.
+    pub(crate) fn depth(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+
+    /// Waits up to `timeout` for every queued record to be captured. Returns whether they were.
+    pub(crate) fn flush_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        while !state.queue.is_empty() || state.busy {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+            state = self
+                .shared
+                .progress
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        true
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        if !state.queue.is_empty() {
+            eprintln!(
+                "log4rs: discarded {} queued sentry events at shutdown",
+                state.queue.len()
+            );
+            state.queue.clear();
+        }
+        state.shutdown = true;
+        drop(state);
+        self.shared.pending.notify_one();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run(shared: &Shared, inner: &Inner) {
+    loop {
+        let mut job = {
+            let mut state = shared.lock();
+            loop {
+                if let Some(job) = state.queue.pop_front() {
+                    state.busy = true;
+                    break job;
+                }
+                if state.shutdown {
+                    return;
+                }
+                state = shared
+                    .pending
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner());
+            }
+        };
+        shared.progress.notify_all();
+
+        let snapshot = job.snapshot.take().unwrap_or_default();
+        // A panicking encoder or callback loses its record, not the worker: without it, `busy`
+        // would never be cleared and flushes and blocked appends would wait on a dead thread.
+        match panic::catch_unwind(AssertUnwindSafe(|| {
+            job.with_record(|record| inner.capture(record, snapshot))
+        })) {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => eprintln!("log4rs: {}", err),
+            Err(_) => eprintln!("log4rs: sentry appender panicked capturing a record"),
+        }
+
+        shared.lock().busy = false;
+        shared.progress.notify_all();
+    }
+}